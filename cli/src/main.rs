@@ -1,5 +1,8 @@
 use clap::{Parser, Subcommand};
-use mips_vm::{compiler::Compiler, parser::parse, vm::VM};
+use mips_vm::{
+    address::Address, compiler::Compiler, lint::lint, parser::parse_with_resolver,
+    program::Program, vm::VM,
+};
 
 mod mmio;
 
@@ -29,6 +32,13 @@ impl From<Target> for mips_vm::compiler::Target {
     }
 }
 
+#[derive(clap::ValueEnum, Clone, Debug)]
+#[clap(rename_all = "lower")]
+enum DumpFormat {
+    Text,
+    Json,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Compile the input file
@@ -46,8 +56,9 @@ enum Commands {
     /// Run the input file
     #[command(name = "run", alias = "r")]
     Run {
-        /// Input file to run
-        input: String,
+        /// Input files to run, linked together in the given order
+        #[arg(required = true)]
+        input: Vec<String>,
         /// Optional memory dump to file
         #[arg(short, long)]
         dump_file: Option<String>,
@@ -57,9 +68,86 @@ enum Commands {
         /// Shard size for compression
         #[arg(short, long, default_value = "128")]
         shard_size: usize,
+        /// Print all registers after execution
+        #[arg(long, default_value = "false")]
+        dump_registers: bool,
+        /// Format of the register dump
+        #[arg(long, value_enum, default_value = "text")]
+        dump_format: DumpFormat,
+        /// Print the execution count of every instruction after execution
+        #[arg(long, default_value = "false")]
+        coverage: bool,
+        /// Fail on accesses to .data that cross the bounds of a symbol
+        #[arg(long, default_value = "false")]
+        strict: bool,
+        /// Print every executed instruction and the registers it changed to stderr
+        #[arg(long, default_value = "false")]
+        trace: bool,
+        /// Print the number of memory reads and writes after execution
+        #[arg(long, default_value = "false")]
+        mem_stats: bool,
+        /// Warn about calls inside functions that have not saved $ra
+        #[arg(long, default_value = "false")]
+        check_ra: bool,
+        /// Fill uninitialized memory with 0xCC and warn about reads of it
+        #[arg(long, default_value = "false")]
+        poison: bool,
+        /// Warn about likely mistakes before running, such as unrestored callee-saved registers
+        #[arg(long, default_value = "false")]
+        lint: bool,
+        /// Argument passed to the program in argc/argv ($a0/$a1), may be repeated
+        #[arg(long = "arg")]
+        args: Vec<String>,
+        /// Label or hex address to start execution at, instead of the detected entrypoint
+        #[arg(long)]
+        entry: Option<String>,
+    },
+    /// Debug the input file with GDB over the remote serial protocol
+    #[cfg(feature = "gdbstub")]
+    #[command(name = "gdbserver")]
+    GdbServer {
+        /// Input file to debug
+        input: String,
+        /// TCP port to listen on for the debugger
+        #[arg(short, long, default_value = "1234")]
+        port: u16,
     },
 }
 
+/// Parse an input file, resolving `.include` directives relative to its directory.
+fn parse_file(input: &str) -> Option<Program> {
+    let input_content = std::fs::read_to_string(input).expect("Failed to read input file");
+    let base = std::path::Path::new(input)
+        .parent()
+        .unwrap_or(std::path::Path::new(""))
+        .to_path_buf();
+    parse_with_resolver(&input_content, &|path| {
+        std::fs::read_to_string(base.join(path))
+    })
+    .map_err(|mut e| match &mut e.file {
+        Some(file) => {
+            *file = base.join(&file).display().to_string();
+            eprintln!("{}", e)
+        }
+        None => eprintln!("{}: {}", input, e),
+    })
+    .ok()
+}
+
+/// Resolve an `--entry` argument, either a label or a hex address such as `0x00400010`.
+fn resolve_entry(vm: &VM, entry: &str) -> Result<Address, String> {
+    if let Some(hex) = entry.strip_prefix("0x") {
+        return u32::from_str_radix(hex, 16)
+            .map(Address::new)
+            .map_err(|_| format!("Invalid entry address: {}", entry));
+    }
+    vm.memory()
+        .labels()
+        .get(entry)
+        .copied()
+        .ok_or_else(|| format!("Entry label not found: {}", entry))
+}
+
 fn main() {
     log_init();
     let args = Cli::parse();
@@ -69,8 +157,7 @@ fn main() {
             output,
             target,
         } => {
-            let input_content = std::fs::read_to_string(&input).expect("Failed to read input file");
-            if let Some(program) = parse(&input_content) {
+            if let Some(program) = parse_file(&input) {
                 let output = if let Some(output) = output {
                     std::path::PathBuf::from(output)
                 } else {
@@ -88,6 +175,7 @@ fn main() {
                 );
             } else {
                 eprintln!("Failed to compile the input file.");
+                std::process::exit(1);
             }
         }
         Commands::Run {
@@ -95,19 +183,138 @@ fn main() {
             dump_file,
             non_compressed,
             shard_size,
+            dump_registers,
+            dump_format,
+            coverage,
+            strict,
+            trace,
+            mem_stats,
+            check_ra,
+            poison,
+            lint: check,
+            args,
+            entry,
         } => {
-            let input_content = std::fs::read_to_string(input).expect("Failed to read input file");
-            if let Some(program) = parse(&input_content) {
-                let mmio = Vec::new();
-                let mut vm = VM::new(program, mmio);
-                if let Some(dump_file) = dump_file {
-                    let dump = vm.memory().dump(!non_compressed, shard_size, false);
-                    let dump_path = std::path::PathBuf::from(dump_file);
-                    std::fs::write(&dump_path, dump).unwrap();
+            // The parse errors are already printed
+            let Some(programs) = input
+                .iter()
+                .map(|input| parse_file(input))
+                .collect::<Option<Vec<_>>>()
+            else {
+                std::process::exit(1);
+            };
+            let program = Program::link(programs).unwrap_or_else(|err| {
+                eprintln!("Link error: {}", err);
+                std::process::exit(1);
+            });
+            if check {
+                for warning in lint(&program) {
+                    eprintln!("Warning: {}", warning);
                 }
-                vm.execute(vm.entrypoint().expect("No entrypoint found"));
+            }
+            let mmio = Vec::new();
+            let mut vm = VM::try_new(program, mmio).unwrap_or_else(|err| {
+                eprintln!("Load error: {}", err);
+                std::process::exit(1);
+            });
+            vm.memory_mut().set_strict_bounds(strict);
+            vm.set_ra_check(check_ra);
+            if !args.is_empty() {
+                vm.set_args(args);
+            }
+            if poison {
+                vm.set_poison(Some(0xCC));
+            }
+            if trace {
+                vm.set_trace_hook(|step| {
+                    let changes = step
+                        .changes
+                        .iter()
+                        .map(|(register, old, new)| {
+                            format!("{}: 0x{:08X} → 0x{:08X}", register.show(), old, new)
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let line = format!(
+                        "{}  {:<24}  {}",
+                        step.pc.show(),
+                        step.instruction.show(),
+                        changes
+                    );
+                    eprintln!("{}", line.trim_end());
+                });
+            }
+            if let Some(dump_file) = dump_file {
+                let dump = vm.memory().dump(!non_compressed, shard_size, false);
+                let dump_path = std::path::PathBuf::from(dump_file);
+                std::fs::write(&dump_path, dump).unwrap();
+            }
+            let entrypoint = match entry {
+                Some(entry) => resolve_entry(&vm, &entry).unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }),
+                None => vm.entrypoint().expect("No entrypoint found"),
+            };
+            let result = vm.execute(entrypoint);
+            for call in vm.ra_warnings() {
+                eprintln!(
+                    "Warning: call at {} overwrites $ra before it was saved",
+                    vm.show_code_address(*call)
+                );
+            }
+            for address in vm.memory().uninitialized_reads() {
+                eprintln!("Warning: read of uninitialized memory at {}", address);
+            }
+            if dump_registers {
+                match dump_format {
+                    DumpFormat::Text => print!("{}", vm.registers().show_all()),
+                    DumpFormat::Json => print!("{}", vm.registers().show_json()),
+                }
+            }
+            if coverage {
+                print!("{}", vm.show_coverage());
+            }
+            if mem_stats {
+                print!("{}", vm.memory().stats().show());
+            }
+            if let Err(err) = result {
+                eprintln!("Runtime error: {}", err);
+                eprint!("{}", vm.show_backtrace());
+                std::process::exit(1);
+            }
+            let assertions = vm.check_assertions();
+            for assertion in &assertions {
+                eprintln!("{}", assertion.show());
+            }
+            if assertions.iter().any(|assertion| !assertion.passed()) {
+                std::process::exit(1);
+            }
+            if let Some(code) = vm.exit_code() {
+                std::process::exit(code);
             }
         }
+        #[cfg(feature = "gdbstub")]
+        Commands::GdbServer { input, port } => gdbserver(&input, port),
+    }
+}
+
+#[cfg(feature = "gdbstub")]
+fn gdbserver(input: &str, port: u16) {
+    let Some(program) = parse_file(input) else {
+        std::process::exit(1);
+    };
+    let vm = VM::new(program, Vec::new());
+    let entrypoint = vm.entrypoint().expect("No entrypoint found");
+    let mut stub = mips_vm::gdbstub::GdbStub::new(vm, entrypoint);
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port)).unwrap_or_else(|err| {
+        eprintln!("Failed to listen on port {}: {}", port, err);
+        std::process::exit(1);
+    });
+    eprintln!("Listening for GDB on port {}", port);
+    if let Err(err) = stub.serve(&listener) {
+        eprintln!("Debugger connection failed: {}", err);
+        std::process::exit(1);
     }
 }
 
@@ -0,0 +1,189 @@
+use std::process::Command;
+
+/// Write `source` to a temporary assembly file named after `name`.
+fn source_file(name: &str, source: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("mips_cli_{}_{}.asm", name, std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn dump_registers_json() {
+    let path = source_file(
+        "dump_registers",
+        ".text\nmain:\n\tli $t0, 42\n\tli $v0, 10\n\tsyscall\n",
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_mips_cli"))
+        .args(["run", "--dump-registers", "--dump-format", "json"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\"$v0\": 10,"), "{}", stdout);
+    assert!(stdout.contains("\"$t0\": 42,"), "{}", stdout);
+}
+
+#[test]
+fn entry() {
+    let path = source_file(
+        "entry",
+        ".text\nmain:\n\tli $t0, 1\n\tli $v0, 10\n\tsyscall\nother:\n\tli $t0, 2\n\tli $v0, 10\n\tsyscall\n",
+    );
+    let run = |entry: &str| {
+        Command::new(env!("CARGO_BIN_EXE_mips_cli"))
+            .args(["run", "--dump-registers", "--entry", entry])
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+    let by_label = run("other");
+    let by_address = run("0x0040000c");
+    let missing = run("nowhere");
+    std::fs::remove_file(&path).unwrap();
+    for output in [by_label, by_address] {
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("$t0 = 0x00000002"), "{}", stdout);
+    }
+    assert!(!missing.status.success());
+    let stderr = String::from_utf8(missing.stderr).unwrap();
+    assert_eq!(stderr.trim(), "Entry label not found: nowhere");
+}
+
+#[test]
+fn trace() {
+    let path = source_file(
+        "trace",
+        ".text\nmain:\n\tli $t0, 42\n\tli $v0, 10\n\tsyscall\n",
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_mips_cli"))
+        .args(["run", "--trace"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines = stderr.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3, "{}", stderr);
+    assert!(
+        lines[0].starts_with("0x00400000  li $t0, 0x2a"),
+        "{}",
+        stderr
+    );
+    assert!(
+        lines[0].ends_with("$t0: 0x00000000 → 0x0000002A"),
+        "{}",
+        stderr
+    );
+    assert!(
+        lines[1].ends_with("$v0: 0x00000000 → 0x0000000A"),
+        "{}",
+        stderr
+    );
+    assert_eq!(lines[2], "0x00400008  syscall");
+}
+
+#[test]
+fn check_ra() {
+    let path = source_file(
+        "check_ra",
+        ".text\nmain:\n\tjal outer\n\tli $v0, 10\n\tsyscall\nouter:\n\tjal inner\n\tli $v0, 10\n\tsyscall\ninner:\n\tjr $ra\n",
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_mips_cli"))
+        .args(["run", "--check-ra"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        stderr.trim(),
+        "Warning: call at 0x0040000C <outer> overwrites $ra before it was saved"
+    );
+}
+
+#[test]
+fn lint() {
+    let path = source_file(
+        "lint",
+        ".text\n.globl main\nmain:\n\tli $s0, 1\n\tli $v0, 10\n\tsyscall\n",
+    );
+    let run = |flags: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_mips_cli"))
+            .arg("run")
+            .args(flags)
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+    let quiet = run(&[]);
+    let linted = run(&["--lint"]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(quiet.status.success());
+    assert_eq!(String::from_utf8(quiet.stderr).unwrap(), "");
+    assert!(linted.status.success());
+    assert_eq!(
+        String::from_utf8(linted.stderr).unwrap().trim(),
+        "Warning: main: Callee-saved register $s0 is written but never restored"
+    );
+}
+
+#[test]
+fn poison() {
+    let path = source_file(
+        "poison",
+        ".bss\nbuffer: .space 4\n.text\nmain:\n\tla $t0, buffer\n\tlw $a0, 0($t0)\n\tli $v0, 10\n\tsyscall\n",
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_mips_cli"))
+        .args(["run", "--poison", "--dump-registers"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("$a0 = 0xCCCCCCCC"), "{}", stdout);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        stderr.trim(),
+        "Warning: read of uninitialized memory at 0x10010000"
+    );
+}
+
+#[test]
+fn args() {
+    let path = source_file(
+        "args",
+        ".text\nmain:\n\tlw $a0, 4($a1)\n\tli $v0, 4\n\tsyscall\n\tli $v0, 10\n\tsyscall\n",
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_mips_cli"))
+        .args(["run", "--arg", "first", "--arg", "second"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "second");
+}
+
+#[test]
+fn parse_error() {
+    let path = source_file("parse_error", ".text\nmain:\n\tli $t0,\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_mips_cli"))
+        .arg("run")
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with(&format!("{}: 3:", path.display())),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,40 @@
+//! Micro-benchmark of reading a 1MB region of memory,
+//! with [`Memory::read`] allocating the result and [`Memory::read_into`] filling a buffer.
+//!
+//! Run with `cargo bench -p mips_vm --bench read_into`.
+
+use std::time::{Duration, Instant};
+
+use mips_vm::{memory::Memory, parser::parse};
+
+const REGION_SIZE: usize = 1 << 20;
+const ITERATIONS: u32 = 100;
+
+fn time(name: &str, mut read: impl FnMut()) {
+    // Warm up before measuring
+    read();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        read();
+    }
+    let elapsed: Duration = start.elapsed() / ITERATIONS;
+    println!("{:<10} {:>10.3?} per 1MB read", name, elapsed);
+}
+
+fn main() {
+    let source = format!(
+        ".data\nbuffer: .space {}\n.text\nmain:\n\tnop\n",
+        REGION_SIZE
+    );
+    let mut memory = Memory::load(parse(&source).unwrap(), Vec::new());
+    let buffer = memory.address_of_label("buffer").unwrap();
+
+    time("read", || {
+        std::hint::black_box(memory.read(buffer, REGION_SIZE).unwrap());
+    });
+    let mut buf = vec![0; REGION_SIZE];
+    time("read_into", || {
+        memory.read_into(buffer, &mut buf).unwrap();
+        std::hint::black_box(&buf);
+    });
+}
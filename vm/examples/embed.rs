@@ -0,0 +1,39 @@
+//! Embed the VM in an application: parse a program from a string,
+//! run it with captured input and output, and inspect its registers.
+//!
+//! Run with `cargo run -p mips_vm --example embed`.
+
+use mips_vm::{parse_result, Register, VM};
+
+const SOURCE: &str = "
+.data
+prompt: .asciiz \"Sum: \"
+.text
+main:
+\tli $v0, 5
+\tsyscall
+\tmove $t0, $v0
+\tli $v0, 5
+\tsyscall
+\taddu $t0, $t0, $v0
+\tla $a0, prompt
+\tli $v0, 4
+\tsyscall
+\tmove $a0, $t0
+\tli $v0, 1
+\tsyscall
+\tli $v0, 10
+\tsyscall
+";
+
+fn main() {
+    let program = parse_result(SOURCE).unwrap_or_else(|err| panic!("Parse error: {}", err));
+    let mut vm = VM::new(program, Vec::new());
+    let entrypoint = vm.entrypoint().expect("No entrypoint found");
+    let result = vm
+        .run_captured(entrypoint, "19\n23\n")
+        .unwrap_or_else(|err| panic!("Runtime error: {}", err));
+    println!("Output: {}", result.stdout);
+    println!("Instructions: {}", result.instructions);
+    println!("$t0 = {}", vm.registers().get(&Register::T0));
+}
@@ -28,8 +28,9 @@ impl Address {
         Address(address)
     }
 
-    pub fn from_page_number(page_number: u32) -> Address {
-        Address(page_number << 12)
+    /// Creates the address of the first byte of a page, for a power of two `page_size` in bytes.
+    pub fn from_page_number(page_number: u32, page_size: usize) -> Address {
+        Address(page_number << page_size.trailing_zeros())
     }
 
     /// Returns the 32-bit unsigned integer value of the address.
@@ -45,18 +46,18 @@ impl Address {
     ///
     /// # Returns
     ///
-    /// The page number of the address, assuming a page size of 4KB (12 bits).
-    pub fn page_number(&self) -> u32 {
-        self.0 >> 12
+    /// The page number of the address, for a power of two `page_size` in bytes.
+    pub fn page_number(&self, page_size: usize) -> u32 {
+        self.0 >> page_size.trailing_zeros()
     }
 
     /// Calculates the offset of the address within the page.
     ///
     /// # Returns
     ///
-    /// The offset of the address within the page, assuming a page size of 4KB (12 bits).
-    pub fn page_offset(&self) -> u32 {
-        self.0 & 0xFFF
+    /// The offset of the address within the page, for a power of two `page_size` in bytes.
+    pub fn page_offset(&self, page_size: usize) -> u32 {
+        self.0 & (page_size - 1) as u32
     }
 
     /// Creates an `Address` from a little-endian byte array.
@@ -94,6 +95,36 @@ impl Address {
         Address((self.0 as i32 + offset) as u32)
     }
 
+    /// Iterates over every address from `start` up to, but not including, `end`.
+    pub fn range(start: Address, end: Address) -> impl DoubleEndedIterator<Item = Address> {
+        (start.0..end.0).map(Address)
+    }
+
+    /// Iterates over the addresses from `start` up to, but not including, `end`,
+    /// `step` bytes apart.
+    pub fn step_by(start: Address, end: Address, step: u32) -> impl Iterator<Item = Address> {
+        (start.0..end.0).step_by(step as usize).map(Address)
+    }
+
+    /// Iterates over the addresses from `start` up to, but not including, `end`, one word apart.
+    pub fn step_by_words(start: Address, end: Address) -> impl Iterator<Item = Address> {
+        Address::step_by(start, end, 4)
+    }
+
+    /// Rounds the address down to a multiple of `alignment`.
+    pub fn align_down(&self, alignment: u32) -> Address {
+        Address(self.0 - self.0 % alignment)
+    }
+
+    /// Rounds the address up to a multiple of `alignment`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the aligned address does not fit in 32 bits.
+    pub fn align_up(&self, alignment: u32) -> Address {
+        Address(self.0.next_multiple_of(alignment))
+    }
+
     /// Returns a string representation of the address in hexadecimal format.
     ///
     /// # Returns
@@ -270,3 +301,35 @@ impl Display for Address {
         write!(f, "0x{:08X}", self.0)
     }
 }
+
+#[cfg(test)]
+mod test_address {
+    use super::Address;
+
+    #[test]
+    fn range() {
+        let start = Address::new(0x1001_0000);
+        let end = Address::new(0x1001_0003);
+        let addresses = Address::range(start, end).collect::<Vec<_>>();
+        assert_eq!(
+            addresses,
+            vec![start, Address::new(0x1001_0001), Address::new(0x1001_0002)]
+        );
+        let words = Address::step_by_words(start, start + 12u32).collect::<Vec<_>>();
+        assert_eq!(words, vec![start, start + 4u32, start + 8u32]);
+        assert_eq!(Address::step_by(start, start + 5u32, 2).count(), 3);
+        assert_eq!(Address::range(end, start).count(), 0);
+    }
+
+    #[test]
+    fn align() {
+        let address = Address::new(0x1001_0FFD);
+        assert_eq!(address.align_down(4), Address::new(0x1001_0FFC));
+        assert_eq!(address.align_up(4), Address::new(0x1001_1000));
+        assert_eq!(address.align_down(4096), Address::new(0x1001_0000));
+        assert_eq!(address.align_up(4096), Address::new(0x1001_1000));
+        let aligned = Address::new(0x0040_1000);
+        assert_eq!(aligned.align_down(4096), aligned);
+        assert_eq!(aligned.align_up(4096), aligned);
+    }
+}
@@ -1,9 +1,9 @@
-use std::sync::RwLock;
+use std::{fmt::Display, sync::RwLock};
 
 use crate::{
     address::Address,
     memory::LabelMap,
-    program::{Immediate, Instruction, InstructionKind},
+    program::{Immediate, Instruction, InstructionArg, InstructionKind},
     registers::Register,
 };
 
@@ -99,273 +99,508 @@ impl InstructionInfo {
         }
     }
 }
-pub fn assemble_all(instructions: &[Instruction], labels: &LabelMap) -> Vec<EncodedInstruction> {
-    instructions
+/// The reason an instruction could not be assembled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssembleErrorReason {
+    /// The label is not defined anywhere in the program.
+    UnknownLabel(String),
+    /// The operand is of a different type than required.
+    WrongOperand {
+        expected: &'static str,
+        found: InstructionArg,
+    },
+    /// The instruction has fewer operands than required.
+    MissingOperand { expected: &'static str },
+    /// The address of the label does not fit in a signed 16-bit offset.
+    LabelOutOfRange(String),
+}
+
+/// An instruction that could not be encoded into machine code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembleError {
+    pub instruction: Instruction,
+    pub reason: AssembleErrorReason,
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to assemble `{}`: ", self.instruction.show())?;
+        match &self.reason {
+            AssembleErrorReason::UnknownLabel(label) => write!(f, "unknown label {}", label),
+            AssembleErrorReason::WrongOperand { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found.show())
+            }
+            AssembleErrorReason::MissingOperand { expected } => {
+                write!(f, "missing {} operand", expected)
+            }
+            AssembleErrorReason::LabelOutOfRange(label) => {
+                write!(f, "label {} does not fit in a 16-bit offset", label)
+            }
+        }
+    }
+}
+
+/// Assemble all instructions, placed consecutively from `base_address`,
+/// collecting the errors of every instruction that failed.
+pub fn assemble_all(
+    instructions: &[Instruction],
+    base_address: Address,
+    labels: &LabelMap,
+) -> Result<Vec<EncodedInstruction>, Vec<AssembleError>> {
+    let (encoded, errors): (Vec<_>, Vec<_>) = instructions
         .iter()
-        .map(|i| encode_instruction(i, labels))
-        .collect()
+        .enumerate()
+        .map(|(index, i)| encode_instruction(i, base_address + index * Instruction::size(), labels))
+        .partition(Result::is_ok);
+    if errors.is_empty() {
+        Ok(encoded.into_iter().map(Result::unwrap).collect())
+    } else {
+        Err(errors.into_iter().map(Result::unwrap_err).collect())
+    }
 }
 
-pub fn info(instruction: &Instruction, labels: &LabelMap) -> InstructionInfo {
+/// The encoding of an `instruction` placed at `address`.
+pub fn info(
+    instruction: &Instruction,
+    address: Address,
+    labels: &LabelMap,
+) -> Result<InstructionInfo, AssembleError> {
     let args = RwLock::new(instruction.args.iter());
     let next = || args.write().unwrap().next();
-    let reg = || next().map(|arg| arg.clone().as_register().unwrap());
-    let imm = || {
-        next()
-            .map(|arg| arg.clone().as_immediate().unwrap())
-            .expect("Expected immediate argument")
+    let error = |reason| AssembleError {
+        instruction: instruction.clone(),
+        reason,
+    };
+    let wrong = |expected, found: &InstructionArg| {
+        error(AssembleErrorReason::WrongOperand {
+            expected,
+            found: found.clone(),
+        })
+    };
+    let missing = |expected| error(AssembleErrorReason::MissingOperand { expected });
+    let reg = || match next() {
+        Some(InstructionArg::Register(r)) => Ok(Some(*r)),
+        Some(arg) => Err(wrong("register", arg)),
+        None => Ok(None),
+    };
+    let imm = || match next() {
+        Some(InstructionArg::Immediate(i)) => Ok(*i),
+        Some(arg) => Err(wrong("immediate", arg)),
+        None => Err(missing("immediate")),
+    };
+    let label = |label: &String| {
+        labels
+            .get(label)
+            .copied()
+            .ok_or_else(|| error(AssembleErrorReason::UnknownLabel(label.clone())))
+    };
+    let addr = || match next() {
+        Some(InstructionArg::Label(l)) => label(l),
+        Some(arg) => Err(wrong("label", arg)),
+        None => Err(missing("label")),
     };
-    let addr = || {
-        next()
-            .map(|arg| {
-                let label = arg.clone().as_label().unwrap();
-                *labels.get(&label).expect("Expected label argument")
-            })
-            .expect("Expected address argument")
+    // Branches to a label are encoded as the signed word offset from the delay slot at `address + 4`
+    let branch = || match next() {
+        Some(InstructionArg::Label(l)) => {
+            let target = label(l)?.unwrap();
+            let offset = target.wrapping_sub(address.unwrap() + 4) as i32 >> 2;
+            Ok(offset as u16 as Immediate)
+        }
+        Some(InstructionArg::Immediate(i)) => Ok(*i),
+        Some(arg) => Err(wrong("label", arg)),
+        None => Err(missing("label")),
     };
-    let offset = || {
-        next()
-            .map(|arg| arg.clone().as_offset().unwrap())
-            .expect("Expected offset argument")
+    // A bare label operand addresses the label's memory directly: `label` is `label($zero)`
+    let offset = || match next() {
+        Some(InstructionArg::RegisterOffset(o, r)) => Ok((*o, *r)),
+        Some(InstructionArg::Label(l)) => Ok((label(l)?.unwrap() & 0xFFFF, Register::Zero)),
+        // The offset is sign-extended, so only the lowest and highest 32 KiB are reachable
+        Some(InstructionArg::LabelOffset(l, r)) => match label(l)?.unwrap() {
+            address @ (0..=0x7FFF | 0xFFFF_8000..) => Ok((address & 0xFFFF, *r)),
+            _ => Err(error(AssembleErrorReason::LabelOutOfRange(l.clone()))),
+        },
+        Some(arg) => Err(wrong("offset", arg)),
+        None => Err(missing("offset")),
     };
-    match instruction.kind {
+    Ok(match instruction.kind {
         // Arithmetic Logical Unit
         InstructionKind::Add => InstructionInfo::new(
-            InstructionFormat::register(reg(), reg(), reg(), None),
+            InstructionFormat::register(reg()?, reg()?, reg()?, None),
             0,
             0x20,
         ),
         InstructionKind::Addi => {
-            InstructionInfo::new(InstructionFormat::immediate(reg(), reg(), imm()), 8, 0)
+            InstructionInfo::new(InstructionFormat::immediate(reg()?, reg()?, imm()?), 8, 0)
         }
         InstructionKind::Addiu => {
-            InstructionInfo::new(InstructionFormat::immediate(reg(), reg(), imm()), 9, 0)
+            InstructionInfo::new(InstructionFormat::immediate(reg()?, reg()?, imm()?), 9, 0)
         }
         InstructionKind::Addu => InstructionInfo::new(
-            InstructionFormat::register(reg(), reg(), reg(), None),
+            InstructionFormat::register(reg()?, reg()?, reg()?, None),
             0,
             0x21,
         ),
         InstructionKind::Sub => InstructionInfo::new(
-            InstructionFormat::register(reg(), reg(), reg(), None),
+            InstructionFormat::register(reg()?, reg()?, reg()?, None),
             0,
             0x22,
         ),
         InstructionKind::Subu => InstructionInfo::new(
-            InstructionFormat::register(reg(), reg(), reg(), None),
+            InstructionFormat::register(reg()?, reg()?, reg()?, None),
             0,
             0x23,
         ),
         InstructionKind::And => InstructionInfo::new(
-            InstructionFormat::register(reg(), reg(), reg(), None),
+            InstructionFormat::register(reg()?, reg()?, reg()?, None),
             0,
             0x24,
         ),
         InstructionKind::Andi => {
-            InstructionInfo::new(InstructionFormat::immediate(reg(), reg(), imm()), 0xC, 0)
+            InstructionInfo::new(InstructionFormat::immediate(reg()?, reg()?, imm()?), 0xC, 0)
         }
 
         InstructionKind::Nor => InstructionInfo::new(
-            InstructionFormat::register(reg(), reg(), reg(), None),
+            InstructionFormat::register(reg()?, reg()?, reg()?, None),
             0,
             0x27,
         ),
         InstructionKind::Or => InstructionInfo::new(
-            InstructionFormat::register(reg(), reg(), reg(), None),
+            InstructionFormat::register(reg()?, reg()?, reg()?, None),
             0,
             0x25,
         ),
-        InstructionKind::Ori => {
-            InstructionInfo::new(InstructionFormat::immediate(reg(), reg(), imm()), 0x0D, 0)
-        }
+        InstructionKind::Ori => InstructionInfo::new(
+            InstructionFormat::immediate(reg()?, reg()?, imm()?),
+            0x0D,
+            0,
+        ),
         InstructionKind::Slt => InstructionInfo::new(
-            InstructionFormat::register(reg(), reg(), reg(), None),
+            InstructionFormat::register(reg()?, reg()?, reg()?, None),
             0,
             0x2A,
         ),
-        InstructionKind::Slti => {
-            InstructionInfo::new(InstructionFormat::immediate(reg(), reg(), imm()), 0x0A, 0)
-        }
-        InstructionKind::Sltiu => {
-            InstructionInfo::new(InstructionFormat::immediate(reg(), reg(), imm()), 0x0B, 0)
-        }
+        InstructionKind::Slti => InstructionInfo::new(
+            InstructionFormat::immediate(reg()?, reg()?, imm()?),
+            0x0A,
+            0,
+        ),
+        InstructionKind::Sltiu => InstructionInfo::new(
+            InstructionFormat::immediate(reg()?, reg()?, imm()?),
+            0x0B,
+            0,
+        ),
         InstructionKind::Sltu => InstructionInfo::new(
-            InstructionFormat::register(reg(), reg(), reg(), None),
+            InstructionFormat::register(reg()?, reg()?, reg()?, None),
             0,
             0x2B,
         ),
         InstructionKind::Xor => InstructionInfo::new(
-            InstructionFormat::register(reg(), reg(), reg(), None),
+            InstructionFormat::register(reg()?, reg()?, reg()?, None),
             0,
             0x26,
         ),
-        InstructionKind::Xori => {
-            InstructionInfo::new(InstructionFormat::immediate(reg(), reg(), imm()), 0x0E, 0)
-        }
+        InstructionKind::Xori => InstructionInfo::new(
+            InstructionFormat::immediate(reg()?, reg()?, imm()?),
+            0x0E,
+            0,
+        ),
 
         // Shifter
         InstructionKind::Sll => InstructionInfo::new(
-            InstructionFormat::register(reg(), None, reg(), Some(imm() as u8)),
+            InstructionFormat::register(reg()?, None, reg()?, Some(imm()? as u8 & 0x1F)),
             0,
             0,
         ),
         InstructionKind::Sllv => {
             // Note the order or registers is different from the other instructions
-            let rd = reg();
-            let rt = reg();
-            let rs = reg();
+            let rd = reg()?;
+            let rt = reg()?;
+            let rs = reg()?;
             InstructionInfo::new(InstructionFormat::register(rd, rs, rt, None), 0, 0x04)
         }
         InstructionKind::Sra => InstructionInfo::new(
-            InstructionFormat::register(reg(), None, reg(), Some(imm() as u8)),
+            InstructionFormat::register(reg()?, None, reg()?, Some(imm()? as u8 & 0x1F)),
             0,
             0x03,
         ),
         InstructionKind::Srav => {
             // Note the order or registers is different from the other instructions
-            let rd = reg();
-            let rt = reg();
-            let rs = reg();
+            let rd = reg()?;
+            let rt = reg()?;
+            let rs = reg()?;
             InstructionInfo::new(InstructionFormat::register(rd, rs, rt, None), 0, 0x07)
         }
 
         InstructionKind::Srl => InstructionInfo::new(
-            InstructionFormat::register(reg(), None, reg(), Some(imm() as u8)),
+            InstructionFormat::register(reg()?, None, reg()?, Some(imm()? as u8 & 0x1F)),
             0,
             0x02,
         ),
         InstructionKind::Srlv => {
             // Note the order or registers is different from the other instructions
-            let rd = reg();
-            let rt = reg();
-            let rs = reg();
+            let rd = reg()?;
+            let rt = reg()?;
+            let rs = reg()?;
             InstructionInfo::new(InstructionFormat::register(rd, rs, rt, None), 0, 0x06)
         }
 
-        // Multiply and Divide
-        InstructionKind::Div => InstructionInfo::new(
-            InstructionFormat::register(None, reg(), reg(), None),
+        // Rotate (MIPS32r2), encoded as `srl`/`srlv` with the otherwise unused `rs`/`shamt` field set to 1
+        InstructionKind::Rotr => InstructionInfo::new(
+            InstructionFormat::register(
+                reg()?,
+                Some(Register::At),
+                reg()?,
+                Some(imm()? as u8 & 0x1F),
+            ),
             0,
-            0x1A,
+            0x02,
         ),
-        InstructionKind::Divu => InstructionInfo::new(
-            InstructionFormat::register(None, reg(), reg(), None),
+        InstructionKind::Rotrv => {
+            // Note the order or registers is different from the other instructions
+            let rd = reg()?;
+            let rt = reg()?;
+            let rs = reg()?;
+            InstructionInfo::new(InstructionFormat::register(rd, rs, rt, Some(1)), 0, 0x06)
+        }
+
+        // Count leading zeros (MIPS32), with the destination repeated in `rt`
+        InstructionKind::Clz => {
+            let rd = reg()?;
+            let rs = reg()?;
+            InstructionInfo::new(InstructionFormat::register(rd, rs, rd, None), 0x1C, 0x20)
+        }
+
+        // Sign extension (MIPS32r2), selected by the `shamt` field of the `bshfl` function
+        InstructionKind::Seb => {
+            let rd = reg()?;
+            let rt = reg()?;
+            InstructionInfo::new(
+                InstructionFormat::register(rd, None, rt, Some(0x10)),
+                0x1F,
+                0x20,
+            )
+        }
+        InstructionKind::Seh => {
+            let rd = reg()?;
+            let rt = reg()?;
+            InstructionInfo::new(
+                InstructionFormat::register(rd, None, rt, Some(0x18)),
+                0x1F,
+                0x20,
+            )
+        }
+
+        // Coprocessor 0 with `MF` (0) or `MT` (4) in the `rs` field
+        InstructionKind::Mfc0 | InstructionKind::Mtc0 => {
+            let rt = reg()?;
+            let rd = reg()?;
+            let rs = match instruction.kind {
+                InstructionKind::Mfc0 => Register::Zero,
+                _ => Register::decode(4).unwrap(),
+            };
+            InstructionInfo::new(InstructionFormat::register(rd, Some(rs), rt, None), 0x10, 0)
+        }
+        InstructionKind::Mfhi => InstructionInfo::new(
+            InstructionFormat::register(reg()?, None, None, None),
             0,
-            0x1B,
+            0x10,
         ),
-        InstructionKind::Mult => {
-            let rd = reg();
-            let rs = reg();
-            let rt = reg();
-            InstructionInfo::new(InstructionFormat::register(rd, rs, rt, None), 0, 0x18)
-        }
-        InstructionKind::Multu => {
-            let rd = reg();
-            let rs = reg();
-            let rt = reg();
-            InstructionInfo::new(InstructionFormat::register(rd, rs, rt, None), 0, 0x19)
-        }
-
-        // Branch
-        InstructionKind::Beq => InstructionInfo::new(
-            InstructionFormat::immediate(reg(), reg(), addr().unwrap() as u16),
-            4,
+        InstructionKind::Mflo => InstructionInfo::new(
+            InstructionFormat::register(reg()?, None, None, None),
             0,
+            0x12,
         ),
-        InstructionKind::Blez => {
-            InstructionInfo::new(InstructionFormat::immediate(reg(), None, imm()), 6, 0)
+        // `mult $s, $t` and `div $s, $t` leave `rd` zero, the three operand forms also store into `rd`
+        InstructionKind::Mult
+        | InstructionKind::Multu
+        | InstructionKind::Div
+        | InstructionKind::Divu => {
+            let (rd, rs, rt) = match instruction.args.len() {
+                2 => (None, reg()?, reg()?),
+                _ => (reg()?, reg()?, reg()?),
+            };
+            let funct = match instruction.kind {
+                InstructionKind::Mult => 0x18,
+                InstructionKind::Multu => 0x19,
+                InstructionKind::Div => 0x1A,
+                _ => 0x1B,
+            };
+            InstructionInfo::new(InstructionFormat::register(rd, rs, rt, None), 0, funct)
+        }
+
+        // Branch, comparing `rs` with `rt`
+        InstructionKind::Beq | InstructionKind::Bne => {
+            let rs = reg()?;
+            let rt = reg()?;
+            let opcode = if instruction.kind == InstructionKind::Beq {
+                4
+            } else {
+                5
+            };
+            InstructionInfo::new(InstructionFormat::immediate(rt, rs, branch()?), opcode, 0)
         }
-        InstructionKind::Bne => {
-            InstructionInfo::new(InstructionFormat::immediate(reg(), reg(), imm()), 5, 0)
+        InstructionKind::Blez => {
+            InstructionInfo::new(InstructionFormat::immediate(None, reg()?, branch()?), 6, 0)
         }
         InstructionKind::Bgtz => {
-            InstructionInfo::new(InstructionFormat::immediate(reg(), None, imm()), 7, 0)
+            InstructionInfo::new(InstructionFormat::immediate(None, reg()?, branch()?), 7, 0)
         }
-        InstructionKind::J => InstructionInfo::new(InstructionFormat::jump(addr()), 2, 0),
-        InstructionKind::Jal => InstructionInfo::new(InstructionFormat::jump(addr()), 3, 0),
-        InstructionKind::Jalr => InstructionInfo::new(
-            InstructionFormat::register(None, reg(), None, None),
+        // REGIMM `bgezal $zero, offset`, with the 0x11 condition in the `rt` field
+        InstructionKind::Bal => InstructionInfo::new(
+            InstructionFormat::immediate(Register::decode(0x11), Some(Register::Zero), branch()?),
+            1,
             0,
-            0x09,
         ),
+        InstructionKind::J => InstructionInfo::new(InstructionFormat::jump(addr()?), 2, 0),
+        InstructionKind::Jal => InstructionInfo::new(InstructionFormat::jump(addr()?), 3, 0),
+        InstructionKind::Jalr => {
+            // `jalr $rs` links into `$ra`, `jalr $rd, $rs` into `$rd`
+            let (rd, rs) = if instruction.args.len() == 1 {
+                (Some(Register::Ra), reg()?)
+            } else {
+                (reg()?, reg()?)
+            };
+            InstructionInfo::new(InstructionFormat::register(rd, rs, None, None), 0, 0x09)
+        }
         InstructionKind::Jr => InstructionInfo::new(
-            InstructionFormat::register(None, reg(), None, None),
+            InstructionFormat::register(None, reg()?, None, None),
             0,
             0x08,
         ),
 
         // Memory Access
         InstructionKind::Lb => {
-            let rt = reg();
-            let (offset, rs) = offset();
+            let rt = reg()?;
+            let (offset, rs) = offset()?;
 
-            InstructionInfo::new(InstructionFormat::immediate(Some(rs), rt, offset), 0x20, 0)
+            InstructionInfo::new(InstructionFormat::immediate(rt, Some(rs), offset), 0x20, 0)
         }
         InstructionKind::Lbu => {
-            let rt = reg();
-            let (offset, rs) = offset();
-            InstructionInfo::new(InstructionFormat::immediate(Some(rs), rt, offset), 0x24, 0)
+            let rt = reg()?;
+            let (offset, rs) = offset()?;
+            InstructionInfo::new(InstructionFormat::immediate(rt, Some(rs), offset), 0x24, 0)
         }
         InstructionKind::Lh => {
-            let rt = reg();
-            let (offset, rs) = offset();
-            InstructionInfo::new(InstructionFormat::immediate(Some(rs), rt, offset), 0x21, 0)
+            let rt = reg()?;
+            let (offset, rs) = offset()?;
+            InstructionInfo::new(InstructionFormat::immediate(rt, Some(rs), offset), 0x21, 0)
         }
         InstructionKind::Lhu => {
-            let rt = reg();
-            let (offset, rs) = offset();
-            InstructionInfo::new(InstructionFormat::immediate(Some(rs), rt, offset), 0x25, 0)
+            let rt = reg()?;
+            let (offset, rs) = offset()?;
+            InstructionInfo::new(InstructionFormat::immediate(rt, Some(rs), offset), 0x25, 0)
         }
         InstructionKind::Lui => {
-            let rt = reg();
-            let imm = imm();
-            InstructionInfo::new(InstructionFormat::immediate(None, rt, imm), 0xF, 0)
+            let rt = reg()?;
+            let imm = imm()?;
+            InstructionInfo::new(InstructionFormat::immediate(rt, None, imm), 0xF, 0)
         }
         InstructionKind::Lw => {
-            let rt = reg();
-            let (offset, rs) = offset();
-            InstructionInfo::new(InstructionFormat::immediate(Some(rs), rt, offset), 0x23, 0)
+            let rt = reg()?;
+            let (offset, rs) = offset()?;
+            InstructionInfo::new(InstructionFormat::immediate(rt, Some(rs), offset), 0x23, 0)
+        }
+        InstructionKind::Lwl => {
+            let rt = reg()?;
+            let (offset, rs) = offset()?;
+            InstructionInfo::new(InstructionFormat::immediate(rt, Some(rs), offset), 0x22, 0)
+        }
+        InstructionKind::Lwr => {
+            let rt = reg()?;
+            let (offset, rs) = offset()?;
+            InstructionInfo::new(InstructionFormat::immediate(rt, Some(rs), offset), 0x26, 0)
         }
         InstructionKind::Sb => {
-            let rt = reg();
-            let (offset, rs) = offset();
-            InstructionInfo::new(InstructionFormat::immediate(Some(rs), rt, offset), 0x28, 0)
+            let rt = reg()?;
+            let (offset, rs) = offset()?;
+            InstructionInfo::new(InstructionFormat::immediate(rt, Some(rs), offset), 0x28, 0)
         }
         InstructionKind::Sh => {
-            let rt = reg();
-            let (offset, rs) = offset();
-            InstructionInfo::new(InstructionFormat::immediate(Some(rs), rt, offset), 0x29, 0)
+            let rt = reg()?;
+            let (offset, rs) = offset()?;
+            InstructionInfo::new(InstructionFormat::immediate(rt, Some(rs), offset), 0x29, 0)
         }
         InstructionKind::Sw => {
-            let rt = reg();
-            let (offset, rs) = offset();
-            InstructionInfo::new(InstructionFormat::immediate(Some(rs), rt, offset), 0x2B, 0)
+            let rt = reg()?;
+            let (offset, rs) = offset()?;
+            InstructionInfo::new(InstructionFormat::immediate(rt, Some(rs), offset), 0x2B, 0)
+        }
+        InstructionKind::Swl => {
+            let rt = reg()?;
+            let (offset, rs) = offset()?;
+            InstructionInfo::new(InstructionFormat::immediate(rt, Some(rs), offset), 0x2A, 0)
+        }
+        InstructionKind::Swr => {
+            let rt = reg()?;
+            let (offset, rs) = offset()?;
+            InstructionInfo::new(InstructionFormat::immediate(rt, Some(rs), offset), 0x2E, 0)
         }
 
         // Psuedo instructions
         // TODO: Replace with actual instructions in parser
         InstructionKind::La => InstructionInfo::new(
-            InstructionFormat::immediate(None, reg(), addr().unwrap() as u16),
+            InstructionFormat::immediate(None, reg()?, addr()?.unwrap() & 0xFFFF),
             0,
             0,
         ),
         InstructionKind::Li => {
-            InstructionInfo::new(InstructionFormat::immediate(None, reg(), imm()), 0, 0)
-        }
-        InstructionKind::Move => {
-            InstructionInfo::new(InstructionFormat::register(reg(), reg(), None, None), 0, 0)
-        }
+            // Only the lower half of a full word fits in the encoding
+            InstructionInfo::new(
+                InstructionFormat::immediate(None, reg()?, imm()? & 0xFFFF),
+                0,
+                0,
+            )
+        }
+        InstructionKind::Move => InstructionInfo::new(
+            InstructionFormat::register(reg()?, reg()?, None, None),
+            0,
+            0,
+        ),
+        InstructionKind::Rol => {
+            // Rotating left by `n` equals rotating right by `32 - n`
+            let rd = reg()?;
+            let rt = reg()?;
+            let shamt = (32 - (imm()? & 0x1F)) as u8 & 0x1F;
+            InstructionInfo::new(
+                InstructionFormat::register(rd, Some(Register::At), rt, Some(shamt)),
+                0,
+                0x02,
+            )
+        }
+        InstructionKind::Ror => InstructionInfo::new(
+            InstructionFormat::register(
+                reg()?,
+                Some(Register::At),
+                reg()?,
+                Some(imm()? as u8 & 0x1F),
+            ),
+            0,
+            0x02,
+        ),
         InstructionKind::Nop => {
             InstructionInfo::new(InstructionFormat::register(None, None, None, None), 0, 0)
         }
         InstructionKind::Syscall => {
             InstructionInfo::new(InstructionFormat::register(None, None, None, None), 0, 0x0C)
         }
-    }
+        InstructionKind::Break => {
+            InstructionInfo::new(InstructionFormat::register(None, None, None, None), 0, 0x0D)
+        }
+        // Coprocessor 0 with the `CO` bit set in the `rs` field
+        InstructionKind::Eret => InstructionInfo::new(
+            InstructionFormat::register(None, Register::decode(0x10), None, None),
+            0x10,
+            0x18,
+        ),
+    })
 }
 
-pub fn encode_instruction(instruction: &Instruction, labels: &LabelMap) -> EncodedInstruction {
-    let info = info(instruction, labels);
+pub fn encode_instruction(
+    instruction: &Instruction,
+    address: Address,
+    labels: &LabelMap,
+) -> Result<EncodedInstruction, AssembleError> {
+    let info = info(instruction, address, labels)?;
     let encoded = if info.format.is_register() {
         encode_register_type(&info, info.format.unwrap_register())
     } else if info.format.is_immediate() {
@@ -374,7 +609,7 @@ pub fn encode_instruction(instruction: &Instruction, labels: &LabelMap) -> Encod
         encode_jump_type(&info, info.format.unwrap_jump())
     };
     log::trace!("Encoded {:?} into 0x{:08x}", instruction, encoded);
-    encoded
+    Ok(encoded)
 }
 
 /// Encode R-type instruction.
@@ -417,7 +652,7 @@ pub fn encode_register_type(info: &InstructionInfo, args: &RegisterFormat) -> En
 /// |:--------:|:------:|:----:|:-----------------------:|
 /// |    6     |   5    |  5   |           16            |
 pub fn encode_immediate_type(info: &InstructionInfo, args: &ImmediateFormat) -> EncodedInstruction {
-    let mut immediate = args.imm as u32;
+    let mut immediate = args.imm;
     let mut rt = args.rt.as_ref().map(Register::encode).unwrap_or(0) as u32;
     let mut rs = args.rs.as_ref().map(Register::encode).unwrap_or(0) as u32;
     let mut opcode = info.opcode as u32;
@@ -436,6 +671,8 @@ pub fn encode_immediate_type(info: &InstructionInfo, args: &ImmediateFormat) ->
 }
 
 /// Encode J-type instruction.
+/// The `address` field holds the word address of the target,
+/// whose upper 4 bits are taken from the address of the delay slot when jumping.
 ///
 /// Sizes of fields (bits):
 ///
@@ -443,7 +680,7 @@ pub fn encode_immediate_type(info: &InstructionInfo, args: &ImmediateFormat) ->
 /// |:--------:|:---------------------------:|
 /// |    6     |              26             |
 pub fn encode_jump_type(info: &InstructionInfo, args: &JumpFormat) -> EncodedInstruction {
-    let mut address = args.address.unwrap();
+    let mut address = (args.address.unwrap() >> 2) & 0x03FF_FFFF;
     let mut opcode = info.opcode as u32;
 
     assert!(address < 2 << 26);
@@ -454,3 +691,121 @@ pub fn encode_jump_type(info: &InstructionInfo, args: &JumpFormat) -> EncodedIns
 
     opcode | address
 }
+
+#[cfg(test)]
+mod test_assembler {
+    use super::{assemble_all, encode_instruction, AssembleErrorReason};
+    use crate::{
+        address::Address,
+        memory::LabelMap,
+        program::{Instruction, InstructionArg, InstructionKind},
+        registers::Register,
+    };
+
+    #[test]
+    fn undefined_label() {
+        let instruction = Instruction {
+            kind: InstructionKind::J,
+            args: vec![InstructionArg::Label("nowhere".to_string())],
+        };
+        let errors = assemble_all(
+            std::slice::from_ref(&instruction),
+            Address::new(0),
+            &LabelMap::new(),
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].instruction, instruction);
+        assert_eq!(
+            errors[0].reason,
+            AssembleErrorReason::UnknownLabel("nowhere".to_string())
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "Failed to assemble `j nowhere`: unknown label nowhere"
+        );
+    }
+
+    #[test]
+    fn label_offset_out_of_range() {
+        let instruction = Instruction {
+            kind: InstructionKind::Lw,
+            args: vec![
+                InstructionArg::Register(Register::T0),
+                InstructionArg::LabelOffset("arr".to_string(), Register::T1),
+            ],
+        };
+        let mut labels = LabelMap::new();
+        labels.insert("arr".to_string(), Address::new(0x1001_0000));
+        let error = encode_instruction(&instruction, Address::new(0), &labels).unwrap_err();
+        assert_eq!(
+            error.reason,
+            AssembleErrorReason::LabelOutOfRange("arr".to_string())
+        );
+        labels.insert("arr".to_string(), Address::new(0x10));
+        assert_eq!(
+            encode_instruction(&instruction, Address::new(0), &labels),
+            Ok(0x8D28_0010)
+        );
+    }
+
+    #[test]
+    fn register_instead_of_immediate() {
+        let instruction = Instruction {
+            kind: InstructionKind::Addi,
+            args: vec![
+                InstructionArg::Register(Register::T0),
+                InstructionArg::Register(Register::T1),
+                InstructionArg::Register(Register::T2),
+            ],
+        };
+        let errors = assemble_all(&[instruction], Address::new(0), &LabelMap::new()).unwrap_err();
+        assert_eq!(
+            errors[0].reason,
+            AssembleErrorReason::WrongOperand {
+                expected: "immediate",
+                found: InstructionArg::Register(Register::T2),
+            }
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "Failed to assemble `addi $t0, $t1, $t2`: expected immediate, found $t2"
+        );
+    }
+
+    #[test]
+    fn branch_offset() {
+        let mut labels = LabelMap::new();
+        labels.insert("back".to_string(), Address::new(0x0040_0000));
+        labels.insert("forward".to_string(), Address::new(0x0040_0020));
+        let branch = |kind, label: &str| Instruction {
+            kind,
+            args: vec![
+                InstructionArg::Register(Register::T0),
+                InstructionArg::Register(Register::Zero),
+                InstructionArg::Label(label.to_string()),
+            ],
+        };
+        let at = Address::new(0x0040_0008);
+
+        // (0x00400020 - 0x0040000C) / 4 = 5
+        let word = encode_instruction(&branch(InstructionKind::Beq, "forward"), at, &labels);
+        assert_eq!(word.unwrap() & 0xFFFF, 5);
+        // (0x00400000 - 0x0040000C) / 4 = -3
+        let word = encode_instruction(&branch(InstructionKind::Bne, "back"), at, &labels);
+        assert_eq!(word.unwrap() & 0xFFFF, -3i16 as u16 as u32);
+    }
+
+    #[test]
+    fn jump_address() {
+        let mut labels = LabelMap::new();
+        labels.insert("target".to_string(), Address::new(0x0040_0010));
+        let jump = Instruction {
+            kind: InstructionKind::J,
+            args: vec![InstructionArg::Label("target".to_string())],
+        };
+        let words = assemble_all(&[jump], Address::new(0x0040_0000), &labels).unwrap();
+        assert_eq!(words[0] >> 26, 2);
+        assert_eq!(words[0] & 0x03FF_FFFF, 0x0040_0010 >> 2);
+    }
+}
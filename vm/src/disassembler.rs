@@ -0,0 +1,369 @@
+use crate::{
+    address::Address,
+    assembler::EncodedInstruction,
+    memory::LabelMap,
+    program::{Immediate, Instruction, InstructionArg, InstructionKind},
+    registers::Register,
+};
+
+/// The raw fields of an encoded instruction.
+/// Which fields are meaningful depends on the instruction format.
+struct Fields {
+    opcode: u8,
+    rs: Register,
+    rt: Register,
+    rd: Register,
+    shamt: u8,
+    funct: u8,
+    imm: Immediate,
+    address: u32,
+}
+
+impl Fields {
+    fn new(word: EncodedInstruction) -> Self {
+        let register = |shift: u32| Register::decode(((word >> shift) & 0x1F) as u8).unwrap();
+        Fields {
+            opcode: (word >> 26) as u8,
+            rs: register(21),
+            rt: register(16),
+            rd: register(11),
+            shamt: ((word >> 6) & 0x1F) as u8,
+            funct: (word & 0x3F) as u8,
+            imm: (word & 0xFFFF) as Immediate,
+            address: word & 0x03FF_FFFF,
+        }
+    }
+}
+
+/// Decode all words, placed consecutively from `base_address`.
+pub fn disassemble_all(
+    words: &[EncodedInstruction],
+    base_address: Address,
+    labels: &LabelMap,
+) -> Option<Vec<Instruction>> {
+    words
+        .iter()
+        .enumerate()
+        .map(|(index, word)| {
+            decode_instruction(*word, base_address + index * Instruction::size(), labels)
+        })
+        .collect()
+}
+
+/// Decode a machine word produced by [`crate::assembler::encode_instruction`] at `address` back into an instruction.
+/// Jump and branch targets are resolved back to labels, branches without a label keep their offset.
+///
+/// Pseudo instructions are not distinguishable from the real instructions sharing their encoding,
+/// and decode as the latter.
+///
+/// Returns `None` if the word is not a known instruction encoding.
+pub fn decode_instruction(
+    word: EncodedInstruction,
+    address: Address,
+    labels: &LabelMap,
+) -> Option<Instruction> {
+    use InstructionArg::{Immediate as Imm, Register as Reg};
+
+    let f = Fields::new(word);
+    let label = |address: u32| {
+        labels
+            .iter()
+            .find(|(_, a)| **a == Address::new(address))
+            .map(|(label, _)| InstructionArg::Label(label.clone()))
+    };
+    // Jumps stay within the 256 MB region of the delay slot
+    let jump = |field: u32| label(((address.unwrap() + 4) & 0xF000_0000) | (field << 2));
+    let branch = |imm: Immediate| {
+        let offset = (imm as i16 as i32) << 2;
+        label((address.unwrap() + 4).wrapping_add(offset as u32)).unwrap_or(Imm(imm))
+    };
+    // Loads and stores keep the data register in `rt` and the base register in `rs`
+    let memory = |kind| {
+        Some((
+            kind,
+            vec![Reg(f.rt), InstructionArg::RegisterOffset(f.imm, f.rs)],
+        ))
+    };
+
+    let (kind, args) = match f.opcode {
+        0 => match f.funct {
+            0x00 if word == 0 => (InstructionKind::Nop, vec![]),
+            0x00 => (
+                InstructionKind::Sll,
+                vec![Reg(f.rd), Reg(f.rt), Imm(f.shamt as Immediate)],
+            ),
+            0x02 if f.rs == Register::At => (
+                InstructionKind::Rotr,
+                vec![Reg(f.rd), Reg(f.rt), Imm(f.shamt as Immediate)],
+            ),
+            0x02 => (
+                InstructionKind::Srl,
+                vec![Reg(f.rd), Reg(f.rt), Imm(f.shamt as Immediate)],
+            ),
+            0x03 => (
+                InstructionKind::Sra,
+                vec![Reg(f.rd), Reg(f.rt), Imm(f.shamt as Immediate)],
+            ),
+            0x04 => (InstructionKind::Sllv, vec![Reg(f.rd), Reg(f.rt), Reg(f.rs)]),
+            0x06 if f.shamt == 1 => (
+                InstructionKind::Rotrv,
+                vec![Reg(f.rd), Reg(f.rt), Reg(f.rs)],
+            ),
+            0x06 => (InstructionKind::Srlv, vec![Reg(f.rd), Reg(f.rt), Reg(f.rs)]),
+            0x07 => (InstructionKind::Srav, vec![Reg(f.rd), Reg(f.rt), Reg(f.rs)]),
+            0x08 => (InstructionKind::Jr, vec![Reg(f.rs)]),
+            0x09 if f.rd == Register::Ra => (InstructionKind::Jalr, vec![Reg(f.rs)]),
+            0x09 => (InstructionKind::Jalr, vec![Reg(f.rd), Reg(f.rs)]),
+            0x0C => (InstructionKind::Syscall, vec![]),
+            0x0D => (InstructionKind::Break, vec![]),
+            0x10 => (InstructionKind::Mfhi, vec![Reg(f.rd)]),
+            0x12 => (InstructionKind::Mflo, vec![Reg(f.rd)]),
+            // The standard `mult $s, $t` and `div $s, $t` leave `rd` zero, the three operand forms store into it
+            0x18..=0x1B => {
+                let kind = match f.funct {
+                    0x18 => InstructionKind::Mult,
+                    0x19 => InstructionKind::Multu,
+                    0x1A => InstructionKind::Div,
+                    _ => InstructionKind::Divu,
+                };
+                match f.rd {
+                    Register::Zero => (kind, vec![Reg(f.rs), Reg(f.rt)]),
+                    _ => (kind, vec![Reg(f.rd), Reg(f.rs), Reg(f.rt)]),
+                }
+            }
+            0x20 => (InstructionKind::Add, vec![Reg(f.rd), Reg(f.rs), Reg(f.rt)]),
+            0x21 => (InstructionKind::Addu, vec![Reg(f.rd), Reg(f.rs), Reg(f.rt)]),
+            0x22 => (InstructionKind::Sub, vec![Reg(f.rd), Reg(f.rs), Reg(f.rt)]),
+            0x23 => (InstructionKind::Subu, vec![Reg(f.rd), Reg(f.rs), Reg(f.rt)]),
+            0x24 => (InstructionKind::And, vec![Reg(f.rd), Reg(f.rs), Reg(f.rt)]),
+            0x25 => (InstructionKind::Or, vec![Reg(f.rd), Reg(f.rs), Reg(f.rt)]),
+            0x26 => (InstructionKind::Xor, vec![Reg(f.rd), Reg(f.rs), Reg(f.rt)]),
+            0x27 => (InstructionKind::Nor, vec![Reg(f.rd), Reg(f.rs), Reg(f.rt)]),
+            0x2A => (InstructionKind::Slt, vec![Reg(f.rd), Reg(f.rs), Reg(f.rt)]),
+            0x2B => (InstructionKind::Sltu, vec![Reg(f.rd), Reg(f.rs), Reg(f.rt)]),
+            _ => return None,
+        },
+        0x1C if f.funct == 0x20 => (InstructionKind::Clz, vec![Reg(f.rd), Reg(f.rs)]),
+        0x1F if f.funct == 0x20 && f.shamt == 0x10 => {
+            (InstructionKind::Seb, vec![Reg(f.rd), Reg(f.rt)])
+        }
+        0x1F if f.funct == 0x20 && f.shamt == 0x18 => {
+            (InstructionKind::Seh, vec![Reg(f.rd), Reg(f.rt)])
+        }
+        0x01 if f.rt as u8 == 0x11 && f.rs == Register::Zero => {
+            (InstructionKind::Bal, vec![branch(f.imm)])
+        }
+        0x10 if f.rs as u8 == 0x10 && f.funct == 0x18 => (InstructionKind::Eret, vec![]),
+        0x10 if f.rs == Register::Zero && f.funct == 0 => {
+            (InstructionKind::Mfc0, vec![Reg(f.rt), Reg(f.rd)])
+        }
+        0x10 if f.rs as u8 == 4 && f.funct == 0 => {
+            (InstructionKind::Mtc0, vec![Reg(f.rt), Reg(f.rd)])
+        }
+        0x02 => (InstructionKind::J, vec![jump(f.address)?]),
+        0x03 => (InstructionKind::Jal, vec![jump(f.address)?]),
+        0x04 => (
+            InstructionKind::Beq,
+            vec![Reg(f.rs), Reg(f.rt), branch(f.imm)],
+        ),
+        0x05 => (
+            InstructionKind::Bne,
+            vec![Reg(f.rs), Reg(f.rt), branch(f.imm)],
+        ),
+        0x06 => (InstructionKind::Blez, vec![Reg(f.rs), branch(f.imm)]),
+        0x07 => (InstructionKind::Bgtz, vec![Reg(f.rs), branch(f.imm)]),
+        0x08 => (
+            InstructionKind::Addi,
+            vec![Reg(f.rt), Reg(f.rs), Imm(f.imm)],
+        ),
+        0x09 => (
+            InstructionKind::Addiu,
+            vec![Reg(f.rt), Reg(f.rs), Imm(f.imm)],
+        ),
+        0x0A => (
+            InstructionKind::Slti,
+            vec![Reg(f.rt), Reg(f.rs), Imm(f.imm)],
+        ),
+        0x0B => (
+            InstructionKind::Sltiu,
+            vec![Reg(f.rt), Reg(f.rs), Imm(f.imm)],
+        ),
+        0x0C => (
+            InstructionKind::Andi,
+            vec![Reg(f.rt), Reg(f.rs), Imm(f.imm)],
+        ),
+        0x0D => (InstructionKind::Ori, vec![Reg(f.rt), Reg(f.rs), Imm(f.imm)]),
+        0x0E => (
+            InstructionKind::Xori,
+            vec![Reg(f.rt), Reg(f.rs), Imm(f.imm)],
+        ),
+        0x0F => (InstructionKind::Lui, vec![Reg(f.rt), Imm(f.imm)]),
+        0x20 => memory(InstructionKind::Lb)?,
+        0x21 => memory(InstructionKind::Lh)?,
+        0x22 => memory(InstructionKind::Lwl)?,
+        0x23 => memory(InstructionKind::Lw)?,
+        0x24 => memory(InstructionKind::Lbu)?,
+        0x25 => memory(InstructionKind::Lhu)?,
+        0x26 => memory(InstructionKind::Lwr)?,
+        0x28 => memory(InstructionKind::Sb)?,
+        0x29 => memory(InstructionKind::Sh)?,
+        0x2A => memory(InstructionKind::Swl)?,
+        0x2B => memory(InstructionKind::Sw)?,
+        0x2E => memory(InstructionKind::Swr)?,
+        _ => return None,
+    };
+    Some(Instruction { kind, args })
+}
+
+#[cfg(test)]
+mod test_disassembler {
+    use super::decode_instruction;
+    use crate::{
+        address::Address, assembler::encode_instruction, memory::LabelMap, parser::parse,
+        program::Instruction,
+    };
+
+    #[test]
+    fn decode_encoded() {
+        let program = parse(
+            ".text
+main:
+\taddu $t0, $t1, $t2
+\tsrl $t0, $t1, 3
+\tsw $t1, 8($sp)
+\tori $t0, $t1, 0xFF
+\tseh $t0, $t1
+\tclz $t0, $t1
+\tjr $ra
+\tj main
+\tbne $t0, $zero, main
+\tblez $t0, 2
+\tbal main
+\tbreak
+\teret
+\tmfc0 $t0, $13
+\tmtc0 $t0, $14
+\tmult $t0, $t1, $t2
+\tmultu $t1, $t2
+\tdiv $t0, $t1, $t2
+\tdiv $t1, $t2
+\tdivu $t0, $t1, $t2
+\tdivu $t1, $t2
+",
+        )
+        .unwrap();
+        let mut labels = LabelMap::new();
+        labels.insert("main".to_string(), 0x0040_0000.into());
+        let mut address = Address::new(0x0040_0000);
+        for instruction in program.text_section.instructions() {
+            let word = encode_instruction(instruction, address, &labels).unwrap();
+            assert_eq!(
+                decode_instruction(word, address, &labels).as_ref(),
+                Some(instruction)
+            );
+            address += Instruction::size();
+        }
+    }
+
+    /// Words as emitted by GNU `as` for the same instructions.
+    #[test]
+    fn binutils_words() {
+        let program = parse(
+            ".text
+main:
+\tlw $t0, 4($sp)
+\tlui $t0, 0x1001
+\tsw $ra, 20($sp)
+\tbeq $t0, $t1, 3
+\tbgtz $t0, 3
+\tmult $t0, $t1
+",
+        )
+        .unwrap();
+        let words = [
+            0x8FA8_0004,
+            0x3C08_1001,
+            0xAFBF_0014,
+            0x1109_0003,
+            0x1D00_0003,
+            0x0109_0018,
+        ];
+        let labels = LabelMap::new();
+        let mut address = Address::new(0x0040_0000);
+        for (instruction, word) in program.text_section.instructions().into_iter().zip(words) {
+            assert_eq!(
+                encode_instruction(instruction, address, &labels).unwrap(),
+                word,
+                "{}",
+                instruction
+            );
+            assert_eq!(
+                decode_instruction(word, address, &labels).as_ref(),
+                Some(instruction)
+            );
+            address += Instruction::size();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_round_trip {
+    use super::disassemble_all;
+    use crate::{assembler::assemble_all, memory::Memory, parser::parse, program::Instruction};
+    use std::mem::discriminant;
+
+    /// Assemble every instruction of `source` and decode it back,
+    /// checking that the kind and operand types survive the round trip.
+    fn round_trip(name: &str, source: &str) {
+        let memory = Memory::load(parse(source).unwrap(), Vec::new());
+        let program = parse(source).unwrap();
+        let instructions = program.text_section.instructions_move();
+        let base = memory.text().start_address;
+        let words = assemble_all(&instructions, base, memory.labels()).unwrap();
+        for (index, (instruction, word)) in instructions.iter().zip(&words).enumerate() {
+            if instruction.kind.is_pseudo() {
+                continue;
+            }
+            let decoded = disassemble_all(
+                &[*word],
+                base + index * Instruction::size(),
+                memory.labels(),
+            );
+            let decoded = decoded.and_then(|mut decoded| decoded.pop());
+            let matches = decoded.as_ref().is_some_and(|decoded| {
+                decoded.kind == instruction.kind
+                    && decoded.args.len() == instruction.args.len()
+                    && decoded
+                        .args
+                        .iter()
+                        .zip(&instruction.args)
+                        .all(|(a, b)| discriminant(a) == discriminant(b))
+            });
+            assert!(
+                matches,
+                "{} instruction {}: `{}` encoded as 0x{:08x} decoded as {}",
+                name,
+                index,
+                instruction.show(),
+                word,
+                decoded.map_or("nothing".to_string(), |decoded| format!(
+                    "`{}`",
+                    decoded.show()
+                )),
+            );
+        }
+    }
+
+    #[test]
+    fn examples() {
+        round_trip(
+            "hello_world.asm",
+            include_str!("../../examples/hello_world.asm"),
+        );
+        round_trip(
+            "mmio_hello_world.asm",
+            include_str!("../../examples/mmio_hello_world.asm"),
+        );
+        // mem_text.asm is left out, its immediate does not fit in 16 bits
+    }
+}
@@ -0,0 +1,312 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+};
+
+use crate::{
+    address::Address,
+    registers::Register,
+    vm::{VmError, VM},
+};
+
+/// Registers in a `g` packet, numbered as GDB does for MIPS:
+/// the general purpose registers, followed by `sr`, `lo`, `hi`, `bad`, `cause` and `pc`.
+const REGISTER_COUNT: usize = 38;
+const SR: usize = 32;
+const LO: usize = 33;
+const HI: usize = 34;
+const BAD: usize = 35;
+const CAUSE: usize = 36;
+const PC: usize = 37;
+
+/// Stop reply for a program stopped by a breakpoint or a single step (`SIGTRAP`).
+const STOPPED: &str = "S05";
+/// Stop reply for a program stopped by a runtime error (`SIGSEGV`).
+const FAULTED: &str = "S0b";
+/// Error reply for memory that cannot be accessed (`EFAULT`).
+const BAD_ADDRESS: &str = "E0e";
+/// Error reply for a malformed packet.
+const MALFORMED: &str = "E01";
+/// The most bytes an `m` packet reads, GDB accepts a shorter reply and reads the rest after.
+const MAX_READ_LENGTH: usize = 4096;
+
+/// A stub for the [GDB Remote Serial Protocol](https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html),
+/// letting a debugger control the VM over TCP, e.g. `target remote :1234` in GDB.
+///
+/// Supports reading and writing registers (`g`/`G`) and memory (`m`/`M`),
+/// stepping and continuing (`s`/`c`) and breakpoints (`Z0`/`z0`).
+pub struct GdbStub {
+    vm: VM,
+}
+
+impl GdbStub {
+    /// Debug `vm`, stopped before the instruction at `entrypoint`.
+    pub fn new(mut vm: VM, entrypoint: Address) -> Self {
+        vm.registers_mut().pc = entrypoint.unwrap();
+        GdbStub { vm }
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    /// Accept a single debugger connection on `listener`,
+    /// and serve it until the debugger detaches, kills the program or disconnects.
+    pub fn serve(&mut self, listener: &TcpListener) -> std::io::Result<()> {
+        let (stream, address) = listener.accept()?;
+        log::info!("Debugger connected from {}", address);
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        while let Some((packet, sum)) = read_packet(&mut reader)? {
+            if sum != checksum(&packet) {
+                writer.write_all(b"-")?;
+                continue;
+            }
+            writer.write_all(b"+")?;
+            log::debug!("GDB packet: {}", packet);
+            match self.handle(&packet) {
+                Some(reply) => write_packet(&mut writer, &reply)?,
+                None => break,
+            }
+            if packet == "D" {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// The reply to `packet`, or `None` if the debugger killed the program.
+    /// Unsupported packets get an empty reply.
+    fn handle(&mut self, packet: &str) -> Option<String> {
+        let args = packet.get(1..).unwrap_or_default();
+        let reply = match packet.chars().next() {
+            Some('?') => STOPPED.to_string(),
+            Some('g') => self.read_registers(),
+            Some('G') => self.write_registers(args),
+            Some('m') => self.read_memory(args),
+            Some('M') => self.write_memory(args),
+            Some('s') => {
+                let result = self.vm.step();
+                self.stop_reply(result)
+            }
+            Some('c') => {
+                let result = self.vm.resume();
+                self.stop_reply(result)
+            }
+            Some('Z') | Some('z') if args.starts_with("0,") => {
+                match parse_range(&args[2..]) {
+                    Some((address, _)) if packet.starts_with('Z') => {
+                        self.vm.add_breakpoint(address)
+                    }
+                    Some((address, _)) => {
+                        self.vm.remove_breakpoint(address);
+                    }
+                    None => return Some(MALFORMED.to_string()),
+                }
+                "OK".to_string()
+            }
+            Some('D') => "OK".to_string(),
+            Some('k') => return None,
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn registers(&self) -> [u32; REGISTER_COUNT] {
+        let registers = self.vm.registers();
+        let mut values = [0; REGISTER_COUNT];
+        values[..32].copy_from_slice(&registers.all());
+        values[SR] = registers.cp0.status;
+        values[LO] = registers.lo;
+        values[HI] = registers.hi;
+        values[BAD] = registers.cp0.bad_vaddr;
+        values[CAUSE] = registers.cp0.cause;
+        values[PC] = registers.pc;
+        values
+    }
+
+    fn read_registers(&self) -> String {
+        let bytes = self
+            .registers()
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        hex(&bytes)
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let Some(bytes) = unhex(args) else {
+            return MALFORMED.to_string();
+        };
+        let values = bytes
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<_>>();
+        if values.len() < REGISTER_COUNT {
+            return MALFORMED.to_string();
+        }
+        let registers = self.vm.registers_mut();
+        for (register, value) in Register::ALL.iter().zip(&values) {
+            registers.set(register, *value);
+        }
+        registers.cp0.status = values[SR];
+        registers.lo = values[LO];
+        registers.hi = values[HI];
+        registers.cp0.bad_vaddr = values[BAD];
+        registers.cp0.cause = values[CAUSE];
+        registers.pc = values[PC];
+        "OK".to_string()
+    }
+
+    fn read_memory(&mut self, args: &str) -> String {
+        let Some((address, length)) = parse_range(args) else {
+            return MALFORMED.to_string();
+        };
+        match self
+            .vm
+            .memory_mut()
+            .peek(address, length.min(MAX_READ_LENGTH))
+        {
+            Ok(bytes) => hex(&bytes),
+            Err(_) => BAD_ADDRESS.to_string(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else {
+            return MALFORMED.to_string();
+        };
+        let (Some((address, length)), Some(bytes)) = (parse_range(range), unhex(data)) else {
+            return MALFORMED.to_string();
+        };
+        if bytes.len() != length {
+            return MALFORMED.to_string();
+        }
+        match self.vm.memory_mut().write(address, &bytes) {
+            Ok(()) => "OK".to_string(),
+            Err(_) => BAD_ADDRESS.to_string(),
+        }
+    }
+
+    fn stop_reply(&mut self, result: Result<bool, VmError>) -> String {
+        self.vm.flush_output();
+        match result {
+            Ok(false) => STOPPED.to_string(),
+            Ok(true) => format!("W{:02x}", self.vm.exit_code().unwrap_or(0) as u8),
+            Err(err) => {
+                log::error!("Runtime error: {}", err);
+                FAULTED.to_string()
+            }
+        }
+    }
+}
+
+/// Parse an `address,length` pair in hex.
+fn parse_range(args: &str) -> Option<(Address, usize)> {
+    let (address, length) = args.split_once(',')?;
+    let address = u32::from_str_radix(address, 16).ok()?;
+    let length = usize::from_str_radix(length, 16).ok()?;
+    Some((Address::new(address), length))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn checksum(packet: &str) -> u8 {
+    packet.bytes().fold(0, u8::wrapping_add)
+}
+
+/// Read the next `$packet#checksum`, skipping acknowledgements,
+/// returning the packet and its checksum, or `None` at the end of the stream.
+fn read_packet(reader: &mut impl BufRead) -> std::io::Result<Option<(String, u8)>> {
+    let mut skipped = Vec::new();
+    if reader.read_until(b'$', &mut skipped)? == 0 || skipped.last() != Some(&b'$') {
+        return Ok(None);
+    }
+    let mut packet = Vec::new();
+    reader.read_until(b'#', &mut packet)?;
+    if packet.pop() != Some(b'#') {
+        return Ok(None);
+    }
+    let mut sum = [0; 2];
+    reader.read_exact(&mut sum)?;
+    let sum = std::str::from_utf8(&sum)
+        .ok()
+        .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+        .unwrap_or_default();
+    Ok(Some((String::from_utf8_lossy(&packet).into_owned(), sum)))
+}
+
+fn write_packet(writer: &mut impl Write, packet: &str) -> std::io::Result<()> {
+    write!(writer, "${}#{:02x}", packet, checksum(packet))?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod test_gdbstub {
+    use super::{read_packet, write_packet, GdbStub};
+    use crate::{parser::parse, registers::Register, vm::VM};
+    use std::{
+        io::{BufReader, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    #[test]
+    fn step_and_continue() {
+        let program = parse(".text\nmain:\n\tli $t0, 42\n\tli $v0, 10\n\tsyscall\n").unwrap();
+        let vm = VM::new(program, Vec::new());
+        let entrypoint = vm.entrypoint().unwrap();
+        let mut stub = GdbStub::new(vm, entrypoint);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = |packet: &str| {
+                write_packet(&mut stream, packet).unwrap();
+                let (reply, _) = read_packet(&mut reader).unwrap().unwrap();
+                stream.write_all(b"+").unwrap();
+                reply
+            };
+            ["g", "s", "g", "Z0,400008,4", "c", "m400008,4", "c", "D"].map(&mut request)
+        });
+        stub.serve(&listener).unwrap();
+        let replies = client.join().unwrap();
+
+        // Registers are little-endian, with $t0 8th and pc 37th
+        let register =
+            |registers: &str, index: usize| registers[index * 8..index * 8 + 8].to_string();
+        assert_eq!(register(&replies[0], 37), "00004000");
+        assert_eq!(replies[1], "S05");
+        assert_eq!(register(&replies[2], 8), "2a000000");
+        assert_eq!(register(&replies[2], 37), "04004000");
+        assert_eq!(replies[3], "OK");
+        assert_eq!(replies[4], "S05");
+        assert_eq!(replies[5], "0c000000");
+        assert_eq!(replies[6], "W00");
+        assert_eq!(replies[7], "OK");
+        assert_eq!(stub.vm().registers().get(&Register::T0), 42);
+    }
+
+    #[test]
+    fn read_memory_length() {
+        let program = parse(".text\nmain:\n\tori $t0, $zero, 42\n").unwrap();
+        let vm = VM::new(program, Vec::new());
+        let entrypoint = vm.entrypoint().unwrap();
+        let mut stub = GdbStub::new(vm, entrypoint);
+        assert_eq!(stub.handle("m400000,4").unwrap(), "2a000834");
+        assert_eq!(stub.handle("m400000,ffffffff").unwrap(), "E0e");
+    }
+}
@@ -0,0 +1,68 @@
+use std::{
+    cell::RefCell,
+    io::{BufRead, Write},
+    rc::Rc,
+};
+
+/// The input sink read by the VM's read syscalls.
+pub type Input = Box<dyn BufRead>;
+
+/// The output sink written by the VM's print syscalls.
+pub type Output = Box<dyn Write>;
+
+/// The input sink used until another is set.
+#[cfg(feature = "std_io")]
+pub(crate) fn default_input() -> Input {
+    Box::new(std::io::BufReader::new(std::io::stdin()))
+}
+
+/// The input sink used until another is set, without stdin.
+#[cfg(not(feature = "std_io"))]
+pub(crate) fn default_input() -> Input {
+    Box::new(std::io::empty())
+}
+
+/// The output sink used until another is set.
+#[cfg(feature = "std_io")]
+pub(crate) fn default_output() -> Output {
+    Box::new(std::io::stdout())
+}
+
+/// The output sink used until another is set, discarding all output without stdout.
+#[cfg(not(feature = "std_io"))]
+pub(crate) fn default_output() -> Output {
+    Box::new(std::io::sink())
+}
+
+/// An output sink writing into a shared in-memory buffer.
+/// Clones share the same buffer, so the output can be inspected
+/// after the VM, which owns the sink, has finished writing.
+#[derive(Debug, Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The bytes written so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    /// The bytes written so far, as (lossy) UTF-8 text.
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,9 +1,44 @@
+//! A MIPS32 virtual machine: parse assembly into a [`Program`], load it into a [`VM`] and run it.
+//!
+//! ```
+//! use mips_vm::{parse_result, Register, VM};
+//!
+//! let program = parse_result(".text\nmain:\n\tli $a0, 42\n\tli $v0, 1\n\tsyscall\n\tli $v0, 10\n\tsyscall\n")
+//!     .expect("valid program");
+//! let mut vm = VM::new(program, Vec::new());
+//! let entrypoint = vm.entrypoint().unwrap();
+//! let result = vm.run_captured(entrypoint, "").expect("program runs");
+//! assert_eq!(result.stdout, "42");
+//! assert_eq!(vm.registers().get(&Register::A0), 42);
+//! ```
+//!
+//! The most used types are re-exported here, the others are found in their modules.
+
 pub mod address;
 pub mod assembler;
+#[cfg(feature = "std_io")]
 pub mod compiler;
+pub mod disassembler;
+#[cfg(feature = "gdbstub")]
+pub mod gdbstub;
+pub mod io;
+pub mod lint;
 pub mod memory;
 pub mod parser;
 pub mod program;
 pub mod registers;
+#[cfg(feature = "serialize")]
+pub mod serialize;
+pub mod syscall;
+#[cfg(feature = "std_io")]
 pub mod transpilers;
 pub mod vm;
+
+pub use address::Address;
+pub use memory::{Memory, MemoryError};
+pub use parser::{parse, parse_result, ParseError, ParseOptions};
+pub use program::{LinkError, Program};
+pub use registers::{Register, Registers};
+#[cfg(feature = "serialize")]
+pub use serialize::DecodeError;
+pub use vm::{RunResult, VmError, VM};
@@ -0,0 +1,187 @@
+use std::fmt::Display;
+
+use crate::{
+    program::{Block, InstructionArg, InstructionKind, Program},
+    registers::{AbiRole, Register},
+};
+
+/// A likely mistake in a program, found without running it.
+#[derive(Debug, PartialEq)]
+pub struct Warning {
+    /// The label of the function the warning is about.
+    pub label: String,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.label, self.message)
+    }
+}
+
+/// Check the program for calling convention mistakes,
+/// and for execution running past the end of the text section.
+pub fn lint(program: &Program) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    for (label, blocks) in functions(program) {
+        unrestored_saved_registers(label, blocks, &mut warnings);
+    }
+    fall_off_end(&program.text_section.blocks, &mut warnings);
+    warnings
+}
+
+/// The `.globl` functions of the text section, each with the blocks
+/// from its label up to the next global label.
+fn functions(program: &Program) -> Vec<(&str, &[Block])> {
+    let text = &program.text_section;
+    let starts = text
+        .blocks
+        .iter()
+        .enumerate()
+        .filter(|(_, block)| text.global_labels.contains(&block.label))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, start)| {
+            let end = starts.get(i + 1).copied().unwrap_or(text.blocks.len());
+            (
+                text.blocks[*start].label.as_str(),
+                &text.blocks[*start..end],
+            )
+        })
+        .collect()
+}
+
+/// Warn about `$s0`-`$s7` registers a function writes, but never restores with a `lw`.
+fn unrestored_saved_registers(label: &str, blocks: &[Block], warnings: &mut Vec<Warning>) {
+    let mut written: Vec<Register> = Vec::new();
+    let mut restored: Vec<Register> = Vec::new();
+    for instruction in blocks.iter().flat_map(|block| &block.instructions) {
+        let Some(register) = instruction.destination() else {
+            continue;
+        };
+        if register.abi_role() != AbiRole::Saved {
+            continue;
+        }
+        let registers = match instruction.kind {
+            InstructionKind::Lw => &mut restored,
+            _ => &mut written,
+        };
+        if !registers.contains(&register) {
+            registers.push(register);
+        }
+    }
+    for register in written {
+        if !restored.contains(&register) {
+            warnings.push(Warning {
+                label: label.to_string(),
+                message: format!(
+                    "Callee-saved register {} is written but never restored",
+                    register.show()
+                ),
+            });
+        }
+    }
+}
+
+/// Warn if the last instruction of the text section is neither an unconditional jump
+/// nor an exit syscall, so that execution can continue past the end of `.text`.
+fn fall_off_end(blocks: &[Block], warnings: &mut Vec<Warning>) {
+    let Some(block) = blocks
+        .iter()
+        .rev()
+        .find(|block| !block.instructions.is_empty())
+    else {
+        return;
+    };
+    let (last, preceding) = block.instructions.split_last().unwrap();
+    let terminated = match last.kind {
+        InstructionKind::J
+        | InstructionKind::Jr
+        | InstructionKind::Eret
+        | InstructionKind::Break => true,
+        // Exit (10) or exit with a code (17), the service set by the last write to $v0
+        InstructionKind::Syscall => preceding
+            .iter()
+            .rev()
+            .find(|instruction| instruction.destination() == Some(Register::V0))
+            .is_some_and(|instruction| {
+                instruction.kind == InstructionKind::Li
+                    && matches!(instruction.args[1], InstructionArg::Immediate(10 | 17))
+            }),
+        _ => false,
+    };
+    if !terminated {
+        let label = match block.label.as_str() {
+            "" => ".text",
+            label => label,
+        };
+        warnings.push(Warning {
+            label: label.to_string(),
+            message: format!(
+                "Execution can fall off the end of .text after `{}`, add an exit syscall or a jump",
+                last.show()
+            ),
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_lint {
+    use super::lint;
+    use crate::parser::parse;
+
+    #[test]
+    fn clobbered_saved_register() {
+        let program = parse(
+            ".text
+.globl clobber
+clobber:
+\tli $s0, 1
+\tli $s1, 2
+\tjr $ra
+.globl preserve
+preserve:
+\tsw $s0, 0($sp)
+\tli $s0, 3
+\tlw $s0, 0($sp)
+\tjr $ra
+.globl multiply
+multiply:
+\tmult $s0, $s1
+\tmflo $v0
+\tjr $ra
+",
+        )
+        .unwrap();
+        let warnings = lint(&program)
+            .iter()
+            .map(|warning| warning.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            vec![
+                "clobber: Callee-saved register $s0 is written but never restored",
+                "clobber: Callee-saved register $s1 is written but never restored",
+            ]
+        );
+    }
+
+    #[test]
+    fn fall_off_end() {
+        let warnings = |source: &str| {
+            lint(&parse(source).unwrap())
+                .iter()
+                .map(|warning| warning.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            warnings(".text\nmain:\n\tjal print\n\tli $v0, 10\n\tsyscall\nprint:\n\tli $v0, 1\n\tsyscall\n"),
+            vec!["print: Execution can fall off the end of .text after `syscall`, add an exit syscall or a jump"]
+        );
+        assert!(warnings(".text\nmain:\n\tli $v0, 10\n\tsyscall\n").is_empty());
+        assert!(warnings(".text\nmain:\n\tnop\nloop:\n\tj loop\n").is_empty());
+    }
+}
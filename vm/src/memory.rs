@@ -1,8 +1,9 @@
-use std::{collections::HashMap, mem::size_of};
+use std::{collections::BTreeMap, mem::size_of};
 
 use crate::address::Address;
-use crate::assembler::assemble_all;
-use crate::program::{Instruction, Program, Word};
+use crate::assembler::{assemble_all, AssembleError};
+use crate::disassembler::decode_instruction;
+use crate::program::{ImageSegment, Instruction, LoadedImage, Program, Word};
 use std::fmt::Debug;
 
 #[derive(Debug, PartialEq)]
@@ -17,8 +18,11 @@ pub enum MemoryError {
     InvalidData,
     InvalidHeap,
     InvalidStack,
+    OutOfMemory,     // Section larger than its address range
     SegmentFault,    // Invalid memory access
     ProtectionFault, // Invalid memory access
+    NoText,          // Program without any .text blocks
+    Assemble(Vec<AssembleError>),
 }
 
 pub type Result<T> = std::result::Result<T, MemoryError>;
@@ -58,15 +62,123 @@ impl ProtectionLevel {
     }
 }
 
-/// The size of a page in bytes.
+/// The byte order used for multi-byte values stored in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Endianness {
+    /// Least significant byte first (matches the x86 targets).
+    #[default]
+    Little,
+    /// Most significant byte first (classic MIPS).
+    Big,
+}
+
+impl Endianness {
+    pub fn halfword_from_bytes(&self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Endianness::Little => u16::from_le_bytes(bytes),
+            Endianness::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    pub fn halfword_to_bytes(&self, value: u16) -> [u8; 2] {
+        match self {
+            Endianness::Little => value.to_le_bytes(),
+            Endianness::Big => value.to_be_bytes(),
+        }
+    }
+
+    pub fn word_from_bytes(&self, bytes: [u8; 4]) -> Word {
+        match self {
+            Endianness::Little => Word::from_le_bytes(bytes),
+            Endianness::Big => Word::from_be_bytes(bytes),
+        }
+    }
+
+    pub fn word_to_bytes(&self, value: Word) -> [u8; 4] {
+        match self {
+            Endianness::Little => value.to_le_bytes(),
+            Endianness::Big => value.to_be_bytes(),
+        }
+    }
+}
+
+/// Counts of the memory accesses made through a [`Memory`], see [`Memory::stats`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemStats {
+    /// Number of reads per section name.
+    pub reads: BTreeMap<String, u64>,
+    /// Number of writes per section name.
+    pub writes: BTreeMap<String, u64>,
+    /// Number of reads per access size in bytes.
+    pub reads_by_size: BTreeMap<usize, u64>,
+    /// Number of writes per access size in bytes.
+    pub writes_by_size: BTreeMap<usize, u64>,
+    /// Number of instructions fetched for execution.
+    pub fetches: u64,
+}
+
+impl MemStats {
+    /// Show the counts as a table of reads and writes per section and per access size.
+    pub fn show(&self) -> String {
+        let mut output = format!("{:<10} {:>10} {:>10}\n", "section", "reads", "writes");
+        let mut sections = self
+            .reads
+            .keys()
+            .chain(self.writes.keys())
+            .collect::<Vec<_>>();
+        sections.sort();
+        sections.dedup();
+        for section in sections {
+            output.push_str(&format!(
+                "{:<10} {:>10} {:>10}\n",
+                section,
+                self.reads.get(section).copied().unwrap_or(0),
+                self.writes.get(section).copied().unwrap_or(0)
+            ));
+        }
+        let mut sizes = self
+            .reads_by_size
+            .keys()
+            .chain(self.writes_by_size.keys())
+            .collect::<Vec<_>>();
+        sizes.sort();
+        sizes.dedup();
+        for size in sizes {
+            output.push_str(&format!(
+                "{:<10} {:>10} {:>10}\n",
+                format!("{} bytes", size),
+                self.reads_by_size.get(size).copied().unwrap_or(0),
+                self.writes_by_size.get(size).copied().unwrap_or(0)
+            ));
+        }
+        output.push_str(&format!("{:<10} {:>10}\n", "fetches", self.fetches));
+        output
+    }
+}
+
+/// A range of bytes that differs between two memories, see [`Memory::diff`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemDiff {
+    pub address: Address,
+    /// The bytes in the memory compared against, e.g. a reference solution.
+    pub expected: Vec<u8>,
+    /// The bytes in the other memory.
+    pub actual: Vec<u8>,
+}
+
+/// The default size of a page in bytes, see [`Memory::load_with_page_size`].
 /// For MIPS32, the page size is 4KB (4096 bytes).
 pub const PAGE_SIZE: usize = 4096; // 4KB
 
 /// A page is a fixed-length contiguous block of virtual memory, described by a single entry in the page table.
 /// It is the smallest unit of data for memory management in a virtual memory system.
+#[derive(Clone)]
 struct Page {
-    data: [u8; PAGE_SIZE],
+    data: Box<[u8]>,
     protection: ProtectionLevel,
+    /// Whether each byte still holds the poison pattern, never written since the page was poisoned.
+    /// `None` when not poisoning.
+    uninitialized: Option<Box<[bool]>>,
 }
 
 impl Debug for Page {
@@ -75,6 +187,13 @@ impl Debug for Page {
             .field("size", &self.data.len())
             .field("nonZero", &self.data.iter().filter(|b| **b != 0).count())
             .field("protection", &self.protection)
+            .field(
+                "uninitialized",
+                &self
+                    .uninitialized
+                    .as_ref()
+                    .map(|bytes| bytes.iter().filter(|b| **b).count()),
+            )
             .finish()
     }
 }
@@ -83,26 +202,46 @@ impl Debug for Page {
 /// Virtual addresses are used by the CPU, and physical addresses are used by the hardware.
 /// The page table is used to translate virtual addresses to physical addresses.
 /// The page table is stored in memory and is managed by the operating system.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone)]
 struct PageTable {
-    pages: HashMap<Address, Page>,
+    pages: BTreeMap<Address, Page>,
+    /// The size of every page in bytes, a power of two.
+    page_size: usize,
+    /// Byte new pages are filled with, zero unless poisoning.
+    poison: Option<u8>,
 }
 
 impl PageTable {
+    fn new(page_size: usize) -> Self {
+        assert!(
+            page_size.is_power_of_two() && (4..=1 << 31).contains(&page_size),
+            "Page size must be a power of two between 4 bytes and 2 GiB: {}",
+            page_size
+        );
+        PageTable {
+            pages: BTreeMap::new(),
+            page_size,
+            poison: None,
+        }
+    }
+
     /// Insert a page into the page table.
     pub fn insert_page(&mut self, page_number: u32, protection: ProtectionLevel) {
         self.pages.insert(
-            Address::from_page_number(page_number),
+            Address::from_page_number(page_number, self.page_size),
             Page {
-                data: [0; PAGE_SIZE],
+                data: vec![self.poison.unwrap_or(0); self.page_size].into_boxed_slice(),
                 protection,
+                uninitialized: self
+                    .poison
+                    .map(|_| vec![true; self.page_size].into_boxed_slice()),
             },
         );
     }
 
     pub fn ensure_pages(&mut self, start_page: u32, end_page: u32, protection: ProtectionLevel) {
         for page_number in start_page..=end_page {
-            let page_address = Address::from_page_number(page_number);
+            let page_address = Address::from_page_number(page_number, self.page_size);
             if !self.pages.contains_key(&page_address) {
                 self.insert_page(page_number, protection.clone());
             }
@@ -119,7 +258,10 @@ impl PageTable {
     }
 
     pub fn set_protection(&mut self, page_number: u32, protection: ProtectionLevel) {
-        if let Some(page) = self.pages.get_mut(&Address::from_page_number(page_number)) {
+        if let Some(page) = self
+            .pages
+            .get_mut(&Address::from_page_number(page_number, self.page_size))
+        {
             page.protection = protection;
         }
     }
@@ -132,19 +274,22 @@ impl PageTable {
 
     /// Get a mutable reference to the page for a given page number.
     pub fn get_page_mut(&mut self, page_number: u32) -> Option<&mut Page> {
-        self.pages.get_mut(&Address::from_page_number(page_number))
+        self.pages
+            .get_mut(&Address::from_page_number(page_number, self.page_size))
     }
 
     /// Get an immutable reference to the page for a given page number.
     pub fn get_page(&self, page_number: u32) -> Option<&Page> {
-        self.pages.get(&Address::from_page_number(page_number))
+        self.pages
+            .get(&Address::from_page_number(page_number, self.page_size))
     }
 
     /// Write data to one or more pages in the page table.
     /// Throw an error if the page is not writable or if the page is not found.
     pub fn write_bytes(&mut self, address: Address, bytes: &[u8]) -> Result<()> {
-        let mut page_number = address.page_number();
-        let mut offset = address.page_offset();
+        let page_size = self.page_size;
+        let mut page_number = address.page_number(page_size);
+        let mut offset = address.page_offset(page_size);
         let mut left = bytes.len();
         while left > 0 {
             let page = self
@@ -160,9 +305,13 @@ impl PageTable {
                 return Err(MemoryError::ProtectionFault);
             }
             let page_offset = offset as usize;
-            let write_size = left.min(PAGE_SIZE - page_offset);
+            let write_size = left.min(page_size - page_offset);
+            let written = bytes.len() - left;
             page.data[page_offset..(page_offset + write_size)]
-                .copy_from_slice(&bytes[..write_size]);
+                .copy_from_slice(&bytes[written..written + write_size]);
+            if let Some(uninitialized) = &mut page.uninitialized {
+                uninitialized[page_offset..(page_offset + write_size)].fill(false);
+            }
             left -= write_size;
             offset = 0;
             page_number += 1;
@@ -170,18 +319,35 @@ impl PageTable {
         Ok(())
     }
 
-    /// Read data from one or more pages in the page table.
+    /// Reverse the `size` bytes at `address` in place, regardless of the page protections.
+    fn reverse_bytes(&mut self, address: Address, size: usize) {
+        for i in 0..size / 2 {
+            let (low, high) = (address + i, address + (size - 1 - i));
+            let (Some(&low_byte), Some(&high_byte)) = (self.byte(low), self.byte(high)) else {
+                continue;
+            };
+            let page_size = self.page_size;
+            for (address, byte) in [(low, high_byte), (high, low_byte)] {
+                if let Some(page) = self.get_page_mut(address.page_number(page_size)) {
+                    page.data[address.page_offset(page_size) as usize] = byte;
+                }
+            }
+        }
+    }
+
+    fn byte(&self, address: Address) -> Option<&u8> {
+        let page = self.get_page(address.page_number(self.page_size))?;
+        page.data.get(address.page_offset(self.page_size) as usize)
+    }
+
+    /// Read data from one or more pages in the page table, filling `buf`.
     /// Throw an error if the page is not readable or if the page is not found.
-    /// Return the data read from the page.
-    ///
-    /// # Returns
-    /// A vector of slices of the data read from the page.
-    pub fn read_bytes(&self, address: Address, size: usize) -> Result<Vec<&[u8]>> {
-        let page_number = address.page_number();
-        let offset = address.page_offset();
-        let mut data = Vec::new();
-        let mut left = size;
-        while left > 0 {
+    pub fn read_into(&self, address: Address, buf: &mut [u8]) -> Result<()> {
+        let page_size = self.page_size;
+        let mut page_number = address.page_number(page_size);
+        let mut offset = address.page_offset(page_size);
+        let mut read = 0;
+        while read < buf.len() {
             let page = self
                 .get_page(page_number)
                 .ok_or(MemoryError::SegmentFault)?;
@@ -189,11 +355,25 @@ impl PageTable {
                 return Err(MemoryError::ProtectionFault);
             }
             let page_offset = offset as usize;
-            let read_size = left.min(PAGE_SIZE - page_offset);
-            data.push(&page.data[page_offset..(page_offset + read_size)]);
-            left -= read_size;
+            let read_size = (buf.len() - read).min(page_size - page_offset);
+            buf[read..read + read_size]
+                .copy_from_slice(&page.data[page_offset..(page_offset + read_size)]);
+            read += read_size;
+            offset = 0;
+            page_number += 1;
         }
-        Ok(data)
+        Ok(())
+    }
+
+    /// Whether any of the `size` bytes at `address` still holds the poison pattern.
+    /// Bytes of missing pages are not reported.
+    fn is_uninitialized(&self, address: Address, size: usize) -> bool {
+        (0..size as u32).any(|i| {
+            let address = address + i;
+            self.get_page(address.page_number(self.page_size))
+                .and_then(|page| page.uninitialized.as_ref())
+                .is_some_and(|bytes| bytes[address.page_offset(self.page_size) as usize])
+        })
     }
 }
 
@@ -202,7 +382,7 @@ type WriteHandler = fn(Address, u8);
 
 /// Memory paging is a memory management scheme that eliminates the need for
 /// contiguous allocation of physical memory.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct MemorySegment {
     #[allow(dead_code)]
     name: String,
@@ -212,10 +392,22 @@ pub struct MemorySegment {
     write_handler: Option<WriteHandler>,
 }
 
-pub type LabelMap = HashMap<String, Address>;
+// Constants from: https://wilkinsonj.people.charleston.edu/mem-map.html
+pub(crate) const TEXT_START: Address = Address::new(0x0040_0000);
+pub(crate) const TEXT_MAX: Address = Address::new(0x0FFF_FFFF);
+pub(crate) const ANY_DATA_START: Address = Address::new(0x1001_0000);
+pub(crate) const ANY_DATA_END: Address = Address::new(0x7FFF_FFFF);
+/// The default [`Memory::set_stack_limit`], as with `ulimit -s`.
+pub const DEFAULT_STACK_LIMIT: usize = 8 * 1024 * 1024;
+const MMIO_START: Address = Address::new(0xFFFF_0000);
+const MMIO_MAX: Address = Address::new(0xFFFF_FFFF);
+
+/// Labels by name, iterated in name order.
+pub type LabelMap = BTreeMap<String, Address>;
 
 /// The memory of the MIPS VM is divided into several sections:
 /// - `.text` section: read-only and executable (code) from the program's instructions.
+/// - `.rdata` section: read-only and contains constant data.
 /// - `.data` section: read-write and typically contains global variables from the initialized data.
 /// - `.bss` section: read-write and is used for uninitialized data.
 /// - `.heap` section: read-write and is used for dynamic memory allocation from the dynamically allocated memory.
@@ -231,14 +423,14 @@ pub type LabelMap = HashMap<String, Address>;
 /// - [MIPS memory layout](https://www.it.uu.se/education/course/homepage/os/vt18/module-0/mips-and-mars/mips-memory-layout/)
 /// - [Memory Management Unit](https://en.wikipedia.org/wiki/Memory_management_unit)
 /// - [Memory Management](https://en.wikipedia.org/wiki/Memory_management)
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Memory {
     page_table: PageTable,
     /// Labels with their names as the key and their address as the value.
     /// This is used to store the mapping of all address of labels in the original program.
     labels: LabelMap,
     /// Sections of memory with their start address as the key.
-    sections: HashMap<Address, MemorySegment>,
+    sections: BTreeMap<Address, MemorySegment>,
     /// Text section: contains the program's instructions
     /// This section is read-only and executable (code).
     text: Address,
@@ -246,6 +438,12 @@ pub struct Memory {
     /// Data section: contains initialized data
     /// This section is read-write and typically contains global variables.
     data: Option<Address>,
+    /// Rdata section: contains read-only initialized data
+    /// This section is read-only, placed on the pages after `.text`.
+    rdata: Option<Address>,
+    /// Bss section: contains uninitialized data
+    /// This section is read-write and zero-initialized, placed between `.data` and the heap.
+    bss: Option<Address>,
     /// Heap section: contains dynamically allocated memory
     /// This section is read-write and is used for dynamic memory allocation.
     /// The section is **allocated by the operating system at runtime**.
@@ -262,38 +460,138 @@ pub struct Memory {
     /// - Is used for function calls, local variables, and bookkeeping information.
     /// - Grows downwards, starting from a high address and growing towards lower addresses.
     stack: Address,
+    /// Byte order of halfword, word and address accesses.
+    /// The `.word` data is loaded little-endian and reordered when the endianness changes.
+    endianness: Endianness,
+    /// Start address and size of each `.data` symbol, in address order.
+    data_symbols: Vec<(Address, usize)>,
+    /// Start address and size of each `.word` directive in `.data` and `.rdata`.
+    data_words: Vec<(Address, usize)>,
+    /// Fail accesses to `.data` that are not within a single symbol.
+    strict_bounds: bool,
+    /// Allow writes into the `.text` section, see [`Memory::set_text_writable`].
+    text_writable: bool,
+    /// Alignment in bytes of the addresses returned by [`Memory::heap_allocate`].
+    heap_alignment: usize,
+    /// How far in bytes the stack may grow below its original top, see [`Memory::set_stack_limit`].
+    stack_limit: usize,
+    /// Record reads of poisoned bytes, see [`Memory::set_poison`].
+    uninitialized_check: bool,
+    /// Addresses of the reads of poisoned bytes so far.
+    uninitialized_reads: Vec<Address>,
+    /// Counts of the accesses made so far.
+    stats: MemStats,
+    /// The previous contents of every write while journaling, see [`Memory::start_journal`].
+    journal: Option<Vec<(Address, Vec<u8>)>>,
 }
 
 impl Memory {
     /// Load the program into memory.
     /// The program memory is loaded  with the following sections:
     /// - `.text` section: read-only and executable (code) from the program's instructions. (**Lowest addresses**)
+    /// - `.rdata` section: read-only constant data, on the pages after `.text`.
     /// - `.data` section: read-write and typically contains global variables from the initialized data. (**Slighly higher addresses**)
     /// - `.bss` section: read-write and is used for uninitialized data. (**Higher addresses**)
     /// - `.heap` section: read-write and is used for dynamic memory allocation from the dynamically allocated memory. (**Second-to-highest addresses**)
     /// - `.stack` section: read-write and is used for function calls and local variables from the stack. (**Highest addresses**)
     pub fn load(program: Program, mmio: Vec<MemorySegment>) -> Self {
-        let mut page_table = PageTable::default();
+        Self::load_with_page_size(program, mmio, PAGE_SIZE)
+    }
+
+    /// Load the program into memory like [`Memory::load`], failing instead of panicking,
+    /// see [`Memory::try_load_with_page_size`].
+    pub fn try_load(program: Program, mmio: Vec<MemorySegment>) -> Result<Self> {
+        Self::try_load_with_page_size(program, mmio, PAGE_SIZE)
+    }
+
+    /// The maximum size of the `.text` section in bytes.
+    pub fn text_capacity() -> usize {
+        (TEXT_MAX - TEXT_START) as usize
+    }
+
+    /// The size in bytes of a `.text` section of `instructions` instructions,
+    /// if it fits between `base` and the end of the `.text` address range.
+    fn text_size(base: Address, instructions: usize) -> Result<usize> {
+        instructions
+            .checked_mul(Instruction::size())
+            .filter(|size| *size <= (TEXT_MAX - base) as usize)
+            .ok_or(MemoryError::OutOfMemory)
+    }
+
+    /// The end of `size` bytes of `.data` and `.bss` placed at `base`,
+    /// if they end below the stack, which may grow down [`DEFAULT_STACK_LIMIT`] bytes.
+    fn data_end(base: Address, size: usize) -> Result<Address> {
+        let limit = ANY_DATA_END.unwrap() as usize - DEFAULT_STACK_LIMIT;
+        (base.unwrap() as usize)
+            .checked_add(size)
+            .filter(|end| *end <= limit)
+            .map(|end| Address::new(end as u32))
+            .ok_or(MemoryError::OutOfMemory)
+    }
+
+    /// Load the program into memory divided into pages of `page_size` bytes instead of [`PAGE_SIZE`],
+    /// e.g. 64KB pages for fewer page table entries.
+    ///
+    /// The page size must be a power of two, small enough that sections with different protections
+    /// (such as `.text` and `.data`) do not share a page.
+    pub fn load_with_page_size(
+        program: Program,
+        mmio: Vec<MemorySegment>,
+        page_size: usize,
+    ) -> Self {
+        Self::try_load_with_page_size(program, mmio, page_size)
+            .unwrap_or_else(|err| panic!("Failed to load the program: {:?}", err))
+    }
+
+    /// Load the program into memory divided into pages of `page_size` bytes like [`Memory::load_with_page_size`].
+    ///
+    /// Fails with:
+    /// - [`MemoryError::OutOfMemory`] if a section does not fit in its address range,
+    ///   e.g. the instructions do not fit in the `.text` section.
+    /// - [`MemoryError::NoText`] if the program has no `.text` blocks.
+    /// - [`MemoryError::Assemble`] if an instruction cannot be encoded, e.g. a jump to an undefined label.
+    /// - [`MemoryError::InvalidAddress`] if an MMIO section lies outside of the MMIO address range.
+    pub fn try_load_with_page_size(
+        mut program: Program,
+        mmio: Vec<MemorySegment>,
+        page_size: usize,
+    ) -> Result<Self> {
+        // Check the size before allocating any pages, as the text section would overflow into .data
+        let text_start_address = program.text_section.base.unwrap_or(TEXT_START);
+        let text_size = Self::text_size(
+            text_start_address,
+            program.text_section.instructions().len(),
+        )?;
+        let mut page_table = PageTable::new(page_size);
         let mut labels: LabelMap = LabelMap::new();
-        let mut sections = HashMap::new();
+        let mut sections = BTreeMap::new();
+        let mut data_symbols = Vec::new();
+        let mut data_words = Vec::new();
 
-        // Constants from: https://wilkinsonj.people.charleston.edu/mem-map.html
-        const TEXT_START: Address = Address::new(0x0040_0000);
-        const TEXT_MAX: Address = Address::new(0x0FFF_FFFF);
-        const ANY_DATA_START: Address = Address::new(0x1001_0000);
-        const ANY_DATA_END: Address = Address::new(0x7FFF_FFFF);
-        const MMIO_START: Address = Address::new(0xFFFF_0000);
-        const MMIO_MAX: Address = Address::new(0xFFFF_FFFF);
+        let reserved = std::mem::take(&mut program.data_section.uninitialized);
+        let read_only = std::mem::take(&mut program.data_section.read_only);
 
         // =========== .data section =========== //
-        let mut address = ANY_DATA_START;
+        let mut address = program.data_section.base.unwrap_or(ANY_DATA_START);
         let data_start_address = address;
+        let data_size = program
+            .data_section
+            .initialized
+            .iter()
+            .map(|data| data.data.len());
+        // Leave room for the alignment of every reserved symbol
+        let reserved_size = reserved.iter().map(|data| data.size.saturating_add(3));
+        Self::data_end(data_start_address, data_size.chain(reserved_size).sum())?;
         let mut data_end_address = data_start_address;
         let data = if !program.data_section.empty() {
             let data_initialized = program.data_section.initialized_static_move();
             let mut data_label_address: Address = data_start_address;
             for data in &data_initialized {
                 labels.insert(data.label.clone(), data_label_address);
+                data_symbols.push((data_label_address, data.data.len()));
+                if data.element_size == size_of::<Word>() {
+                    data_words.push((data_label_address, data.data.len()));
+                }
                 data_label_address += data.data.len();
             }
             let data_raw_initialized: Vec<u8> = data_initialized
@@ -302,7 +600,6 @@ impl Memory {
                 .collect();
             address += data_raw_initialized.len();
             data_end_address = address;
-            assert!(data_raw_initialized.len() == (data_end_address - data_start_address) as usize);
             let data = MemorySegment {
                 name: ".data".to_string(),
                 start_address: data_start_address,
@@ -311,13 +608,11 @@ impl Memory {
                 write_handler: None,
             };
             page_table.ensure_pages(
-                data.start_address.page_number(),
-                data.end_address.page_number(),
+                data.start_address.page_number(page_size),
+                data.end_address.page_number(page_size),
                 ProtectionLevel::ReadWrite,
             );
-            page_table
-                .write_bytes(data.start_address, &data_raw_initialized)
-                .unwrap();
+            page_table.write_bytes(data.start_address, &data_raw_initialized)?;
 
             log::trace!("Data section: {:?}", &data);
             sections.insert(data_start_address, data);
@@ -326,15 +621,46 @@ impl Memory {
             None
         };
 
+        // =========== .bss section =========== //
+        // Every reserved symbol is word-aligned, so it can hold words
+        let bss_start_address = data_end_address.align_up(4);
+        let bss = if !reserved.is_empty() {
+            let mut bss_label_address = bss_start_address;
+            for data in &reserved {
+                bss_label_address = bss_label_address.align_up(4);
+                labels.insert(data.label.clone(), bss_label_address);
+                bss_label_address += data.size;
+            }
+            let bss = MemorySegment {
+                name: ".bss".to_string(),
+                start_address: bss_start_address,
+                end_address: bss_label_address,
+                read_handler: None,
+                write_handler: None,
+            };
+            // New pages are zeroed
+            page_table.ensure_pages(
+                bss.start_address.page_number(page_size),
+                bss.end_address.page_number(page_size),
+                ProtectionLevel::ReadWrite,
+            );
+            log::trace!("Bss section: {:?}", &bss);
+            address = bss.end_address;
+            sections.insert(bss_start_address, bss);
+            Some(bss_start_address)
+        } else {
+            None
+        };
+        let bss_end_address = address;
+
         // =========== .text section =========== //
-        // Initialize the text section after .data
+        // Initialize the text section after .data and .bss
         // because of label address dependencies
         // during instruction encoding/assembly.
-        address = TEXT_START;
+        address = text_start_address;
         if program.text_section.blocks.is_empty() {
-            panic!("Invalid program: no .text code blocks found");
+            return Err(MemoryError::NoText);
         }
-        let text_start_address = address;
         let mut text_label_address: Address = text_start_address;
         for block in &program.text_section.blocks {
             if !block.label.is_empty() {
@@ -343,12 +669,8 @@ impl Memory {
             text_label_address += block.instructions.len() * Instruction::size();
         }
         let text_instructions = program.text_section.instructions_move();
-        address += text_instructions.len() * Instruction::size();
+        address += text_size;
         let text_end_address = address;
-        assert!(
-            text_instructions.len()
-                == ((text_end_address - text_start_address) / Instruction::size() as u32) as usize
-        );
         let text = MemorySegment {
             name: ".text".to_string(),
             start_address: text_start_address,
@@ -357,35 +679,210 @@ impl Memory {
             write_handler: None,
         };
         page_table.ensure_pages(
-            text.start_address.page_number(),
-            text.end_address.page_number(),
+            text.start_address.page_number(page_size),
+            text.end_address.page_number(page_size),
             ProtectionLevel::Write,
         );
+
+        // =========== .rdata section =========== //
+        // Initialize the rdata section on the pages after .text,
+        // before assembling as instructions may refer to its labels.
+        let rdata = if !read_only.is_empty() {
+            let rdata_start_address = text_end_address.align_up(page_size as u32);
+            let mut rdata_label_address = rdata_start_address;
+            for data in &read_only {
+                labels.insert(data.label.clone(), rdata_label_address);
+                if data.element_size == size_of::<Word>() {
+                    data_words.push((rdata_label_address, data.data.len()));
+                }
+                rdata_label_address += data.data.len();
+            }
+            if rdata_label_address > TEXT_MAX {
+                return Err(MemoryError::OutOfMemory);
+            }
+            let rdata = MemorySegment {
+                name: ".rdata".to_string(),
+                start_address: rdata_start_address,
+                end_address: rdata_label_address,
+                read_handler: None,
+                write_handler: None,
+            };
+            page_table.ensure_pages(
+                rdata.start_address.page_number(page_size),
+                rdata.end_address.page_number(page_size),
+                ProtectionLevel::Write,
+            );
+            let rdata_raw: Vec<u8> = read_only.into_iter().flat_map(|rd| rd.data).collect();
+            page_table.write_bytes(rdata.start_address, &rdata_raw)?;
+            log::trace!("Rdata section: {:?}", &rdata);
+            sections.insert(rdata_start_address, rdata);
+            Some(rdata_start_address)
+        } else {
+            None
+        };
+
         // Assemble instructions into raw machine code bytes
-        let encoded_instructions = assemble_all(&text_instructions, &labels);
+        let encoded_instructions = assemble_all(&text_instructions, text_start_address, &labels)
+            .map_err(MemoryError::Assemble)?;
         let raw_instructions = encoded_instructions
             .into_iter()
             .flat_map(|x| x.to_le_bytes())
             .collect::<Vec<u8>>();
-        assert!(
-            raw_instructions.len() == (text_end_address - text_start_address) as usize,
-            "Invalid instruction size: {}, expected {}",
-            raw_instructions.len(),
-            (text_end_address - text_start_address) as usize
-        );
-        page_table
-            .write_bytes(text.start_address, &raw_instructions)
-            .unwrap();
+        page_table.write_bytes(text.start_address, &raw_instructions)?;
         page_table.set_protections(
-            text.start_address.page_number(),
-            text.end_address.page_number(),
+            text.start_address.page_number(page_size),
+            text.end_address.page_number(page_size),
             ProtectionLevel::ReadExecute,
         );
-        assert!(address <= TEXT_MAX, "Out of memory: text section");
+        // Protect .rdata after .text, as the last page of .text may be the first of .rdata
+        if let Some(rdata) = rdata.and_then(|rdata| sections.get(&rdata)) {
+            page_table.set_protections(
+                rdata.start_address.page_number(page_size),
+                rdata.end_address.page_number(page_size),
+                ProtectionLevel::Read,
+            );
+        }
         sections.insert(text.start_address, text);
 
+        // Begin the heap at the end of the .data and .bss sections
+        let heap_start_address = bss_end_address;
+        let stack_start_address =
+            Self::load_runtime(&mut page_table, &mut sections, heap_start_address, mmio)?;
+
+        Ok(Memory {
+            page_table,
+            labels,
+            sections,
+            text: text_start_address,
+            text_instructions,
+            data,
+            rdata,
+            bss,
+            heap: heap_start_address,
+            stack: stack_start_address,
+            endianness: Endianness::default(),
+            data_symbols,
+            data_words,
+            strict_bounds: false,
+            text_writable: false,
+            heap_alignment: 4,
+            stack_limit: DEFAULT_STACK_LIMIT,
+            uninitialized_check: false,
+            uninitialized_reads: Vec::new(),
+            stats: MemStats::default(),
+            journal: None,
+        })
+    }
+
+    /// Load a pre-assembled image, see [`crate::program::from_elf`],
+    /// placing every segment at its virtual address.
+    /// The executable segment becomes the `.text` section and the first other segment the `.data` section.
+    /// The heap begins at the first page after the highest segment.
+    ///
+    /// # Panics
+    ///
+    /// If the image has no executable segment, a segment does not fit in the address space,
+    /// or an MMIO section lies outside of the MMIO address range.
+    /// Images returned by [`crate::program::from_elf`] are always valid.
+    pub fn load_image(image: LoadedImage, mmio: Vec<MemorySegment>) -> Self {
+        let page_size = PAGE_SIZE;
+        let mut page_table = PageTable::new(page_size);
+        let mut sections = BTreeMap::new();
+        let mut text = None;
+        let mut data = None;
+        let mut end_address = Address::new(0);
+        // Write all segments before protecting them, as segments may share a page
+        let segment_end = |segment: &ImageSegment| {
+            let end = u32::try_from(segment.data.len())
+                .ok()
+                .and_then(|size| segment.address.unwrap().checked_add(size));
+            Address::new(end.expect("Invalid image: segment out of the address space"))
+        };
+        for segment in &image.segments {
+            let end = segment_end(segment);
+            page_table.ensure_pages(
+                segment.address.page_number(page_size),
+                end.page_number(page_size),
+                ProtectionLevel::Write,
+            );
+            page_table
+                .write_bytes(segment.address, &segment.data)
+                .unwrap();
+        }
+        for segment in image.segments {
+            let end = segment_end(&segment);
+            let (name, protection) = if segment.executable {
+                text = Some(segment.address);
+                (".text", ProtectionLevel::ReadExecute)
+            } else {
+                data.get_or_insert(segment.address);
+                let protection = if segment.writable {
+                    ProtectionLevel::ReadWrite
+                } else {
+                    ProtectionLevel::Read
+                };
+                (".data", protection)
+            };
+            page_table.set_protections(
+                segment.address.page_number(page_size),
+                end.page_number(page_size),
+                protection,
+            );
+            let section = MemorySegment {
+                name: name.to_string(),
+                start_address: segment.address,
+                end_address: end,
+                read_handler: None,
+                write_handler: None,
+            };
+            log::trace!("Image section: {:?}", &section);
+            sections.insert(segment.address, section);
+            end_address = end_address.max(end);
+        }
+        let text = text.expect("Invalid image: no executable segment");
+
+        let heap_start_address =
+            Address::from_page_number(end_address.page_number(page_size) + 1, page_size);
+        let stack_start_address =
+            Self::load_runtime(&mut page_table, &mut sections, heap_start_address, mmio)
+                .expect("Invalid MMIO section range");
+
+        Memory {
+            page_table,
+            labels: image.labels,
+            sections,
+            text,
+            text_instructions: image.text_instructions,
+            data,
+            rdata: None,
+            bss: None,
+            heap: heap_start_address,
+            stack: stack_start_address,
+            endianness: Endianness::default(),
+            data_symbols: Vec::new(),
+            data_words: Vec::new(),
+            strict_bounds: false,
+            text_writable: false,
+            heap_alignment: 4,
+            stack_limit: DEFAULT_STACK_LIMIT,
+            uninitialized_check: false,
+            uninitialized_reads: Vec::new(),
+            stats: MemStats::default(),
+            journal: None,
+        }
+    }
+
+    /// Add the `.heap` section beginning at `heap_start_address`, the `.stack` section
+    /// and the MMIO sections, returning the start address of the stack.
+    /// Fails with [`MemoryError::InvalidAddress`] if an MMIO section lies outside of the MMIO address range.
+    fn load_runtime(
+        page_table: &mut PageTable,
+        sections: &mut BTreeMap<Address, MemorySegment>,
+        heap_start_address: Address,
+        mmio: Vec<MemorySegment>,
+    ) -> Result<Address> {
+        let page_size = page_table.page_size;
         // =========== .heap section =========== //
-        let heap_start_address = data_end_address; // Begin at the end of the .data section
         let heap = MemorySegment {
             name: ".heap".to_string(),
             start_address: heap_start_address,
@@ -394,8 +891,8 @@ impl Memory {
             write_handler: None,
         };
         page_table.ensure_pages(
-            heap.start_address.page_number(),
-            heap.end_address.page_number(),
+            heap.start_address.page_number(page_size),
+            heap.end_address.page_number(page_size),
             ProtectionLevel::ReadWrite,
         );
         log::trace!("Heap section: {:?}", &heap);
@@ -412,8 +909,8 @@ impl Memory {
             write_handler: None,
         };
         page_table.ensure_pages(
-            stack.start_address.page_number(),
-            stack.end_address.page_number(),
+            stack.start_address.page_number(page_size),
+            stack.end_address.page_number(page_size),
             ProtectionLevel::ReadWrite,
         );
         log::trace!("Stack section: {:?}", &stack);
@@ -422,11 +919,9 @@ impl Memory {
         // =========== other sections =========== //
         // Memory-mapped I/O (MMIO) devices
         for mmio in mmio {
-            assert!(
-                mmio.start_address >= MMIO_START && mmio.end_address <= MMIO_MAX,
-                "Invalid MMIO section range: {:?}",
-                &mmio
-            );
+            if mmio.start_address < MMIO_START || mmio.end_address > MMIO_MAX {
+                return Err(MemoryError::InvalidAddress);
+            }
             let mmio = MemorySegment {
                 name: "MMIO".to_string(),
                 start_address: mmio.start_address,
@@ -435,24 +930,163 @@ impl Memory {
                 write_handler: None,
             };
             page_table.ensure_pages(
-                mmio.start_address.page_number(),
-                mmio.end_address.page_number(),
+                mmio.start_address.page_number(page_size),
+                mmio.end_address.page_number(page_size),
                 ProtectionLevel::ReadWrite,
             );
             log::trace!("MMIO section: {:?}", &mmio);
             sections.insert(mmio.start_address, mmio);
         }
 
-        Memory {
-            page_table,
-            labels,
-            sections,
-            text: text_start_address,
-            text_instructions,
-            data,
-            heap: heap_start_address,
-            stack: stack_start_address,
+        Ok(stack_start_address)
+    }
+
+    /// Restore the contents and section bounds of `loaded`, a copy of this memory taken earlier,
+    /// undoing all writes, heap allocations and stack growth since, and clear the statistics.
+    /// The endianness, bounds checking and text protection settings are kept.
+    pub fn restore(&mut self, loaded: &Memory) {
+        self.page_table = loaded.page_table.clone();
+        self.sections = loaded.sections.clone();
+        self.text_instructions = loaded.text_instructions.clone();
+        self.set_text_writable(self.text_writable);
+        if self.endianness != loaded.endianness {
+            self.swap_data_words();
         }
+        self.stats = MemStats::default();
+        self.uninitialized_reads.clear();
+    }
+
+    /// Counts of the reads, writes and instruction fetches made so far.
+    pub fn stats(&self) -> &MemStats {
+        &self.stats
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    /// Set the byte order of halfword, word and address accesses (little-endian by default),
+    /// reordering the bytes of the `.word` data so that it keeps its values.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        if endianness != self.endianness {
+            self.endianness = endianness;
+            self.swap_data_words();
+        }
+    }
+
+    /// Reverse the bytes of every word of the `.word` data, after the endianness changed.
+    fn swap_data_words(&mut self) {
+        for (start, size) in &self.data_words {
+            for offset in (0..*size).step_by(size_of::<Word>()) {
+                self.page_table
+                    .reverse_bytes(*start + offset, size_of::<Word>());
+            }
+        }
+    }
+
+    /// Fail reads and writes to `.data` that do not lie within a single symbol
+    /// with [`MemoryError::OutOfBounds`], e.g. reading past the end of an array into the next symbol.
+    pub fn set_strict_bounds(&mut self, strict: bool) {
+        self.strict_bounds = strict;
+    }
+
+    /// Align the addresses returned by [`Memory::heap_allocate`] to `alignment` bytes (4 by default),
+    /// e.g. 8 for doubles.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `alignment` is not a power of two.
+    pub fn set_heap_alignment(&mut self, alignment: usize) {
+        assert!(
+            alignment.is_power_of_two(),
+            "Heap alignment must be a power of two: {}",
+            alignment
+        );
+        self.heap_alignment = alignment;
+    }
+
+    /// Limit how far in bytes the stack may grow below its original top
+    /// ([`DEFAULT_STACK_LIMIT`] by default). Accesses further down fail with
+    /// [`MemoryError::InvalidStack`] instead of growing the stack.
+    pub fn set_stack_limit(&mut self, limit: usize) {
+        self.stack_limit = limit;
+    }
+
+    /// Fill the memory the program has not initialized with `pattern` instead of zeros,
+    /// e.g. `0xCC`, to expose reads of uninitialized memory (disabled with `None`).
+    /// This covers the `.bss`, heap and stack sections and every page allocated later,
+    /// so it should be set right after loading, before the program writes to them.
+    pub fn set_poison(&mut self, pattern: Option<u8>) {
+        self.page_table.poison = pattern;
+        let runtime = [self.bss, Some(self.heap), Some(self.stack)];
+        let loaded = self
+            .sections
+            .values()
+            .filter(|section| !runtime.contains(&Some(section.start_address)))
+            .map(|section| (section.start_address, section.end_address))
+            .collect::<Vec<_>>();
+        for (start, page) in &mut self.page_table.pages {
+            page.uninitialized = pattern.map(|pattern| {
+                (0..page.data.len())
+                    .map(|offset| {
+                        let address = *start + offset;
+                        let fresh = !loaded
+                            .iter()
+                            .any(|(start, end)| *start <= address && address < *end);
+                        if fresh {
+                            page.data[offset] = pattern;
+                        }
+                        fresh
+                    })
+                    .collect()
+            });
+        }
+    }
+
+    /// Record reads of poisoned bytes the program never wrote in [`Memory::uninitialized_reads`],
+    /// when poisoning with [`Memory::set_poison`] (disabled by default).
+    pub fn set_uninitialized_check(&mut self, check: bool) {
+        self.uninitialized_check = check;
+    }
+
+    /// Addresses of the reads of uninitialized memory so far, see [`Memory::set_uninitialized_check`].
+    pub fn uninitialized_reads(&self) -> &[Address] {
+        &self.uninitialized_reads
+    }
+
+    /// The number of bytes that can be accessed from `address`:
+    /// up to the end of its section, or of its `.data` symbol with strict bounds.
+    fn access_limit(&self, address: Address) -> Result<usize> {
+        let section = self.find_section(address)?;
+        let mut end = section.end_address;
+        if self.strict_bounds && Some(section.start_address) == self.data {
+            let (start, size) = self
+                .data_symbols
+                .iter()
+                .find(|(start, size)| *start <= address && address < *start + *size)
+                .ok_or(MemoryError::OutOfBounds)?;
+            end = *start + *size;
+        }
+        Ok((end - address) as usize)
+    }
+
+    /// All sections, in address order.
+    pub fn sections(&self) -> impl Iterator<Item = &MemorySegment> {
+        self.sections.values()
+    }
+
+    /// The labels within `section`, in address order.
+    pub fn labels_in(&self, section: &MemorySegment) -> Vec<(&String, Address)> {
+        let mut labels = self
+            .labels
+            .iter()
+            .filter(|(_, &address)| {
+                section.start_address <= address && address < section.end_address
+            })
+            .map(|(label, &address)| (label, address))
+            .collect::<Vec<_>>();
+        labels.sort_by_key(|&(label, address)| (address, label));
+        labels
     }
 
     pub fn add_section(&mut self, section: MemorySegment) {
@@ -473,10 +1107,37 @@ impl Memory {
             .ok_or(MemoryError::InvalidAddress)
     }
 
+    /// The address and size in bytes of the `.data` symbol declared with `label`.
+    pub fn data_symbol(&self, label: &str) -> Option<(Address, usize)> {
+        let address = *self.labels.get(label)?;
+        self.data_symbols
+            .iter()
+            .find(|(start, _)| *start == address)
+            .copied()
+    }
+
+    /// The label, address and size in bytes of the `.data` symbol containing `address`.
+    pub fn data_symbol_at(&self, address: Address) -> Option<(&String, Address, usize)> {
+        let (start, size) = self
+            .data_symbols
+            .iter()
+            .find(|(start, size)| *start <= address && address < *start + *size)?;
+        let label = self.label_at_address(*start).ok()?;
+        Some((label, *start, *size))
+    }
+
+    /// Find the section containing `address`.
+    /// Adjacent sections share a boundary address, e.g. the end of `.data` is the start of the heap,
+    /// so a section starting at `address` is preferred over one ending there.
     pub fn find_section(&self, address: Address) -> Result<&MemorySegment> {
         self.sections
             .values()
-            .find(|section| section.start_address <= address && address <= section.end_address)
+            .find(|section| section.start_address <= address && address < section.end_address)
+            .or_else(|| {
+                self.sections.values().find(|section| {
+                    section.start_address <= address && address <= section.end_address
+                })
+            })
             .ok_or(MemoryError::InvalidSection)
     }
 
@@ -520,28 +1181,53 @@ impl Memory {
 
     /// Read from a memory address location and return the data of the specified size
     pub fn read(&mut self, address: Address, size: usize) -> Result<Vec<u8>> {
-        let section = self.find_section(address)?;
-        if address + size > section.end_address {
+        // Check the size before allocating, as it may come from a register
+        if size > self.access_limit(address)? {
+            return Err(MemoryError::OutOfBounds);
+        }
+        let mut data = vec![0; size];
+        self.read_into(address, &mut data)?;
+        Ok(data)
+    }
+
+    /// Read like [`Memory::read`], copying directly into `buf` instead of allocating.
+    pub fn read_into(&mut self, address: Address, buf: &mut [u8]) -> Result<()> {
+        self.peek_into(address, buf)?;
+        if self.uninitialized_check && self.page_table.is_uninitialized(address, buf.len()) {
+            log::warn!("Read of uninitialized memory at {}", address);
+            self.uninitialized_reads.push(address);
+        }
+        let section = self.find_section(address)?.name.clone();
+        *self.stats.reads.entry(section).or_insert(0) += 1;
+        *self.stats.reads_by_size.entry(buf.len()).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Read like [`Memory::read`], without counting the access in the statistics.
+    pub fn peek(&mut self, address: Address, size: usize) -> Result<Vec<u8>> {
+        if size > self.access_limit(address)? {
+            return Err(MemoryError::OutOfBounds);
+        }
+        let mut data = vec![0; size];
+        self.peek_into(address, &mut data)?;
+        Ok(data)
+    }
+
+    fn peek_into(&mut self, address: Address, buf: &mut [u8]) -> Result<()> {
+        if buf.len() > self.access_limit(address)? {
             return Err(MemoryError::OutOfBounds); // Out of bounds
         }
-        if let Some(data) = self.mmio_try_read_to(section.read_handler, address, size)? {
-            Ok(data)
+        let section = self.find_section(address)?;
+        if let Some(data) = self.mmio_try_read_to(section.read_handler, address, buf.len())? {
+            buf.copy_from_slice(&data);
+            Ok(())
         } else {
-            Ok(self
-                .page_table
-                .read_bytes(address, size)?
-                .into_iter()
-                .flatten()
-                .cloned()
-                .collect::<Vec<u8>>())
+            self.page_table.read_into(address, buf)
         }
     }
 
     pub fn read_buf(&mut self, address: Address, buf: &mut [u8]) -> Result<()> {
-        let size = buf.len();
-        let src = self.read(address, size)?;
-        buf[..size].copy_from_slice(&src);
-        Ok(())
+        self.read_into(address, buf)
     }
 
     pub fn read_const<const N: usize>(&mut self, address: Address) -> Result<[u8; N]> {
@@ -551,18 +1237,44 @@ impl Memory {
     }
 
     pub fn read_max(&mut self, address: Address, max_size: usize) -> Result<Vec<u8>> {
-        let section = self.find_section(address)?;
-        let size = max_size.min((section.end_address - address) as usize);
+        let size = max_size.min(self.access_limit(address)?);
         self.read(address, size)
     }
 
     pub fn read_buf_max(&mut self, address: Address, buf: &mut [u8]) -> Result<usize> {
-        let section = self.find_section(address)?;
-        let size = buf.len().min((section.end_address - address) as usize);
+        let size = buf.len().min(self.access_limit(address)?);
         self.read_buf(address, &mut buf[..size])?;
         Ok(size)
     }
 
+    /// Read a null-terminated string starting at `address`, excluding the terminator.
+    /// If the section ends before a null byte is found, the bytes up to the end of the section are returned.
+    pub fn read_cstr(&mut self, address: Address) -> Result<Vec<u8>> {
+        self.read_cstr_max(address, usize::MAX)
+    }
+
+    /// Read a null-terminated string like [`Memory::read_cstr`], but at most `max_len` bytes of it.
+    pub fn read_cstr_max(&mut self, address: Address, max_len: usize) -> Result<Vec<u8>> {
+        const CHUNK_SIZE: usize = 128;
+        let mut result = Vec::new();
+        let mut buffer = [0u8; CHUNK_SIZE];
+        let mut address = address;
+        loop {
+            let chunk = CHUNK_SIZE.min(max_len - result.len());
+            let n = self.read_buf_max(address, &mut buffer[..chunk])?;
+            if let Some(end) = buffer[..n].iter().position(|&byte| byte == 0) {
+                result.extend_from_slice(&buffer[..end]);
+                return Ok(result);
+            }
+            result.extend_from_slice(&buffer[..n]);
+            if n < chunk || result.len() == max_len {
+                // Reached the end of the section or the maximum length without a terminator
+                return Ok(result);
+            }
+            address += n;
+        }
+    }
+
     pub fn read_byte(&mut self, address: Address) -> Result<u8> {
         let mut data = [0; size_of::<u8>()];
         self.read_buf(address, &mut data)?;
@@ -572,49 +1284,193 @@ impl Memory {
     pub fn read_halfword(&mut self, address: Address) -> Result<u16> {
         let mut data = [0; size_of::<u16>()];
         self.read_buf(address, &mut data)?;
-        Ok(u16::from_le_bytes(data))
+        Ok(self.endianness.halfword_from_bytes(data))
     }
 
     pub fn read_word(&mut self, address: Address) -> Result<Word> {
         let mut data = [0; size_of::<Word>()];
         self.read_buf(address, &mut data)?;
-        Ok(Word::from_le_bytes(data))
+        Ok(self.endianness.word_from_bytes(data))
+    }
+
+    pub fn read_u8(&mut self, address: Address) -> Result<u8> {
+        self.read_byte(address)
+    }
+
+    pub fn read_u16(&mut self, address: Address) -> Result<u16> {
+        self.read_halfword(address)
+    }
+
+    pub fn read_u32(&mut self, address: Address) -> Result<u32> {
+        self.read_word(address)
+    }
+
+    pub fn read_i8(&mut self, address: Address) -> Result<i8> {
+        Ok(self.read_byte(address)? as i8)
+    }
+
+    pub fn read_i16(&mut self, address: Address) -> Result<i16> {
+        Ok(self.read_halfword(address)? as i16)
+    }
+
+    pub fn read_i32(&mut self, address: Address) -> Result<i32> {
+        Ok(self.read_word(address)? as i32)
     }
 
     pub fn read_address(&mut self, address: Address) -> Result<Address> {
         let mut data = [0; size_of::<Address>()];
         self.read_buf(address, &mut data)?;
-        Ok(Address::from_le_bytes(data))
+        Ok(Address::new(self.endianness.word_from_bytes(data)))
     }
 
     /// Write to a memory address location.
     /// The value is written in between `(start_address + offset)` to `(start_address + offset + value.len())`.
     pub fn write(&mut self, address: Address, bytes: &[u8]) -> Result<()> {
-        let section = self.find_section(address)?;
-        if address + bytes.len() > section.end_address {
+        self.grow_stack(address)?;
+        if bytes.len() > self.access_limit(address)? {
             return Err(MemoryError::OutOfBounds); // Out of bounds
         }
+        let section = self.find_section(address)?;
+        let is_text = section.start_address == self.text;
+        let name = section.name.clone();
         self.mmio_try_write_to(section.write_handler, address, bytes)?;
+        *self.stats.writes.entry(name).or_insert(0) += 1;
+        *self.stats.writes_by_size.entry(bytes.len()).or_insert(0) += 1;
+        if self.journal.is_none() {
+            return self.store(address, bytes, is_text);
+        }
+        let mut previous = vec![0; bytes.len()];
+        let readable = self.page_table.read_into(address, &mut previous).is_ok();
+        self.store(address, bytes, is_text)?;
+        if let (Some(journal), true) = (&mut self.journal, readable) {
+            journal.push((address, previous));
+        }
+        Ok(())
+    }
+
+    /// Write `bytes` into the pages at `address`, decoding them again if they are in the `.text` section.
+    fn store(&mut self, address: Address, bytes: &[u8], is_text: bool) -> Result<()> {
+        if is_text {
+            let decoded = self.decode_text_write(address, bytes)?;
+            self.page_table.write_bytes(address, bytes)?;
+            for (index, instruction) in decoded {
+                self.text_instructions[index] = instruction;
+            }
+            return Ok(());
+        }
         self.page_table.write_bytes(address, bytes)
     }
 
+    /// Record the previous contents of every following write, until [`Memory::take_journal`].
+    pub(crate) fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    /// Stop journaling, returning the previous contents of the writes since [`Memory::start_journal`], in order.
+    pub(crate) fn take_journal(&mut self) -> Vec<(Address, Vec<u8>)> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    /// Undo the writes of a journal, restoring the previous contents in reverse order.
+    /// Memory-mapped I/O devices are not notified, and the statistics are kept.
+    pub(crate) fn revert(&mut self, journal: Vec<(Address, Vec<u8>)>) -> Result<()> {
+        for (address, previous) in journal.into_iter().rev() {
+            let is_text = self.find_section(address)?.start_address == self.text;
+            self.store(address, &previous, is_text)?;
+        }
+        Ok(())
+    }
+
+    /// Decode every text word overlapped by writing `bytes` at `address`,
+    /// so that `execute` fetches the new instructions after the write.
+    /// Fails without modifying memory if the text is not writable or a word does not decode.
+    fn decode_text_write(
+        &self,
+        address: Address,
+        bytes: &[u8],
+    ) -> Result<Vec<(usize, Instruction)>> {
+        let start = self.text().start_address;
+        let size = Instruction::size() as u32;
+        let first = (address - start) / size;
+        let last = (address + bytes.len() - 1 - start) / size;
+        let mut decoded = Vec::new();
+        for index in first..=last {
+            let word_address = start + index * size;
+            let page = self
+                .page_table
+                .get_page(word_address.page_number(self.page_size()))
+                .ok_or(MemoryError::SegmentFault)?;
+            if !page.protection.is_writable() {
+                return Err(MemoryError::ProtectionFault);
+            }
+            // Instructions are always encoded little-endian
+            let offset = word_address.page_offset(self.page_size()) as usize;
+            let mut word = [0; size_of::<Word>()];
+            word.copy_from_slice(&page.data[offset..offset + size_of::<Word>()]);
+            for (i, byte) in word.iter_mut().enumerate() {
+                let byte_address = word_address + i;
+                if address <= byte_address && byte_address < address + bytes.len() {
+                    *byte = bytes[(byte_address - address) as usize];
+                }
+            }
+            let instruction =
+                decode_instruction(u32::from_le_bytes(word), word_address, &self.labels)
+                    .ok_or(MemoryError::InvalidInstruction)?;
+            decoded.push((index as usize, instruction));
+        }
+        Ok(decoded)
+    }
+
+    /// The size of every page in bytes, [`PAGE_SIZE`] unless loaded with another page size.
+    pub fn page_size(&self) -> usize {
+        self.page_table.page_size
+    }
+
+    /// Allow or disallow writes into the `.text` section, for self-modifying code.
+    /// Written instructions are decoded again, and the text is read-execute by default.
+    pub fn set_text_writable(&mut self, writable: bool) {
+        self.text_writable = writable;
+        let protection = if writable {
+            ProtectionLevel::ReadWriteExecute
+        } else {
+            ProtectionLevel::ReadExecute
+        };
+        let text = self.text();
+        let (start, end) = (
+            text.start_address.page_number(self.page_size()),
+            text.end_address.page_number(self.page_size()),
+        );
+        self.page_table.set_protections(start, end, protection);
+    }
+
     pub fn write_byte(&mut self, address: Address, value: u8) -> Result<()> {
         self.write(address, &[value])
     }
 
     pub fn write_halfword(&mut self, address: Address, value: u16) -> Result<()> {
-        self.write(address, &value.to_le_bytes())
+        let bytes = self.endianness.halfword_to_bytes(value);
+        self.write(address, &bytes)
     }
 
     pub fn write_word(&mut self, address: Address, value: Word) -> Result<()> {
-        self.write(address, &value.to_le_bytes())
+        let bytes = self.endianness.word_to_bytes(value);
+        self.write(address, &bytes)
+    }
+
+    /// Fetch the instruction at `address` for execution, counting the fetch in the statistics.
+    pub fn fetch(&mut self, address: Address) -> Result<&Instruction> {
+        self.stats.fetches += 1;
+        self.execute(address)
     }
 
     /// Currently, only the text section will be executable
     pub fn execute(&self, address: Address) -> Result<&Instruction> {
         if self.text().start_address <= address && address <= self.text().end_address {
             let index = (address - self.text().start_address) as usize / Instruction::size();
-            let Some(page) = self.page_table.get_page(address.page_number()) else {
+            let Some(page) = self
+                .page_table
+                .get_page(address.page_number(self.page_size()))
+            else {
                 return Err(MemoryError::SegmentFault);
             };
             if page.protection.is_executable() {
@@ -629,7 +1485,7 @@ impl Memory {
         }
     }
 
-    pub fn labels(&self) -> &HashMap<String, Address> {
+    pub fn labels(&self) -> &LabelMap {
         &self.labels
     }
 
@@ -645,6 +1501,14 @@ impl Memory {
         self.sections.get_mut(&self.data?)
     }
 
+    pub fn rdata(&self) -> Option<&MemorySegment> {
+        self.sections.get(&self.rdata?)
+    }
+
+    pub fn bss(&self) -> Option<&MemorySegment> {
+        self.sections.get(&self.bss?)
+    }
+
     pub fn heap(&self) -> &MemorySegment {
         self.sections.get(&self.heap).unwrap()
     }
@@ -668,24 +1532,71 @@ impl Memory {
     /// Returns:
     /// - `Ok(())` if the push is successful.
     pub fn stack_push(&mut self, values: &[u8]) -> Result<()> {
-        // Check if the stack section is colliding with the heap section
-        if self.stack().start_address - 1 <= self.heap().end_address {
-            return Err(MemoryError::InvalidStack);
-        }
+        // Check if the stack section is colliding with the heap section or its limit
+        let stack_old_start = self.stack().start_address;
+        let stack_new_start = stack_old_start
+            .unwrap()
+            .checked_sub(values.len() as u32)
+            .map(Address::new)
+            .filter(|start| *start > self.heap().end_address)
+            .filter(|start| *start >= self.stack_limit_address())
+            .ok_or(MemoryError::InvalidStack)?;
+        let page_size = self.page_size();
         let stack = self.stack_mut();
-        let stack_new_start = stack.start_address - values.len() as u32;
         stack.start_address = stack_new_start;
+        self.page_table.ensure_pages(
+            stack_new_start.page_number(page_size),
+            stack_old_start.page_number(page_size),
+            ProtectionLevel::ReadWrite,
+        );
         self.page_table.write_bytes(stack_new_start, values)
     }
 
+    /// Grow the stack section down to `address` if it lies between the heap and the stack,
+    /// so that stores below the stack pointer reach the stack like on a real system.
+    /// Only writes grow the stack, and not beyond the [`Memory::set_stack_limit`].
+    fn grow_stack(&mut self, address: Address) -> Result<()> {
+        let start = self.stack().start_address;
+        if address >= start || address <= self.heap().end_address {
+            return Ok(());
+        }
+        let limit = self.stack_limit_address();
+        if address < limit {
+            return Err(MemoryError::InvalidStack);
+        }
+        let new_start = address.align_down(4).max(limit);
+        let page_size = self.page_size();
+        self.page_table.ensure_pages(
+            new_start.page_number(page_size),
+            start.page_number(page_size),
+            ProtectionLevel::ReadWrite,
+        );
+        self.stack_mut().start_address = new_start;
+        Ok(())
+    }
+
+    /// The lowest address the stack may grow down to.
+    fn stack_limit_address(&self) -> Address {
+        let top = self.stack().end_address.unwrap();
+        Address::new(top.saturating_sub(self.stack_limit.try_into().unwrap_or(u32::MAX)))
+    }
+
     /// Pop a byte from the stack.
     /// **The stack grows downwards** (from high address to lower addresses),
     /// so the `start_address += 1` to adjust the range of the stack section.
+    ///
+    /// Returns:
+    /// - `Err(MemoryError::InvalidStack)` if the pop would go above the original top of the stack.
     pub fn stack_pop(&mut self, size: usize) -> Result<Vec<u8>> {
-        let stack = self.stack_mut();
-        let stack_new_start = stack.start_address + size as u32;
-        stack.start_address = stack_new_start;
-        self.read(stack_new_start, size)
+        let stack = self.stack();
+        let stack_start = stack.start_address;
+        // The end of the stack section is its original top, it never moves
+        if stack.end_address - stack_start < size as u32 {
+            return Err(MemoryError::InvalidStack);
+        }
+        let values = self.read(stack_start, size)?;
+        self.stack_mut().start_address = stack_start + size as u32;
+        Ok(values)
     }
 
     /// Push a word to the stack.
@@ -696,7 +1607,8 @@ impl Memory {
     /// - `Ok(())` if the push is successful.
     /// - `Err` if the stack section is colliding with the heap section.
     pub fn stack_push_word(&mut self, value: Word) -> Result<()> {
-        self.stack_push(&value.to_le_bytes())
+        let bytes = self.endianness.word_to_bytes(value);
+        self.stack_push(&bytes)
     }
 
     /// Pop a word from the stack.
@@ -708,7 +1620,7 @@ impl Memory {
             .stack_pop(WORD_SIZE)?
             .try_into()
             .map_err(|_| MemoryError::InvalidSize)?;
-        Ok(Word::from_le_bytes(bytes))
+        Ok(self.endianness.word_from_bytes(bytes))
     }
 
     /// Push an address to the stack.
@@ -719,7 +1631,8 @@ impl Memory {
     /// - `Some(())` if the push is successful.
     /// - `None` if the stack section is colliding with the heap section.
     pub fn stack_push_address(&mut self, value: Address) -> Result<()> {
-        self.stack_push(&value.to_le_bytes())
+        let bytes = self.endianness.word_to_bytes(value.unwrap());
+        self.stack_push(&bytes)
     }
 
     /// Pop an address from the stack.
@@ -731,23 +1644,28 @@ impl Memory {
             .stack_pop(ADDRESS_SIZE)?
             .try_into()
             .map_err(|_| MemoryError::InvalidSize)?;
-        Ok(Address::from_le_bytes(bytes))
+        Ok(Address::new(self.endianness.word_from_bytes(bytes)))
     }
 
     /// Allocate memory on the heap of a given size (number of bytes).
     /// The heap grows upwards (from low address to higher addresses),
     /// so the `end_address += size` to adjust the range of the heap section.
+    /// The allocation begins at the end of the heap rounded up to the heap alignment,
+    /// see [`Memory::set_heap_alignment`], and the padding is included in the heap.
     ///
     /// Returns:
-    /// - `Some(Address)` if the allocation is successful, with the address of the allocated memory.
-    /// - `None` if the allocation is unsuccessful, due to out-of-memory or heap-stack collision.
+    /// - `Ok(Address)` if the allocation is successful, with the address of the allocated memory.
+    /// - `Err(MemoryError::InvalidHeap)` if the heap would run into the stack.
     pub fn heap_allocate(&mut self, size: usize) -> Result<Address> {
+        let address = self.heap().end_address.align_up(self.heap_alignment as u32);
         // Check if the heap section is colliding with the stack section
-        if self.heap().end_address + size >= self.stack().start_address {
-            return Err(MemoryError::InvalidHeap);
-        }
-        let address = self.heap_mut().end_address;
-        self.heap_mut().end_address += size;
+        let end = u32::try_from(size)
+            .ok()
+            .and_then(|size| address.unwrap().checked_add(size))
+            .map(Address::new)
+            .filter(|end| *end < self.stack().start_address)
+            .ok_or(MemoryError::InvalidHeap)?;
+        self.heap_mut().end_address = end;
         Ok(address)
     }
 
@@ -762,24 +1680,26 @@ impl Memory {
     /// Dump all the memory contents into a vector of bytes.
     /// This is used for debugging purposes.
     pub fn dump(&self, compress: bool, shard_size: usize, static_only: bool) -> Vec<u8> {
+        let page_size = self.page_size();
         assert!(
-            PAGE_SIZE % shard_size == 0,
-            "Shard size must be a divisor of PAGE_SIZE"
+            page_size.is_multiple_of(shard_size),
+            "Shard size must be a divisor of the page size"
         );
-        assert!(shard_size % 4 == 0, "Shard size must be multiple of 4");
         assert!(
-            shard_size <= PAGE_SIZE,
-            "Shard size must be less than PAGE_SIZE"
+            shard_size.is_multiple_of(4),
+            "Shard size must be multiple of 4"
+        );
+        assert!(
+            shard_size <= page_size,
+            "Shard size must be less than the page size"
         );
         assert!(shard_size > 0, "Shard size must be greater than 0");
         log::trace!("Dumping memory contents...");
         log::trace!("Compress: {}", compress);
         let mut buf = Vec::new();
-        // Iterate through all allocated memory pages
-        let mut page_numbers_sorted = self.page_table.pages.keys().collect::<Vec<_>>();
-        page_numbers_sorted.sort();
-        for page_address in page_numbers_sorted {
-            let page_number = page_address.page_number();
+        // Iterate through all allocated memory pages, in address order
+        for page_address in self.page_table.pages.keys() {
+            let page_number = page_address.page_number(page_size);
             let max_data_size = if let Some(section) = self.sections.get(page_address) {
                 if static_only && (section.name != ".text" && section.name != ".data") {
                     log::trace!(
@@ -806,8 +1726,8 @@ impl Memory {
                     log::trace!(
                         "Skipping unknown section: {} - {} ({} bytes, page {})",
                         page_address,
-                        *page_address + PAGE_SIZE,
-                        PAGE_SIZE,
+                        *page_address + page_size,
+                        page_size,
                         page_number
                     );
                     continue;
@@ -815,11 +1735,11 @@ impl Memory {
                 log::trace!(
                     "Unknown section: {} - {} ({} bytes, page {})",
                     page_address,
-                    *page_address + PAGE_SIZE,
-                    PAGE_SIZE,
+                    *page_address + page_size,
+                    page_size,
                     page_number
                 );
-                PAGE_SIZE
+                page_size
             };
             let page = self.page_table.get_page(page_number).unwrap();
             let mut start = page_address.unwrap() as usize;
@@ -856,4 +1776,509 @@ impl Memory {
         }
         buf
     }
+
+    /// The byte ranges where `other` differs from this memory, in address order.
+    /// Pages allocated in only one of the memories are not compared.
+    pub fn diff(&self, other: &Memory) -> Vec<MemDiff> {
+        let mut diffs: Vec<MemDiff> = Vec::new();
+        for (page_address, page) in &self.page_table.pages {
+            let Some(other_page) = other.page_table.pages.get(page_address) else {
+                continue;
+            };
+            let bytes = page.data.iter().zip(other_page.data.iter());
+            for (offset, (expected, actual)) in bytes.enumerate() {
+                if expected == actual {
+                    continue;
+                }
+                let address = *page_address + offset;
+                match diffs.last_mut() {
+                    Some(diff) if diff.address + diff.expected.len() == address => {
+                        diff.expected.push(*expected);
+                        diff.actual.push(*actual);
+                    }
+                    _ => diffs.push(MemDiff {
+                        address,
+                        expected: vec![*expected],
+                        actual: vec![*actual],
+                    }),
+                }
+            }
+        }
+        diffs
+    }
+
+    /// Show `len` bytes from `start` with 16 bytes per line, in hexadecimal and ASCII:
+    ///
+    /// `0x10010000  68 65 6C 6C 6F 00 -- ...  |hello. ...|`
+    ///
+    /// Bytes of unmapped pages are shown as `--`, and non-printable bytes as `.` in the ASCII column.
+    pub fn hexdump(&self, start: Address, len: usize) -> String {
+        let mut output = String::new();
+        for line_start in (0..len).step_by(16) {
+            let line_address = start + line_start;
+            let bytes = (line_start..len.min(line_start + 16))
+                .map(|i| {
+                    let address = start + i;
+                    self.page_table
+                        .get_page(address.page_number(self.page_size()))
+                        .map(|page| page.data[address.page_offset(self.page_size()) as usize])
+                })
+                .collect::<Vec<_>>();
+            let hex = bytes
+                .iter()
+                .map(|b| b.map_or("--".to_string(), |b| format!("{:02X}", b)))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let ascii = bytes
+                .iter()
+                .map(|b| match b {
+                    Some(b) if b.is_ascii_graphic() || *b == b' ' => *b as char,
+                    Some(_) => '.',
+                    None => ' ',
+                })
+                .collect::<String>();
+            output.push_str(&format!("{}  {:<47}  |{}|\n", line_address, hex, ascii));
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod test_memory {
+    use super::{Endianness, MemDiff, Memory, MemoryError, TEXT_START};
+    use crate::{address::Address, parser::parse, program::Instruction};
+
+    fn load(input: &str) -> Memory {
+        Memory::load(parse(input).unwrap(), Vec::new())
+    }
+
+    #[test]
+    fn word_endianness() {
+        let mut memory = load(".data\nvalue: .word 0\n.text\nmain:\n\tnop\n");
+        let address = memory.address_of_label("value").unwrap();
+
+        assert_eq!(memory.endianness(), Endianness::Little);
+        memory.write_word(address, 0x1122_3344).unwrap();
+        assert_eq!(memory.read_word(address).unwrap(), 0x1122_3344);
+        assert_eq!(memory.read_byte(address).unwrap(), 0x44);
+
+        memory.set_endianness(Endianness::Big);
+        memory.write_word(address, 0x1122_3344).unwrap();
+        assert_eq!(memory.read_word(address).unwrap(), 0x1122_3344);
+        assert_eq!(memory.read_byte(address).unwrap(), 0x11);
+        assert_eq!(memory.read_halfword(address).unwrap(), 0x1122);
+    }
+
+    #[test]
+    fn big_endian_data() {
+        let mut memory = load(
+            ".data\nflag: .byte 7\nvalue: .word 1, 0x11223344\n.rdata\nconst: .word 2\n.text\nmain:\n\tnop\n",
+        );
+        let loaded = memory.clone();
+        let value = memory.address_of_label("value").unwrap();
+        let constant = memory.address_of_label("const").unwrap();
+        let flag = memory.address_of_label("flag").unwrap();
+
+        memory.set_endianness(Endianness::Big);
+        assert_eq!(memory.read_word(value).unwrap(), 1);
+        assert_eq!(memory.read_byte(value).unwrap(), 0);
+        assert_eq!(memory.read_word(value + 4).unwrap(), 0x1122_3344);
+        assert_eq!(memory.read_byte(value + 4).unwrap(), 0x11);
+        assert_eq!(memory.read_word(constant).unwrap(), 2);
+        assert_eq!(memory.read_byte(flag).unwrap(), 7);
+
+        // Restoring the little-endian copy keeps the words readable
+        memory.write_word(value, 5).unwrap();
+        memory.restore(&loaded);
+        assert_eq!(memory.read_word(value).unwrap(), 1);
+
+        memory.set_endianness(Endianness::Little);
+        assert_eq!(memory.read_word(value).unwrap(), 1);
+        assert_eq!(memory.read_byte(value).unwrap(), 1);
+
+        // Words are reordered by their element size, whatever the source says
+        let mut program = parse(".data\nvalue: .word 1\n.text\nmain:\n\tnop\n").unwrap();
+        program.data_section.initialized[0].source = "  .WORD 1".to_string();
+        let mut memory = Memory::load(program, Vec::new());
+        let value = memory.address_of_label("value").unwrap();
+        memory.set_endianness(Endianness::Big);
+        assert_eq!(memory.read_word(value).unwrap(), 1);
+    }
+
+    #[test]
+    fn typed_reads() {
+        let mut memory = load(".data\nvalue: .word 0\n.text\nmain:\n\tnop\n");
+        let address = memory.address_of_label("value").unwrap();
+
+        memory.write_byte(address, 0xFF).unwrap();
+        assert_eq!(memory.read_i8(address).unwrap(), -1);
+        assert_eq!(memory.read_u8(address).unwrap(), 255);
+
+        memory.write_word(address, 0xFFFF_8000).unwrap();
+        assert_eq!(memory.read_i16(address).unwrap(), i16::MIN);
+        assert_eq!(memory.read_u16(address).unwrap(), 0x8000);
+        assert_eq!(memory.read_i32(address).unwrap(), -0x8000);
+        assert_eq!(memory.read_u32(address).unwrap(), 0xFFFF_8000);
+    }
+
+    #[test]
+    fn read_cstr() {
+        let mut memory = load(
+            ".data\nterminated: .asciiz \"hello\"\nunterminated: .ascii \"world\"\n.text\nmain:\n\tnop\n",
+        );
+        let terminated = memory.address_of_label("terminated").unwrap();
+        let unterminated = memory.address_of_label("unterminated").unwrap();
+        assert_eq!(memory.read_cstr(terminated).unwrap(), b"hello");
+        assert_eq!(memory.read_cstr(unterminated).unwrap(), b"world");
+    }
+
+    #[test]
+    fn hexdump() {
+        let memory = load(".data\nmsg: .asciiz \"Hello, hexdump world!\"\n.text\nmain:\n\tnop\n");
+        let msg = memory.address_of_label("msg").unwrap();
+        let dump = memory.hexdump(msg, 22);
+        let lines = dump.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0x10010000  48 65 6C 6C 6F 2C 20 68"));
+        assert!(lines[0].ends_with("|Hello, hexdump w|"));
+        assert!(lines[1].starts_with("0x10010010  6F 72 6C 64 21 00"));
+        assert!(lines[1].ends_with("|orld!.|"));
+
+        let unmapped = memory.hexdump(0x0000_1000.into(), 4);
+        assert_eq!(
+            unmapped,
+            format!("0x00001000  {:<47}  |    |\n", "-- -- -- --")
+        );
+    }
+
+    #[test]
+    fn strict_data_bounds() {
+        let mut memory = load(".data\nvalue: .word 4660\nnext: .word 0\n.text\nmain:\n\tnop\n");
+        let value = memory.address_of_label("value").unwrap();
+        assert_eq!(
+            memory.read(value, 8).unwrap(),
+            [0x34, 0x12, 0, 0, 0, 0, 0, 0]
+        );
+
+        memory.set_strict_bounds(true);
+        assert_eq!(memory.read(value, 8), Err(MemoryError::OutOfBounds));
+        assert_eq!(
+            memory.write_word(value + 2u32, 0),
+            Err(MemoryError::OutOfBounds)
+        );
+        assert_eq!(memory.read_word(value).unwrap(), 0x1234);
+        assert_eq!(memory.read_word(value + 4u32).unwrap(), 0);
+    }
+
+    #[test]
+    fn heap_after_data() {
+        let mut memory = load(".data\nvalue: .word 4660\n.text\nmain:\n\tnop\n");
+        let value = memory.address_of_label("value").unwrap();
+        let heap = memory.heap_allocate(4).unwrap();
+        assert_eq!(heap, value + 4u32);
+        assert_eq!(memory.find_section(heap).unwrap().start_address, heap);
+        memory.write_word(heap, 1).unwrap();
+        assert_eq!(memory.read_word(heap).unwrap(), 1);
+    }
+
+    #[test]
+    fn bss() {
+        let mut memory = load(
+            ".data\nvalue: .word 1\n.bss\nbuffer: .space 16\ncount: .space 4\n.text\nmain:\n\tnop\n",
+        );
+        let value = memory.address_of_label("value").unwrap();
+        let buffer = memory.address_of_label("buffer").unwrap();
+        let count = memory.address_of_label("count").unwrap();
+        let bss = memory.bss().unwrap();
+        assert_eq!(bss.start_address, value + 4u32);
+        assert_eq!(bss.end_address, count + 4u32);
+        assert_eq!(buffer, bss.start_address);
+        assert_eq!(count, buffer + 16u32);
+        assert_eq!(memory.heap().start_address, count + 4u32);
+
+        assert_eq!(memory.read_word(buffer + 12u32).unwrap(), 0);
+        memory.write_word(buffer + 12u32, 0xDEAD_BEEF).unwrap();
+        assert_eq!(memory.read_word(buffer + 12u32).unwrap(), 0xDEAD_BEEF);
+        assert_eq!(memory.read_word(count).unwrap(), 0);
+
+        // Reserved symbols are word-aligned after unaligned data
+        let memory = load(
+            ".data\nflag: .byte 1\n.bss\nodd: .space 3\ncount: .space 4\n.text\nmain:\n\tnop\n",
+        );
+        let flag = memory.address_of_label("flag").unwrap();
+        let odd = memory.address_of_label("odd").unwrap();
+        assert_eq!(memory.bss().unwrap().start_address, flag + 4u32);
+        assert_eq!(odd, flag + 4u32);
+        assert_eq!(memory.address_of_label("count").unwrap(), odd + 4u32);
+    }
+
+    #[test]
+    fn sections() {
+        let memory = load(".data\nmsg: .asciiz \"hi\"\n.text\nmain:\n\tnop\nloop:\n\tj loop\n");
+        let names = memory
+            .sections()
+            .map(|section| section.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![".text", ".data", ".heap", ".stack"]);
+
+        let text = memory.text();
+        assert_eq!(text.start_address, Address::new(0x0040_0000));
+        assert_eq!(text.end_address, Address::new(0x0040_0008));
+        let labels = memory.labels_in(text);
+        let main = "main".to_string();
+        let loop_ = "loop".to_string();
+        assert_eq!(
+            labels,
+            vec![
+                (&main, Address::new(0x0040_0000)),
+                (&loop_, Address::new(0x0040_0004))
+            ]
+        );
+
+        let data = memory.data().unwrap();
+        assert_eq!(data.start_address, Address::new(0x1001_0000));
+        assert_eq!(data.end_address, Address::new(0x1001_0003));
+        let msg = "msg".to_string();
+        assert_eq!(memory.labels_in(data), vec![(&msg, data.start_address)]);
+    }
+
+    #[test]
+    fn text_is_read_only() {
+        let mut memory = load(".text\nmain:\n\tnop\n");
+        let main = memory.address_of_label("main").unwrap();
+        assert_eq!(
+            memory.write_word(main, 0),
+            Err(MemoryError::ProtectionFault)
+        );
+        assert_eq!(memory.read_word(main), Ok(0));
+    }
+
+    #[test]
+    fn stack_pop() {
+        let mut memory = Memory::load(parse(".text\nmain:\n\tnop\n").unwrap(), Vec::new());
+        memory.stack_push_word(0xDEAD_BEEF).unwrap();
+        assert_eq!(memory.stack_pop_word(), Ok(0xDEAD_BEEF));
+        assert_eq!(memory.stack_pop_word(), Err(MemoryError::InvalidStack));
+        assert_eq!(memory.stack().start_address, memory.stack().end_address);
+    }
+
+    #[test]
+    fn oversized_read() {
+        let mut memory = load(".data\nvalue: .word 1\n.text\nmain:\n\tnop\n");
+        let value = memory.address_of_label("value").unwrap();
+        assert_eq!(
+            memory.peek(value, usize::MAX),
+            Err(MemoryError::OutOfBounds)
+        );
+        assert_eq!(
+            memory.read(value, usize::MAX),
+            Err(MemoryError::OutOfBounds)
+        );
+        assert_eq!(memory.peek(value, 4), Ok(vec![1, 0, 0, 0]));
+    }
+
+    #[test]
+    fn stack_limit() {
+        let mut memory = Memory::load(parse(".text\nmain:\n\tnop\n").unwrap(), Vec::new());
+        memory.set_stack_limit(4096);
+        let top = memory.stack().end_address;
+        // Reads below the stack never grow it
+        assert!(memory.read_word(top - 8u32).is_err());
+        assert_eq!(memory.stack().start_address, top);
+        memory.write_word(top - 4096u32, 1).unwrap();
+        assert_eq!(memory.stack().start_address, top - 4096u32);
+        assert_eq!(
+            memory.write_word(top - 4100u32, 1),
+            Err(MemoryError::InvalidStack)
+        );
+        assert_eq!(memory.stack().start_address, top - 4096u32);
+    }
+
+    #[test]
+    fn dump_deterministic() {
+        let source = ".data\na: .word 1\nb: .asciiz \"b\"\nc: .word 3\n.text\nmain:\n\tnop\nloop:\n\tj loop\n";
+        let first = load(source);
+        let second = load(source);
+        assert_eq!(first.dump(true, 128, false), second.dump(true, 128, false));
+        let labels = first.labels().keys().collect::<Vec<_>>();
+        assert_eq!(labels, vec!["a", "b", "c", "loop", "main"]);
+    }
+
+    #[test]
+    fn diff() {
+        let source = ".data\na: .word 1\nb: .word 2\n.text\nmain:\n\tnop\n";
+        let expected = load(source);
+        let mut actual = load(source);
+        assert_eq!(expected.diff(&actual), Vec::new());
+
+        let b = actual.address_of_label("b").unwrap();
+        actual.write_word(b, 0x0102_0302).unwrap();
+        let diffs = expected.diff(&actual);
+        assert_eq!(
+            diffs,
+            vec![MemDiff {
+                address: b + 1u32,
+                expected: vec![0, 0, 0],
+                actual: vec![3, 2, 1],
+            }]
+        );
+    }
+
+    #[test]
+    fn large_pages() {
+        let program = parse(".data\nbuffer: .space 70000\n.text\nmain:\n\tnop\n").unwrap();
+        let mut memory = Memory::load_with_page_size(program, Vec::new(), 0x1_0000);
+        assert_eq!(memory.page_size(), 0x1_0000);
+        // The buffer spans the first two 64KB pages of the data segment
+        let pages = memory.page_table.pages.keys().copied().collect::<Vec<_>>();
+        assert!(pages.contains(&Address::new(0x1001_0000)));
+        assert!(pages.contains(&Address::new(0x1002_0000)));
+        assert!(!pages.contains(&Address::new(0x1001_1000)));
+
+        let boundary = Address::new(0x1002_0000);
+        memory
+            .write(boundary - 4u32, &[1, 2, 3, 4, 5, 6, 7, 8])
+            .unwrap();
+        assert_eq!(
+            memory.read(boundary - 4u32, 8).unwrap(),
+            vec![1, 2, 3, 4, 5, 6, 7, 8]
+        );
+        assert_eq!(memory.read_byte(boundary).unwrap(), 5);
+    }
+
+    #[test]
+    fn read_into() {
+        let mut memory = load(".data\nbuffer: .space 1048576\n.text\nmain:\n\tnop\n");
+        let buffer = memory.address_of_label("buffer").unwrap();
+        let bytes = (0..=255u8).cycle().take(1 << 20).collect::<Vec<_>>();
+        memory.write(buffer, &bytes).unwrap();
+
+        // Spans all 256 pages of the buffer
+        let mut buf = vec![0; 1 << 20];
+        memory.read_into(buffer, &mut buf).unwrap();
+        assert_eq!(buf, bytes);
+        assert_eq!(memory.stats().reads_by_size.get(&(1 << 20)), Some(&1));
+        let mut buf = [0; 3];
+        memory.read_into(buffer + 4095u32, &mut buf).unwrap();
+        assert_eq!(buf, [255, 0, 1]);
+        assert_eq!(
+            memory.read_into(buffer + 1048575u32, &mut buf),
+            Err(MemoryError::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn oversized_text() {
+        let max = Memory::text_capacity() / Instruction::size();
+        assert_eq!(
+            Memory::text_size(TEXT_START, max),
+            Ok(max * Instruction::size())
+        );
+        assert_eq!(
+            Memory::text_size(TEXT_START, max + 1),
+            Err(MemoryError::OutOfMemory)
+        );
+        assert_eq!(
+            Memory::text_size(TEXT_START, usize::MAX),
+            Err(MemoryError::OutOfMemory)
+        );
+        assert!(Memory::try_load(parse(".text\nmain:\n\tnop\n").unwrap(), Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn section_bases() {
+        let memory = load(
+            ".data 0x10020000\nvalue: .word 1\n.text 0x00500000\nmain:\n\tnop\nloop:\n\tj loop\n",
+        );
+        assert_eq!(memory.text().start_address, Address::new(0x0050_0000));
+        assert_eq!(
+            memory.address_of_label("loop").unwrap(),
+            Address::new(0x0050_0004)
+        );
+        assert_eq!(
+            memory.address_of_label("value").unwrap(),
+            Address::new(0x1002_0000)
+        );
+        assert_eq!(memory.heap().start_address, Address::new(0x1002_0004));
+
+        // The data may not run into the stack
+        let program = parse(".data 0x7F7FFFF0\nbuf: .space 32\n.text\nmain:\n\tnop\n").unwrap();
+        assert!(matches!(
+            Memory::try_load(program, Vec::new()),
+            Err(MemoryError::OutOfMemory)
+        ));
+    }
+
+    #[test]
+    fn read_only_data() {
+        let mut memory =
+            load(".rdata\nanswer: .word 42\n.data\nvalue: .word 7\n.text\nmain:\n\tnop\n");
+        let answer = memory.address_of_label("answer").unwrap();
+        assert_eq!(memory.rdata().unwrap().start_address, answer);
+        assert_eq!(memory.read_word(answer), Ok(42));
+        assert_eq!(
+            memory.write_word(answer, 0),
+            Err(MemoryError::ProtectionFault)
+        );
+        assert_eq!(memory.read_word(answer), Ok(42));
+        let value = memory.address_of_label("value").unwrap();
+        assert_eq!(memory.write_word(value, 0), Ok(()));
+    }
+
+    #[test]
+    fn data_symbol() {
+        let memory = load(".data\ncount: .word 3\nmsg: .asciiz \"hello\"\n.text\nmain:\n\tnop\n");
+        let msg = memory.address_of_label("msg").unwrap();
+        assert_eq!(memory.data_symbol("msg"), Some((msg, 6)));
+        assert_eq!(memory.data_symbol("main"), None);
+        assert_eq!(
+            memory.data_symbol_at(msg + 5u32),
+            Some((&"msg".to_string(), msg, 6))
+        );
+        assert_eq!(memory.data_symbol_at(msg + 6u32), None);
+    }
+
+    #[test]
+    fn heap_alignment() {
+        let mut memory = load(".data\nflag: .byte 1\n.text\nmain:\n\tnop\n");
+        let first = memory.heap_allocate(3).unwrap();
+        let second = memory.heap_allocate(4).unwrap();
+        assert_eq!(second.unwrap() % 4, 0);
+        assert_eq!(second, (first + 3u32).align_up(4));
+        assert_eq!(memory.heap().end_address, second + 4u32);
+
+        memory.set_heap_alignment(8);
+        let third = memory.heap_allocate(1).unwrap();
+        assert_eq!(third.unwrap() % 8, 0);
+
+        let end = memory.heap().end_address;
+        assert_eq!(
+            memory.heap_allocate(u32::MAX as usize),
+            Err(MemoryError::InvalidHeap)
+        );
+        assert_eq!(
+            memory.heap_allocate(usize::MAX),
+            Err(MemoryError::InvalidHeap)
+        );
+        assert_eq!(memory.heap().end_address, end);
+    }
+
+    #[test]
+    fn poison() {
+        let mut memory =
+            load(".data\nvalue: .word 1\n.bss\nbuffer: .space 8\n.text\nmain:\n\tnop\n");
+        memory.set_poison(Some(0xCC));
+        memory.set_uninitialized_check(true);
+        let value = memory.address_of_label("value").unwrap();
+        let buffer = memory.address_of_label("buffer").unwrap();
+        assert_eq!(memory.read_word(value).unwrap(), 1);
+        assert_eq!(memory.read_byte(buffer + 3u32).unwrap(), 0xCC);
+        assert_eq!(memory.uninitialized_reads(), [buffer + 3u32]);
+
+        memory.write_byte(buffer, 7).unwrap();
+        assert_eq!(memory.read_byte(buffer).unwrap(), 7);
+        let heap = memory.heap_allocate(4).unwrap();
+        assert_eq!(memory.read_word(heap).unwrap(), 0xCCCC_CCCC);
+        assert_eq!(memory.uninitialized_reads(), [buffer + 3u32, heap]);
+    }
 }
@@ -1,11 +1,15 @@
+use std::{collections::HashMap, fmt::Display, path::Path};
+
 use colorful::Colorful;
-use pest::Parser;
+use pest::{error::LineColLocation, iterators::Pair, Parser};
 use pest_derive::Parser;
 
 use crate::{
+    address::Address,
+    memory::{ANY_DATA_END, ANY_DATA_START, TEXT_MAX, TEXT_START},
     program::{
-        Block, DataSection, Immediate, Instruction, InstructionArg, InstructionKind, Program,
-        Section, StaticData, TextSection,
+        Assertion, Block, DataSection, Immediate, Instruction, InstructionArg, InstructionKind,
+        IsaProfile, Program, ReservedData, Section, StaticData, TextSection, Word,
     },
     registers::Register,
 };
@@ -14,233 +18,1083 @@ use crate::{
 #[grammar = "grammar.pest"]
 struct MainParser;
 
-pub fn parse(input: &str) -> Option<Program> {
-    match MainParser::parse(Rule::program, input) {
-        Ok(pairs) => {
-            assert_eq!(pairs.clone().count(), 1);
-            let program = pairs.clone().next().unwrap();
-            let pairs = program.into_inner();
-            log::trace!("{}", "======= PAIRS =======".blue());
-            for pair in pairs.clone() {
-                log::trace!("{}", "------- PAIR -------".cyan());
-                log::trace!("Rule:    {:?}", pair.as_rule());
-                log::trace!("Span:    {:?}", pair.as_span());
-                log::trace!("Text:    '{}'", pair.as_str().trim().yellow());
-                log::trace!("Inner:   {:?}", pair.into_inner().collect::<Vec<_>>());
+/// An error produced while parsing a MIPS program.
+/// The `line` and `column` are 1-based, or 0 if the error is not yet located in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// The included file the error is located in, or `None` for the parsed input itself.
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>) -> Self {
+        ParseError {
+            message: message.into(),
+            file: None,
+            line: 0,
+            column: 0,
+        }
+    }
+
+    /// Locate the error at the start of a parsed token.
+    fn at(mut self, pair: &Pair<Rule>) -> Self {
+        (self.line, self.column) = pair.as_span().start_pos().line_col();
+        self
+    }
+}
+
+impl From<pest::error::Error<Rule>> for ParseError {
+    fn from(e: pest::error::Error<Rule>) -> Self {
+        let (line, column) = match e.line_col {
+            LineColLocation::Pos(pos) => pos,
+            LineColLocation::Span(start, _) => start,
+        };
+        ParseError {
+            message: e.variant.message().to_string(),
+            file: None,
+            line,
+            column,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else if let Some(file) = &self.file {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                file, self.line, self.column, self.message
+            )
+        } else {
+            write!(f, "{}:{}: {}", self.line, self.column, self.message)
+        }
+    }
+}
+
+/// Numeric local labels, each definition of `N:` is given a unique name.
+#[derive(Default)]
+struct LocalLabels {
+    /// Number of definitions so far of each numeric label.
+    definitions: HashMap<String, usize>,
+}
+
+impl LocalLabels {
+    /// The name of the `index`th definition of `number:`, e.g. `.L1_0`.
+    /// Labels of the source start with a letter, so they cannot collide with it.
+    fn name(number: &str, index: usize) -> String {
+        format!(".L{}_{}", number, index)
+    }
+
+    fn define(&mut self, number: &str) -> String {
+        let count = self.definitions.entry(number.to_string()).or_insert(0);
+        *count += 1;
+        Self::name(number, *count - 1)
+    }
+
+    fn count(&self, number: &str) -> usize {
+        self.definitions.get(number).copied().unwrap_or(0)
+    }
+}
+
+/// The address operand of a `.text address` or `.data address` directive,
+/// which must be word aligned and within the address range of the section.
+fn parse_section_base(section: &Section, operand: &Pair<Rule>) -> Result<Address, ParseError> {
+    let (start, end) = match section {
+        Section::Text => (TEXT_START, TEXT_MAX),
+        Section::Data => (ANY_DATA_START, ANY_DATA_END),
+        _ => {
+            return Err(ParseError::new(format!(
+                "Section {} cannot be placed at an address",
+                section.show()
+            ))
+            .at(operand))
+        }
+    };
+    let base = Address::new(parse_imm(operand, 32)?);
+    if base < start || base >= end || !base.unwrap().is_multiple_of(4) {
+        return Err(ParseError::new(format!(
+            "Section {} must be placed at a word aligned address from {} to {}: {}",
+            section.show(),
+            start,
+            end,
+            operand.as_str()
+        ))
+        .at(operand));
+    }
+    Ok(base)
+}
+
+/// Rewrite every label reference and `.globl` symbol to the spelling of the label it matches ignoring case,
+/// failing if two labels differ only in case.
+fn normalize_label_case(
+    prog: &mut Program,
+    global_labels: &mut [String],
+) -> Result<(), ParseError> {
+    let mut defined = prog.defined_labels().into_iter().collect::<Vec<_>>();
+    defined.sort();
+    let mut spellings: HashMap<String, String> = HashMap::new();
+    for label in defined {
+        if let Some(other) = spellings.insert(label.to_lowercase(), label.clone()) {
+            return Err(ParseError::new(format!(
+                "Labels {} and {} differ only in case",
+                other, label
+            )));
+        }
+    }
+    let normalize = |label: &mut String| {
+        if let Some(spelling) = spellings.get(&label.to_lowercase()) {
+            label.clone_from(spelling);
+        }
+    };
+    global_labels.iter_mut().for_each(normalize);
+    for instruction in prog
+        .text_section
+        .blocks
+        .iter_mut()
+        .flat_map(|block| &mut block.instructions)
+    {
+        for arg in &mut instruction.args {
+            if let InstructionArg::Label(label) | InstructionArg::LabelOffset(label, _) = arg {
+                normalize(label);
             }
-            log::trace!("{}", "======= PROGRAM =======".blue());
-            let mut prog = Program {
-                data_section: DataSection {
-                    initialized: Vec::new(),
-                },
-                text_section: TextSection {
-                    blocks: Vec::new(),
-                    global_labels: Vec::new(),
-                },
-            };
-            let mut current_section: Option<Section> = None;
-
-            // Unnamed initial block
-            let mut current_block: Block = Block {
-                label: String::new(),
-                instructions: Vec::new(),
-            };
-
-            for pair in pairs {
-                match pair.as_rule() {
-                    Rule::directive => {
-                        let mut inner = pair.into_inner();
-                        let inner_first = inner.next().unwrap();
-                        log::trace!("Directive: {:?}", inner);
-                        match inner_first.as_rule() {
-                            Rule::section_directive => {
-                                let section = match inner_first.as_str().trim() {
-                                    ".data" => Section::Data,
-                                    ".text" => Section::Text,
-                                    _ => unreachable!(),
-                                };
-                                log::trace!("Section: {:?}", section);
-                                current_section = Some(section);
+        }
+    }
+    Ok(())
+}
+
+/// Resolves the path of an `.include "path"` directive to the source code of the included file.
+pub type IncludeResolver<'a> = &'a dyn Fn(&str) -> std::io::Result<String>;
+
+/// Options controlling which programs the parser accepts.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Accept pseudo instructions such as `li`, `la`, `move` and `blt` (enabled by default).
+    /// When disabled, only real MIPS instructions are accepted.
+    pub allow_pseudo: bool,
+    /// The instruction set revision to accept (the latest by default).
+    /// Instructions from later revisions are rejected.
+    pub isa: IsaProfile,
+    /// Distinguish labels by case, as MARS does (enabled by default).
+    /// When disabled, `Main` refers to `main:`, and labels differing only in case are rejected.
+    pub case_sensitive: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            allow_pseudo: true,
+            isa: IsaProfile::default(),
+            case_sensitive: true,
+        }
+    }
+}
+
+/// Parse a program, reading the files of `.include` directives relative to the working directory.
+/// Use [`parse_result`] to get the error on failure, it is only logged here.
+pub fn parse(input: &str) -> Option<Program> {
+    parse_result(input).map_err(|e| log::error!("{}", e)).ok()
+}
+
+/// The origin of a line of the source once `.include` directives are expanded.
+#[derive(Debug, Clone, PartialEq)]
+struct SourceLine {
+    /// The included file, or `None` for the parsed input itself.
+    file: Option<String>,
+    /// The 1-based line within the file.
+    line: usize,
+}
+
+/// Replace every `.include "path"` line with the (recursively expanded) source of the included file,
+/// recording the origin of every line of the `output` in `lines`.
+/// Paths within an included file are relative to the directory of that file.
+/// The `stack` holds the paths currently being included, to detect include cycles.
+fn expand_includes(
+    input: &str,
+    resolver: IncludeResolver,
+    stack: &mut Vec<String>,
+    output: &mut String,
+    lines: &mut Vec<SourceLine>,
+) -> Result<(), ParseError> {
+    for (index, line) in input.lines().enumerate() {
+        let Some(rest) = line.trim_start().strip_prefix(".include") else {
+            output.push_str(line);
+            output.push('\n');
+            lines.push(SourceLine {
+                file: stack.last().cloned(),
+                line: index + 1,
+            });
+            continue;
+        };
+        let error = |message: String| ParseError {
+            message,
+            file: stack.last().cloned(),
+            line: index + 1,
+            column: line.len() - line.trim_start().len() + 1,
+        };
+        let argument = rest.split('#').next().unwrap().trim();
+        let path = argument
+            .strip_prefix('"')
+            .and_then(|path| path.strip_suffix('"'))
+            .ok_or_else(|| error(format!("Expected a quoted path to include: {}", argument)))?;
+        let path = match stack
+            .last()
+            .and_then(|including| Path::new(including).parent())
+        {
+            Some(dir) => dir.join(path).to_string_lossy().into_owned(),
+            None => path.to_string(),
+        };
+        let path = path.as_str();
+        if stack.iter().any(|included| included == path) {
+            return Err(error(format!("Include cycle: {}", path)));
+        }
+        let source =
+            resolver(path).map_err(|e| error(format!("Failed to include {}: {}", path, e)))?;
+        stack.push(path.to_string());
+        expand_includes(&source, resolver, stack, output, lines)?;
+        stack.pop();
+    }
+    Ok(())
+}
+
+/// The byte index of the `#` starting the comment of a line, if any,
+/// skipping any `#` within a string literal.
+fn comment_start(line: &str) -> Option<usize> {
+    let mut in_string = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Collect the `#assert $register == value` comments of the source,
+/// where the value is a decimal (possibly negative), hexadecimal or binary 32-bit literal.
+/// Each assertion is located by the origin of its line in `lines`.
+fn parse_assertions(input: &str, lines: &[SourceLine]) -> Result<Vec<Assertion>, ParseError> {
+    let mut assertions = Vec::new();
+    for (line, origin) in input.lines().zip(lines) {
+        let Some(start) = comment_start(line) else {
+            continue;
+        };
+        let Some(condition) = line[start..].strip_prefix("#assert ") else {
+            continue;
+        };
+        let error = |message: String| ParseError {
+            message,
+            file: origin.file.clone(),
+            line: origin.line,
+            column: start + 1,
+        };
+        let condition = condition.trim();
+        let invalid = || error(format!("Invalid assertion: {}", condition));
+        let (register, value) = condition.split_once("==").ok_or_else(invalid)?;
+        let register = Register::try_from(register.trim()).map_err(|e| error(e.message))?;
+        let value = value.trim();
+        let (negative, literal) = match value.strip_prefix('-') {
+            Some(literal) => (true, literal),
+            None => (false, value),
+        };
+        let magnitude = if let Some(hex) = literal.strip_prefix("0x") {
+            u32::from_str_radix(hex, 16)
+        } else if let Some(bin) = literal.strip_prefix("0b") {
+            u32::from_str_radix(bin, 2)
+        } else {
+            literal.parse()
+        };
+        let magnitude = magnitude.map_err(|_| invalid())?;
+        assertions.push(Assertion {
+            register,
+            expected: if negative {
+                magnitude.wrapping_neg()
+            } else {
+                magnitude
+            },
+            file: origin.file.clone(),
+            line: origin.line,
+        });
+    }
+    Ok(assertions)
+}
+
+/// Parse a program, reading the files of `.include` directives relative to the working directory.
+pub fn parse_result(input: &str) -> Result<Program, ParseError> {
+    parse_with_resolver(input, &|path| std::fs::read_to_string(path))
+}
+
+/// Parse a program, reading the files of `.include` directives through `resolver`.
+pub fn parse_with_resolver(input: &str, resolver: IncludeResolver) -> Result<Program, ParseError> {
+    parse_with_options(input, resolver, &ParseOptions::default())
+}
+
+/// Parse a program, reading the files of `.include` directives through `resolver`
+/// and accepting only the instructions allowed by `options`.
+pub fn parse_with_options(
+    input: &str,
+    resolver: IncludeResolver,
+    options: &ParseOptions,
+) -> Result<Program, ParseError> {
+    let (mut expanded, mut lines) = (String::new(), Vec::new());
+    expand_includes(input, resolver, &mut Vec::new(), &mut expanded, &mut lines)?;
+    let input = expanded.as_str();
+    let assertions = parse_assertions(input, &lines)?;
+    parse_expanded(input, options, assertions).map_err(|mut error| {
+        // Locate the error in the file its line was included from
+        if let Some(origin) = error.line.checked_sub(1).and_then(|index| lines.get(index)) {
+            error.file.clone_from(&origin.file);
+            error.line = origin.line;
+        }
+        error
+    })
+}
+
+/// Parse a program whose `.include` directives are expanded, with the `assertions` of its comments.
+fn parse_expanded(
+    input: &str,
+    options: &ParseOptions,
+    assertions: Vec<Assertion>,
+) -> Result<Program, ParseError> {
+    let pairs = MainParser::parse(Rule::program, input).map_err(ParseError::from)?;
+    assert_eq!(pairs.clone().count(), 1);
+    let program = pairs.clone().next().unwrap();
+    let pairs = program.into_inner();
+    log::trace!("{}", "======= PAIRS =======".blue());
+    for pair in pairs.clone() {
+        log::trace!("{}", "------- PAIR -------".cyan());
+        log::trace!("Rule:    {:?}", pair.as_rule());
+        log::trace!("Span:    {:?}", pair.as_span());
+        log::trace!("Text:    '{}'", pair.as_str().trim().yellow());
+        log::trace!("Inner:   {:?}", pair.into_inner().collect::<Vec<_>>());
+    }
+    log::trace!("{}", "======= PROGRAM =======".blue());
+    let mut prog = Program {
+        data_section: DataSection {
+            initialized: Vec::new(),
+            read_only: Vec::new(),
+            uninitialized: Vec::new(),
+            global_labels: Vec::new(),
+            base: None,
+        },
+        text_section: TextSection {
+            blocks: Vec::new(),
+            global_labels: Vec::new(),
+            base: None,
+        },
+        assertions,
+    };
+    // Code before any section directive belongs to the `.text` section.
+    // Each section directive switches where the following statements are accumulated,
+    // so the sections may appear in any order and any number of times.
+    let mut current_section: Option<Section> = None;
+    // Global symbols may be declared before their definition,
+    // so they are assigned to a section once all labels are known.
+    let mut global_labels: Vec<String> = Vec::new();
+    let mut local_labels = LocalLabels::default();
+    // Forward references to local labels, checked to be defined once all labels are known.
+    let mut forward_refs: Vec<(String, usize, ParseError)> = Vec::new();
+    // Constants defined by `.equ` or `.set`, substituted where used as an argument.
+    let mut constants: HashMap<String, Immediate> = HashMap::new();
+
+    // Unnamed initial block
+    let mut current_block: Block = Block {
+        label: String::new(),
+        instructions: Vec::new(),
+    };
+
+    for pair in pairs {
+        match pair.as_rule() {
+            Rule::directive => {
+                let mut inner = pair.into_inner();
+                let inner_first = inner.next().unwrap();
+                log::trace!("Directive: {:?}", inner);
+                match inner_first.as_rule() {
+                    Rule::section_directive => {
+                        let section = match inner_first.as_str().trim() {
+                            ".data" => Section::Data,
+                            ".rdata" => Section::Rdata,
+                            ".bss" => Section::Bss,
+                            ".text" => Section::Text,
+                            _ => unreachable!(),
+                        };
+                        log::trace!("Section: {:?}", section);
+                        if let Some(operand) = inner.next() {
+                            let base = parse_section_base(&section, &operand)?;
+                            let placed = match section {
+                                Section::Data => &mut prog.data_section.base,
+                                Section::Text => &mut prog.text_section.base,
+                                _ => unreachable!(),
+                            };
+                            if placed.is_some_and(|placed| placed != base) {
+                                return Err(ParseError::new(format!(
+                                    "Section {} is already placed at {}",
+                                    section.show(),
+                                    placed.unwrap()
+                                ))
+                                .at(&operand));
                             }
-                            Rule::text_directive => {
-                                let directive = inner_first.as_str().trim();
-                                let symbol = match directive {
-                                    ".global" | ".globl" => {
-                                        let value = inner.next().unwrap().as_str().to_string();
-                                        value
-                                    }
-                                    _ => unreachable!(),
-                                };
-                                prog.text_section.global_labels.push(symbol);
+                            *placed = Some(base);
+                        }
+                        current_section = Some(section);
+                    }
+                    Rule::text_directive => {
+                        let directive = inner_first.as_str().trim();
+                        let symbol = match directive {
+                            ".global" | ".globl" => {
+                                let value = inner.next().unwrap().as_str().to_string();
+                                value
                             }
                             _ => unreachable!(),
+                        };
+                        global_labels.push(symbol);
+                    }
+                    Rule::const_directive => {
+                        let name = inner.next().unwrap();
+                        let value = parse_imm(&inner.next().unwrap(), 32)?;
+                        log::trace!("Constant: {} = {}", name.as_str(), value);
+                        if constants.insert(name.as_str().to_string(), value).is_some() {
+                            return Err(ParseError::new(format!(
+                                "Constant redefined: {}",
+                                name.as_str()
+                            ))
+                            .at(&name));
                         }
                     }
-                    Rule::label => {
-                        let mut inner = pair.into_inner();
-                        let label = inner
-                            .next()
-                            .expect("Expected label identifier")
-                            .as_str()
-                            .to_string();
-                        let source = inner.as_str().trim().to_string();
-                        log::trace!("Label: {:?}, source: {}", label, source.clone().yellow());
-                        if current_section == Some(Section::Data) {
-                            if let Some(inner_directive) = inner.next() {
-                                let directive = inner_directive.as_str().trim();
-                                let data = match directive {
-                                    ".asciiz" => {
-                                        let str = unescape_string(inner.next().unwrap().as_str());
-                                        let data = str.into_bytes();
-                                        // remove first and last character (")
-                                        let mut data = data[1..data.len() - 1].to_vec();
-                                        data.push(0); // null-terminated string
-                                        log::trace!(".asciiz {:?}", &data);
-                                        StaticData {
-                                            label,
-                                            source,
-                                            data,
-                                        }
-                                    }
-                                    ".ascii" => {
-                                        let str = unescape_string(inner.next().unwrap().as_str());
-                                        let data = str.into_bytes();
-                                        // remove first and last character (")
-                                        let data = data[1..data.len() - 1].to_vec();
-                                        log::trace!(".ascii {:?}", &data);
-                                        StaticData {
-                                            label,
-                                            source,
-                                            data,
-                                        }
-                                    }
-                                    ".word" => {
-                                        let word: i32 =
-                                            inner.next().unwrap().as_str().parse().unwrap();
-                                        log::trace!(".word {:?}", word);
-                                        StaticData {
-                                            label,
-                                            source,
-                                            data: word.to_le_bytes().to_vec(),
-                                        }
+                    _ => unreachable!(),
+                }
+            }
+            Rule::label => {
+                let mut inner = pair.into_inner();
+                let identifier = inner.next().expect("Expected label identifier");
+                let label = match identifier.as_rule() {
+                    Rule::local_label => local_labels.define(identifier.as_str()),
+                    _ => identifier.as_str().to_string(),
+                };
+                let source = inner.as_str().trim().to_string();
+                log::trace!("Label: {:?}, source: {}", label, source.clone().yellow());
+                if matches!(current_section, Some(Section::Data | Section::Rdata)) {
+                    if let Some(inner_directive) = inner.next() {
+                        let directive = inner_directive.as_str().trim();
+                        let repeated = inner.clone().find(|arg| arg.as_rule() == Rule::repeat);
+                        if let (Some(arg), false) =
+                            (repeated, matches!(directive, ".word" | ".byte"))
+                        {
+                            return Err(ParseError::new(format!(
+                                "Repeated data is only supported by .word and .byte: {}",
+                                source
+                            ))
+                            .at(&arg));
+                        }
+                        let data = match directive {
+                            // Every string operand is stored in order, each null-terminated by `.asciiz`
+                            ".asciiz" | ".ascii" => {
+                                let mut data = Vec::new();
+                                for arg in inner {
+                                    if arg.as_rule() != Rule::string {
+                                        return Err(ParseError::new(format!(
+                                            "Expected a string: {}",
+                                            arg.as_str()
+                                        ))
+                                        .at(&arg));
                                     }
-                                    ".byte" => {
-                                        let byte: u8 =
-                                            inner.next().unwrap().as_str().parse().unwrap();
-                                        log::trace!(".byte {:?}", byte);
-                                        StaticData {
-                                            label,
-                                            source,
-                                            data: vec![byte],
-                                        }
+                                    data.extend(unescape_string(&arg)?);
+                                    if directive == ".asciiz" {
+                                        data.push(0);
                                     }
-                                    _ => unreachable!(),
-                                };
-                                prog.data_section.initialized.push(data);
-                            } else {
-                                unreachable!();
+                                }
+                                log::trace!("{} {:?}", directive, &data);
+                                StaticData {
+                                    label,
+                                    source,
+                                    data,
+                                    element_size: 1,
+                                }
                             }
-                        } else if current_section == Some(Section::Text) {
-                            log::trace!("Pushing block: {:?}", current_block);
-                            prog.text_section.blocks.push(current_block);
-                            current_block = Block {
-                                label,
-                                instructions: Vec::new(),
-                            };
-                        } else {
-                            unreachable!();
-                        }
-                    }
-                    Rule::instruction => {
-                        log::trace!("Instruction: {:?}", pair);
-                        let mut inner = pair.into_inner();
-                        let kind = InstructionKind::from(inner.next().unwrap().as_str());
-                        let mut args: Vec<InstructionArg> = Vec::new();
-                        for arg in inner {
-                            log::trace!("  - Arg: {:?}", arg);
-                            match arg.as_rule() {
-                                Rule::register => args
-                                    .push(InstructionArg::Register(Register::from(arg.as_str()))),
-                                Rule::offset => {
-                                    let mut inner = arg.into_inner();
-                                    let immediate = inner.next().unwrap();
-                                    let register = Register::from(inner.next().unwrap().as_str());
-                                    args.push(InstructionArg::RegisterOffset(
-                                        parse_imm(immediate),
-                                        register,
-                                    ));
+                            ".word" => {
+                                let mut data = Vec::new();
+                                for arg in inner {
+                                    let (value, count) = parse_repeat(arg)?;
+                                    let word = parse_data_value(&value, 32, "Word")?;
+                                    log::trace!(".word {:?} x {}", word, count);
+                                    data.extend(word.to_le_bytes().repeat(count));
+                                }
+                                StaticData {
+                                    label,
+                                    source,
+                                    data,
+                                    element_size: size_of::<Word>(),
                                 }
-                                Rule::immediate => {
-                                    args.push(InstructionArg::Immediate(parse_imm(arg)))
+                            }
+                            ".byte" => {
+                                let mut data = Vec::new();
+                                for arg in inner {
+                                    let (value, count) = parse_repeat(arg)?;
+                                    let byte = parse_data_value(&value, 8, "Byte")? as u8;
+                                    log::trace!(".byte {:?} x {}", byte, count);
+                                    data.extend(vec![byte; count]);
                                 }
-                                Rule::identifier => {
-                                    args.push(InstructionArg::Label(arg.as_str().to_string()))
+                                StaticData {
+                                    label,
+                                    source,
+                                    data,
+                                    element_size: 1,
                                 }
-                                _ => unreachable!(),
                             }
+                            ".space" => {
+                                let size = parse_imm(&inner.next().unwrap(), 32)? as usize;
+                                if let Some(extra) = inner.next() {
+                                    return Err(ParseError::new(format!(
+                                        "Expected a single size: {}",
+                                        source
+                                    ))
+                                    .at(&extra));
+                                }
+                                log::trace!(".space {:?}", size);
+                                StaticData {
+                                    label,
+                                    source,
+                                    data: vec![0; size],
+                                    element_size: 1,
+                                }
+                            }
+                            _ => unreachable!(),
+                        };
+                        if current_section == Some(Section::Rdata) {
+                            prog.data_section.read_only.push(data);
+                        } else {
+                            prog.data_section.initialized.push(data);
                         }
-                        log::trace!("  - Kind: {:?}", kind);
-                        log::trace!("  - Args: {:?}", args);
-
-                        let instr = Instruction { kind, args };
-                        current_block.instructions.push(instr);
+                    } else {
+                        unreachable!();
                     }
-                    Rule::EOI => {}
-                    _ => unreachable!(),
+                } else if current_section == Some(Section::Bss) {
+                    let directive = inner.next();
+                    let size = match (directive.as_ref().map(|d| d.as_str().trim()), inner.next()) {
+                        (Some(".space"), Some(size)) if size.as_rule() == Rule::immediate => {
+                            parse_imm(&size, 32)? as usize
+                        }
+                        _ => {
+                            return Err(ParseError::new(format!(
+                                "Expected .space in the .bss section: {}",
+                                source
+                            ))
+                            .at(&identifier))
+                        }
+                    };
+                    log::trace!(".bss {:?}", size);
+                    let data = ReservedData {
+                        label,
+                        source,
+                        size,
+                    };
+                    prog.data_section.uninitialized.push(data);
+                } else {
+                    log::trace!("Pushing block: {:?}", current_block);
+                    prog.text_section.blocks.push(current_block);
+                    current_block = Block {
+                        label,
+                        instructions: Vec::new(),
+                    };
                 }
             }
-            log::trace!("Pushing final block: {:?}", current_block);
-            prog.text_section.blocks.push(current_block);
+            Rule::instruction => {
+                log::trace!("Instruction: {:?}", pair);
+                if matches!(
+                    current_section,
+                    Some(Section::Data | Section::Rdata | Section::Bss)
+                ) {
+                    return Err(ParseError::new(format!(
+                        "Instruction outside of the .text section: {}",
+                        pair.as_str().trim()
+                    ))
+                    .at(&pair));
+                }
+                let source = pair.as_str().trim();
+                let mut inner = pair.into_inner();
+                let mnemonic = inner.next().unwrap();
+                if !options.allow_pseudo {
+                    if let Some(equivalent) = pseudo_equivalent(mnemonic.as_str()) {
+                        return Err(ParseError::new(format!(
+                            "Pseudo instruction {} is not allowed, use {} instead",
+                            mnemonic.as_str(),
+                            equivalent
+                        ))
+                        .at(&mnemonic));
+                    }
+                }
+                let comparison = comparison_branch(mnemonic.as_str());
+                let remainder = remainder_division(mnemonic.as_str());
+                let kind = match (&comparison, &remainder) {
+                    (Some(_), _) | (_, Some(_)) => None,
+                    (None, None) => Some(
+                        InstructionKind::try_from(mnemonic.as_str())
+                            .map_err(|e| e.at(&mnemonic))?,
+                    ),
+                };
+                if let Some(kind) = kind.as_ref().filter(|kind| kind.isa() > options.isa) {
+                    return Err(ParseError::new(format!(
+                        "Instruction {} requires {}, but the selected profile is {}",
+                        kind.show(),
+                        kind.isa(),
+                        options.isa
+                    ))
+                    .at(&mnemonic));
+                }
+                let (bits, signedness) = immediate_range(kind.as_ref());
+                let mut args: Vec<InstructionArg> = Vec::new();
+                for arg in inner {
+                    log::trace!("  - Arg: {:?}", arg);
+                    match arg.as_rule() {
+                        // The second operand of `mfc0`/`mtc0` is a coprocessor 0 register
+                        Rule::register
+                            if args.len() == 1
+                                && kind.as_ref().is_some_and(InstructionKind::is_cp0_move) =>
+                        {
+                            let kind = kind.as_ref().unwrap();
+                            args.push(InstructionArg::Register(parse_cp0_register(&arg, kind)?))
+                        }
+                        Rule::register => {
+                            args.push(InstructionArg::Register(parse_register(&arg)?))
+                        }
+                        Rule::offset => {
+                            let mut inner = arg.into_inner();
+                            let base = inner.next().unwrap();
+                            let register = parse_register(&inner.next().unwrap())?;
+                            let arg = match base.as_rule() {
+                                Rule::identifier => match constants.get(base.as_str()) {
+                                    Some(value) => InstructionArg::RegisterOffset(
+                                        check_width(*value, 16, Signedness::Any, &base)?,
+                                        register,
+                                    ),
+                                    None => InstructionArg::LabelOffset(
+                                        base.as_str().to_string(),
+                                        register,
+                                    ),
+                                },
+                                _ => InstructionArg::RegisterOffset(parse_offset(&base)?, register),
+                            };
+                            args.push(arg);
+                        }
+                        Rule::immediate => args.push(InstructionArg::Immediate(parse_literal(
+                            &arg, bits, signedness,
+                        )?)),
+                        Rule::identifier => match constants.get(arg.as_str()) {
+                            Some(value) => args.push(InstructionArg::Immediate(check_width(
+                                *value, bits, signedness, &arg,
+                            )?)),
+                            None => args.push(InstructionArg::Label(arg.as_str().to_string())),
+                        },
+                        Rule::local_ref => {
+                            let reference = arg.as_str();
+                            let (number, direction) = reference.split_at(reference.len() - 1);
+                            let count = local_labels.count(number);
+                            let index = if direction == "f" {
+                                let error = ParseError::new(format!(
+                                    "Undefined local label: {}",
+                                    reference
+                                ));
+                                forward_refs.push((number.to_string(), count, error.at(&arg)));
+                                count
+                            } else if count > 0 {
+                                count - 1
+                            } else {
+                                return Err(ParseError::new(format!(
+                                    "Undefined local label: {}",
+                                    reference
+                                ))
+                                .at(&arg));
+                            };
+                            args.push(InstructionArg::Label(LocalLabels::name(number, index)))
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                log::trace!("  - Kind: {:?}", kind);
+                log::trace!("  - Args: {:?}", args);
 
-            Some(prog)
+                if let Some((set, swap, branch)) = comparison {
+                    // Expand into `set $at, $s, $t` and `branch $at, $zero, label`
+                    let [InstructionArg::Register(s), InstructionArg::Register(t), target] =
+                        args.as_slice()
+                    else {
+                        return Err(ParseError::new(format!(
+                            "Expected two registers and a label: {}",
+                            source
+                        ))
+                        .at(&mnemonic));
+                    };
+                    let (lhs, rhs) = if swap { (t, s) } else { (s, t) };
+                    current_block.instructions.push(Instruction {
+                        kind: set,
+                        args: vec![
+                            InstructionArg::Register(Register::At),
+                            InstructionArg::Register(*lhs),
+                            InstructionArg::Register(*rhs),
+                        ],
+                    });
+                    current_block.instructions.push(Instruction {
+                        kind: branch,
+                        args: vec![
+                            InstructionArg::Register(Register::At),
+                            InstructionArg::Register(Register::Zero),
+                            target.clone(),
+                        ],
+                    });
+                } else if let Some(division) = remainder {
+                    // Expand into `div $s, $t` and `mfhi $d`
+                    let [InstructionArg::Register(d), s, t] = args.as_slice() else {
+                        return Err(ParseError::new(format!(
+                            "Expected a destination register and two operands: {}",
+                            source
+                        ))
+                        .at(&mnemonic));
+                    };
+                    current_block.instructions.push(Instruction {
+                        kind: division,
+                        args: vec![s.clone(), t.clone()],
+                    });
+                    current_block.instructions.push(Instruction {
+                        kind: InstructionKind::Mfhi,
+                        args: vec![InstructionArg::Register(*d)],
+                    });
+                } else if let Some(index) = args
+                    .iter()
+                    .position(|arg| matches!(arg, InstructionArg::LabelOffset(..)))
+                {
+                    // The address of a label does not fit in the 16-bit offset,
+                    // expand into `la $at, label`, `addu $at, $at, $r` and `op $t, 0($at)`
+                    let InstructionArg::LabelOffset(label, register) = std::mem::replace(
+                        &mut args[index],
+                        InstructionArg::RegisterOffset(0, Register::At),
+                    ) else {
+                        unreachable!()
+                    };
+                    current_block.instructions.push(Instruction {
+                        kind: InstructionKind::La,
+                        args: vec![
+                            InstructionArg::Register(Register::At),
+                            InstructionArg::Label(label),
+                        ],
+                    });
+                    current_block.instructions.push(Instruction {
+                        kind: InstructionKind::Addu,
+                        args: vec![
+                            InstructionArg::Register(Register::At),
+                            InstructionArg::Register(Register::At),
+                            InstructionArg::Register(register),
+                        ],
+                    });
+                    current_block.instructions.push(Instruction {
+                        kind: kind.unwrap(),
+                        args,
+                    });
+                } else {
+                    let instr = Instruction {
+                        kind: kind.unwrap(),
+                        args,
+                    };
+                    current_block.instructions.push(instr);
+                }
+            }
+            Rule::EOI => {}
+            _ => unreachable!(),
         }
-        Err(e) => {
-            println!("{}", e.to_string().light_red());
-            None
+    }
+    log::trace!("Pushing final block: {:?}", current_block);
+    prog.text_section.blocks.push(current_block);
+
+    for (number, index, error) in forward_refs {
+        if index >= local_labels.count(&number) {
+            return Err(error);
         }
     }
+
+    if !options.case_sensitive {
+        normalize_label_case(&mut prog, &mut global_labels)?;
+    }
+
+    for symbol in global_labels {
+        let is_data = prog
+            .data_section
+            .initialized
+            .iter()
+            .chain(&prog.data_section.read_only)
+            .any(|data| data.label == symbol)
+            || prog
+                .data_section
+                .uninitialized
+                .iter()
+                .any(|data| data.label == symbol);
+        if is_data {
+            prog.data_section.global_labels.push(symbol);
+        } else {
+            prog.text_section.global_labels.push(symbol);
+        }
+    }
+
+    Ok(prog)
 }
 
-/// immediate  = @{ hex | binary | integer }
+/// The real instructions to use instead of a pseudo instruction, if `mnemonic` is one.
+fn pseudo_equivalent(mnemonic: &str) -> Option<&'static str> {
+    Some(match mnemonic {
+        "li" => "`ori $d, $zero, immediate` or `lui` and `ori`",
+        "la" => "`lui` and `ori` with the label address",
+        "move" => "`addu $d, $s, $zero`",
+        "rol" => "`rotr $d, $s, 32 - shamt`",
+        "ror" => "`rotr $d, $s, shamt`",
+        "blt" | "bltu" | "bge" | "bgeu" => {
+            "`slt`/`sltu $at, $s, $t` and `bne`/`beq $at, $zero, label`"
+        }
+        "bgt" | "bgtu" | "ble" | "bleu" => {
+            "`slt`/`sltu $at, $t, $s` and `bne`/`beq $at, $zero, label`"
+        }
+        "rem" | "remu" => "`div`/`divu $s, $t` and `mfhi $d`",
+        _ => return None,
+    })
+}
+
+/// The expansion of the comparison branch pseudo instructions `blt`, `ble`, `bgt` and `bge`
+/// and their unsigned `u` variants: the instruction setting `$at` to `$s < $t`,
+/// whether `$s` and `$t` are swapped, and the branch on `$at`.
+fn comparison_branch(mnemonic: &str) -> Option<(InstructionKind, bool, InstructionKind)> {
+    let (set, comparison) = match mnemonic.strip_suffix('u') {
+        Some(comparison) => (InstructionKind::Sltu, comparison),
+        None => (InstructionKind::Slt, mnemonic),
+    };
+    let (swap, branch) = match comparison {
+        "blt" => (false, InstructionKind::Bne),
+        "bge" => (false, InstructionKind::Beq),
+        "bgt" => (true, InstructionKind::Bne),
+        "ble" => (true, InstructionKind::Beq),
+        _ => return None,
+    };
+    Some((set, swap, branch))
+}
+
+/// The division of the remainder pseudo instructions `rem` and `remu`,
+/// followed by `mfhi` to move the remainder into the destination register.
+fn remainder_division(mnemonic: &str) -> Option<InstructionKind> {
+    match mnemonic {
+        "rem" => Some(InstructionKind::Div),
+        "remu" => Some(InstructionKind::Divu),
+        _ => None,
+    }
+}
+
+fn parse_register(pair: &Pair<Rule>) -> Result<Register, ParseError> {
+    Register::try_from(pair.as_str()).map_err(|e| e.at(pair))
+}
+
+/// Parse the coprocessor 0 register of `mfc0` or `mtc0`, spelled by its number `$N`.
+/// Only the modeled registers are accepted, and `mtc0` cannot write the read-only `BadVAddr` (`$8`).
+fn parse_cp0_register(pair: &Pair<Rule>, kind: &InstructionKind) -> Result<Register, ParseError> {
+    let modeled: &[u8] = match kind {
+        InstructionKind::Mfc0 => &[8, 12, 13, 14],
+        _ => &[12, 13, 14],
+    };
+    let number = pair.as_str()[1..].parse::<u8>().ok();
+    match number.filter(|number| modeled.contains(number)) {
+        Some(number) => Ok(Register::decode(number).unwrap()),
+        None => Err(ParseError::new(format!(
+            "Expected a coprocessor 0 register {} for {}: {}",
+            modeled
+                .iter()
+                .map(|number| format!("${}", number))
+                .collect::<Vec<_>>()
+                .join(", "),
+            kind.show(),
+            pair.as_str()
+        ))
+        .at(pair)),
+    }
+}
+
+/// How the literal of an immediate is bounded for its `bits` bits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Signedness {
+    /// From 0 to `2^bits - 1`.
+    Unsigned,
+    /// From `-2^(bits-1)` to `2^(bits-1) - 1`.
+    Signed,
+    /// Either signed or unsigned, from `-2^(bits-1)` to `2^bits - 1`.
+    Any,
+}
+
+/// The width and signedness of the immediates of an instruction:
+/// `li` loads a full word, see [`InstructionKind::has_signed_immediate`] for the 16-bit immediates.
+fn immediate_range(kind: Option<&InstructionKind>) -> (u32, Signedness) {
+    match kind {
+        Some(InstructionKind::Li) => (32, Signedness::Any),
+        Some(kind) if kind.has_signed_immediate() => (16, Signedness::Signed),
+        _ => (16, Signedness::Unsigned),
+    }
+}
+
+/// immediate  = @{ "-"? ~ (hex | binary | integer) }
 // integer    = @{ (ASCII_DIGIT)+ }
 // hex        = @{ "0x" ~ (ASCII_HEX_DIGIT)+ }
 // binary     = @{ "0b" ~ ("0" | "1")+ }``
-fn parse_imm(arg: pest::iterators::Pair<Rule>) -> Immediate {
-    let arg = arg.as_str();
-    if let Some(hex) = arg.strip_prefix("0x") {
-        Immediate::from_str_radix(hex, 16).unwrap()
-    } else if let Some(bin) = arg.strip_prefix("0b") {
-        Immediate::from_str_radix(bin, 2).unwrap()
+/// Parse a hexadecimal, binary or decimal literal, which must fit in `bits` bits.
+fn parse_imm(pair: &Pair<Rule>, bits: u32) -> Result<Immediate, ParseError> {
+    parse_literal(pair, bits, Signedness::Unsigned)
+}
+
+/// Parse a literal bounded by `signedness`,
+/// storing a negative literal as its two's complement in `bits` bits.
+fn parse_literal(
+    pair: &Pair<Rule>,
+    bits: u32,
+    signedness: Signedness,
+) -> Result<Immediate, ParseError> {
+    let literal = pair.as_str();
+    let (negative, digits) = match literal.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, literal),
+    };
+    let magnitude = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
+    } else {
+        digits.parse()
+    };
+    let (min, max) = match signedness {
+        Signedness::Unsigned => (0, (1 << bits) - 1),
+        Signedness::Signed => (-(1 << (bits - 1)), (1 << (bits - 1)) - 1),
+        Signedness::Any => (-(1 << (bits - 1)), (1 << bits) - 1),
+    };
+    match magnitude.map(|magnitude| if negative { -magnitude } else { magnitude }) {
+        Ok(value) if (min..=max).contains(&value) => Ok((value & ((1 << bits) - 1)) as Immediate),
+        _ => Err(out_of_range(literal, bits, signedness).at(pair)),
+    }
+}
+
+/// Parse a load or store offset, a literal fitting in 16 bits or a negative one down to -32768,
+/// stored as its 16-bit two's complement.
+fn parse_offset(pair: &Pair<Rule>) -> Result<Immediate, ParseError> {
+    parse_literal(pair, 16, Signedness::Any)
+}
+
+/// The largest count of repeated `value:count` data, at most 4 MiB of words.
+const MAX_REPEAT: u32 = 1 << 20;
+
+/// Split a repeated `value:count` data argument into the value and the count,
+/// or return any other argument with a count of 1.
+fn parse_repeat(pair: Pair<Rule>) -> Result<(Pair<Rule>, usize), ParseError> {
+    if pair.as_rule() != Rule::repeat {
+        return Ok((pair, 1));
+    }
+    let mut inner = pair.into_inner();
+    let value = inner.next().unwrap();
+    let count = inner.next().unwrap();
+    match parse_imm(&count, 32) {
+        Ok(n) if (1..=MAX_REPEAT).contains(&n) => Ok((value, n as usize)),
+        _ => Err(ParseError::new(format!(
+            "Invalid repeat count, expected 1 to {}: {}",
+            MAX_REPEAT,
+            count.as_str()
+        ))
+        .at(&count)),
+    }
+}
+
+/// Parse the value of a `.word` or `.byte` directive, a literal which must fit in `bits` bits,
+/// storing a negative literal as its two's complement.
+fn parse_data_value(pair: &Pair<Rule>, bits: u32, name: &str) -> Result<Immediate, ParseError> {
+    let value = match pair.as_rule() {
+        Rule::immediate => parse_literal(pair, bits, Signedness::Any).ok(),
+        _ => None,
+    };
+    value.ok_or_else(|| {
+        ParseError::new(format!("{} out of range: {}", name, pair.as_str())).at(pair)
+    })
+}
+
+/// Check that the value of a constant used at `pair` fits in `bits` bits,
+/// or in `bits - 1` bits if the immediate is signed since constants are never negative.
+fn check_width(
+    value: Immediate,
+    bits: u32,
+    signedness: Signedness,
+    pair: &Pair<Rule>,
+) -> Result<Immediate, ParseError> {
+    let width = if signedness == Signedness::Signed {
+        bits - 1
+    } else {
+        bits
+    };
+    if (value as u64) >> width == 0 {
+        Ok(value)
     } else {
-        arg.parse().unwrap()
+        Err(out_of_range(pair.as_str(), bits, signedness).at(pair))
     }
 }
 
-fn unescape_string(s: &str) -> String {
-    let mut result = String::new();
-    let mut chars = s.chars();
+fn out_of_range(literal: &str, bits: u32, signedness: Signedness) -> ParseError {
+    let signed = if signedness == Signedness::Signed {
+        "signed "
+    } else {
+        ""
+    };
+    ParseError::new(format!(
+        "Immediate out of range for {}{} bits: {}",
+        signed, bits, literal
+    ))
+}
+
+/// Unescape a quoted string literal into its raw bytes, without the surrounding quotes.
+fn unescape_string(pair: &Pair<Rule>) -> Result<Vec<u8>, ParseError> {
+    let s = pair.as_str();
+    let mut result = Vec::new();
+    let mut chars = s[1..s.len() - 1].chars();
+    let mut buf = [0; 4];
     while let Some(c) = chars.next() {
         if c == '\\' {
             match chars.next() {
-                Some('n') => result.push('\n'),
-                Some('t') => result.push('\t'),
-                Some('r') => result.push('\r'),
-                Some('\\') => result.push('\\'),
-                Some('"') => result.push('"'),
-                Some('0') => result.push('\0'),
-                Some(c) => result.push(c),
-                None => result.push('\\'),
+                Some('n') => result.push(b'\n'),
+                Some('t') => result.push(b'\t'),
+                Some('r') => result.push(b'\r'),
+                Some('\\') => result.push(b'\\'),
+                Some('"') => result.push(b'"'),
+                Some('0') => result.push(0),
+                Some('x') => {
+                    let digits: String = chars.by_ref().take(2).collect();
+                    if digits.len() != 2 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(ParseError::new(format!(
+                            "Invalid escape sequence: \\x{}",
+                            digits
+                        ))
+                        .at(pair));
+                    }
+                    result.push(u8::from_str_radix(&digits, 16).unwrap());
+                }
+                Some(c) => result.extend_from_slice(c.encode_utf8(&mut buf).as_bytes()),
+                None => result.push(b'\\'),
             }
         } else {
-            result.push(c);
+            result.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
         }
     }
-    result
+    Ok(result)
 }
 
 #[cfg(test)]
 mod test_parser {
-    use super::parse;
+    use super::{parse, parse_result, parse_with_options, parse_with_resolver, ParseOptions};
+    use crate::{
+        address::Address,
+        program::{InstructionArg, IsaProfile, LinkError, Program},
+        registers::Register,
+    };
+    use std::collections::HashMap;
 
     #[test]
     fn hello_world() {
@@ -250,4 +1104,568 @@ mod test_parser {
         let prog = prog.unwrap();
         println!("{}", prog.show());
     }
+
+    #[test]
+    fn global_data_label() {
+        let input = ".globl msg\n.data\nmsg: .asciiz \"hi\"\n.text\n.globl main\nmain:\n\tnop\n";
+        let prog = parse(input).unwrap();
+        assert_eq!(prog.data_section.global_labels, vec!["msg".to_string()]);
+        assert_eq!(prog.text_section.global_labels, vec!["main".to_string()]);
+        assert_eq!(prog.global_labels(), vec!["msg", "main"]);
+    }
+
+    #[test]
+    fn statement_separators() {
+        let prog = parse(".text\nmain:\n\tadd $t0,$t1,$t2 ; sub $t3,$t4,$t5;\n").unwrap();
+        let instructions = prog.text_section.instructions();
+        assert_eq!(instructions.len(), 2);
+        assert_eq!(instructions[0].show(), "add $t0, $t1, $t2");
+        assert_eq!(instructions[1].show(), "sub $t3, $t4, $t5");
+    }
+
+    #[test]
+    fn blank_and_comment_lines() {
+        let input = "\n# header\n\n.text\n\n  # before main\nmain:   # entry\n\n\tnop\n   \n# between\n\tnop # trailing\n\n# footer";
+        let prog = parse(input).unwrap();
+        assert_eq!(prog.text_section.instructions().len(), 2);
+    }
+
+    #[test]
+    fn unknown_register() {
+        let err = parse_result(".text\nmain:\n\tadd $t0, $t99, $t1\n").unwrap_err();
+        assert_eq!(err.message, "Invalid register: $t99");
+        assert_eq!((err.line, err.column), (3, 11));
+    }
+
+    #[test]
+    fn unknown_instruction() {
+        let err = parse_result(".text\nmain:\n\taddd $t0, $t1, $t2\n").unwrap_err();
+        assert_eq!(err.message, "Invalid instruction: addd");
+        assert_eq!((err.line, err.column), (3, 2));
+        assert_eq!(err.to_string(), "3:2: Invalid instruction: addd");
+    }
+
+    #[test]
+    fn numbered_registers() {
+        let prog = parse_result(".text\nmain:\n\tadd $8, $9, $31\n").unwrap();
+        let instruction = &prog.text_section.blocks[1].instructions[0];
+        assert_eq!(
+            instruction.args,
+            vec![
+                InstructionArg::Register(Register::T0),
+                InstructionArg::Register(Register::T1),
+                InstructionArg::Register(Register::Ra),
+            ]
+        );
+        assert!(parse_result(".text\nmain:\n\tadd $8, $9, $32\n").is_err());
+    }
+
+    #[test]
+    fn local_labels() {
+        let prog = parse_result(
+            ".text
+main:
+1:
+\tj 1f
+\tj 1b
+1:
+\tj 1b
+",
+        )
+        .unwrap();
+        let blocks = &prog.text_section.blocks;
+        assert_eq!(blocks[2].label, ".L1_0");
+        assert_eq!(blocks[3].label, ".L1_1");
+        let target = |block: usize, index: usize| blocks[block].instructions[index].args[0].clone();
+        assert_eq!(target(2, 0), InstructionArg::Label(".L1_1".to_string()));
+        assert_eq!(target(2, 1), InstructionArg::Label(".L1_0".to_string()));
+        assert_eq!(target(3, 0), InstructionArg::Label(".L1_1".to_string()));
+
+        // A source label cannot collide with the name of a local label
+        let prog = parse_result(".text\nmain:\n1:\n\tj 1b\nL1_0:\n\tj L1_0\n").unwrap();
+        let labels = prog
+            .text_section
+            .blocks
+            .iter()
+            .map(|block| block.label.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, vec!["", "main", ".L1_0", "L1_0"]);
+
+        let err = parse_result(".text\nmain:\n\tj 2f\n").unwrap_err();
+        assert_eq!(err.to_string(), "3:4: Undefined local label: 2f");
+    }
+
+    #[test]
+    fn include() {
+        let files = HashMap::from([
+            (
+                "helpers.asm",
+                ".text\ndouble:\n\tadd $v0, $a0, $a0\n\tjr $ra\n",
+            ),
+            ("cycle.asm", ".include \"main.asm\"\n"),
+            ("main.asm", ".include \"cycle.asm\"\n"),
+            ("lib/outer.asm", ".text\n.include \"inner.asm\"\n"),
+            (
+                "lib/inner.asm",
+                "inner:\n\tjr $ra\n\taddi $t0, $t0, 99999\n",
+            ),
+        ]);
+        let resolver = |path: &str| {
+            files
+                .get(path)
+                .map(|source| source.to_string())
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "file not found"))
+        };
+
+        let input =
+            ".text\nmain:\n\tli $a0, 21\n\tjal double\n.include \"helpers.asm\" # helpers\n";
+        let prog = parse_with_resolver(input, &resolver).unwrap();
+        let labels = prog
+            .text_section
+            .blocks
+            .iter()
+            .map(|block| block.label.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, vec!["", "main", "double"]);
+        assert_eq!(prog.text_section.blocks[2].instructions.len(), 2);
+
+        let err = parse_with_resolver(".include \"main.asm\"\n", &resolver).unwrap_err();
+        assert_eq!(err.to_string(), "cycle.asm:1:1: Include cycle: main.asm");
+        // Nested includes are relative to the including file, and errors are located within it
+        let err = parse_with_resolver(
+            ".text\nmain:\n\tnop\n.include \"lib/outer.asm\"\n",
+            &resolver,
+        )
+        .unwrap_err();
+        assert_eq!(err.file.as_deref(), Some("lib/inner.asm"));
+        assert_eq!(err.line, 3);
+        let err = parse_with_resolver("\n  .include \"missing.asm\"\n", &resolver).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "2:3: Failed to include missing.asm: file not found"
+        );
+    }
+
+    #[test]
+    fn constants() {
+        let prog = parse_result(
+            ".equ N, 10\n.set STEP 2\n.text\nmain:\n\tli $t0, N\n\taddi $t0, $t0, STEP\n",
+        )
+        .unwrap();
+        let instructions = &prog.text_section.blocks[1].instructions;
+        assert_eq!(
+            instructions[0].args,
+            vec![
+                InstructionArg::Register(Register::T0),
+                InstructionArg::Immediate(10)
+            ]
+        );
+        assert_eq!(instructions[1].args[2], InstructionArg::Immediate(2));
+
+        let err = parse_result(".equ N, 1\n.equ N, 2\n").unwrap_err();
+        assert_eq!(err.to_string(), "2:6: Constant redefined: N");
+    }
+
+    #[test]
+    fn interleaved_sections() {
+        let prog = parse_result(
+            "\tnop\n.text\nmain:\n\tnop\n.data\na: .word 1\n.text\n\tnop\nnext:\n\tnop\n.data\nb: .word 2\n.text\n\tnop\n",
+        )
+        .unwrap();
+        let blocks = &prog.text_section.blocks;
+        let labels = blocks.iter().map(|b| b.label.as_str()).collect::<Vec<_>>();
+        assert_eq!(labels, vec!["", "main", "next"]);
+        let sizes = blocks
+            .iter()
+            .map(|b| b.instructions.len())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![1, 2, 2]);
+        let data = prog.data_section.initialized.iter();
+        let data = data.map(|d| d.label.as_str()).collect::<Vec<_>>();
+        assert_eq!(data, vec!["a", "b"]);
+
+        let err = parse_result(".data\n\tnop\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "2:2: Instruction outside of the .text section: nop"
+        );
+    }
+
+    #[test]
+    fn syntax_error() {
+        let err = parse_result(".text\nmain:\n\tadd $t0, $t1,\n").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 15));
+        assert!(parse(".text\nmain:\n\tadd $t0, $t1,\n").is_none());
+    }
+
+    #[test]
+    fn comparison_branches() {
+        let prog = parse_result(
+            ".text\nmain:\n\tblt $t0, $t1, main\n\tbgeu $t0, $t1, main\n\tbgt $t0, $t1, main\n",
+        )
+        .unwrap();
+        let show = prog.text_section.blocks[1]
+            .instructions
+            .iter()
+            .map(|i| i.show())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            show,
+            vec![
+                "slt $at, $t0, $t1",
+                "bne $at, $zero, main",
+                "sltu $at, $t0, $t1",
+                "beq $at, $zero, main",
+                "slt $at, $t1, $t0",
+                "bne $at, $zero, main",
+            ]
+        );
+
+        let err = parse_result(".text\nmain:\n\tble $t0, 1, main\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:2: Expected two registers and a label: ble $t0, 1, main"
+        );
+    }
+
+    #[test]
+    fn remainder() {
+        let prog =
+            parse_result(".text\nmain:\n\trem $t0, $t1, $t2\n\tremu $t0, $t1, $t2\n").unwrap();
+        let show = prog.text_section.blocks[1]
+            .instructions
+            .iter()
+            .map(|i| i.show())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            show,
+            vec!["div $t1, $t2", "mfhi $t0", "divu $t1, $t2", "mfhi $t0"]
+        );
+
+        let err = parse_result(".text\nmain:\n\trem $t0, $t1\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:2: Expected a destination register and two operands: rem $t0, $t1"
+        );
+    }
+
+    #[test]
+    fn strict() {
+        let no_includes = |_: &str| -> std::io::Result<String> { unreachable!() };
+        let source = ".text\nmain:\n\tmove $t0, $t1\n";
+        let strict = ParseOptions {
+            allow_pseudo: false,
+            ..ParseOptions::default()
+        };
+        let err = parse_with_options(source, &no_includes, &strict).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:2: Pseudo instruction move is not allowed, use `addu $d, $s, $zero` instead"
+        );
+        assert!(parse_with_options(source, &no_includes, &ParseOptions::default()).is_ok());
+        let real = ".text\nmain:\n\taddu $t0, $t1, $zero\n";
+        assert!(parse_with_options(real, &no_includes, &strict).is_ok());
+    }
+
+    #[test]
+    fn cp0_registers() {
+        let prog = parse_result(".text\nmain:\n\tmfc0 $t0, $8\n\tmtc0 $t0, $14\n").unwrap();
+        let instructions = &prog.text_section.blocks[1].instructions;
+        assert_eq!(
+            instructions[0].args[1],
+            InstructionArg::Register(Register::T0)
+        );
+        assert_eq!(
+            instructions[1].args[1],
+            InstructionArg::Register(Register::T6)
+        );
+        assert_eq!(instructions[1].show(), "mtc0 $t0, $14");
+
+        let err = parse_result(".text\nmain:\n\tmfc0 $t0, $t5\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:12: Expected a coprocessor 0 register $8, $12, $13, $14 for mfc0: $t5"
+        );
+        let err = parse_result(".text\nmain:\n\tmfc0 $t0, $9\n").unwrap_err();
+        assert_eq!(err.column, 12);
+        let err = parse_result(".text\nmain:\n\tmtc0 $t0, $8\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:12: Expected a coprocessor 0 register $12, $13, $14 for mtc0: $8"
+        );
+    }
+
+    #[test]
+    fn isa_profile() {
+        let no_includes = |_: &str| -> std::io::Result<String> { unreachable!() };
+        let source = ".text\nmain:\n\tclz $t0, $t1\n";
+        let options = |isa| ParseOptions {
+            isa,
+            ..ParseOptions::default()
+        };
+        let err =
+            parse_with_options(source, &no_includes, &options(IsaProfile::Mips1)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:2: Instruction clz requires MIPS32, but the selected profile is MIPS-I"
+        );
+        assert!(parse_with_options(source, &no_includes, &options(IsaProfile::Mips32)).is_ok());
+        let rotate = ".text\nmain:\n\trotr $t0, $t1, 4\n";
+        let err =
+            parse_with_options(rotate, &no_includes, &options(IsaProfile::Mips32)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:2: Instruction rotr requires MIPS32r2, but the selected profile is MIPS32"
+        );
+        assert!(parse_with_options(rotate, &no_includes, &ParseOptions::default()).is_ok());
+        let rotate = ".text\nmain:\n\trol $t0, $t1, 4\n";
+        let err =
+            parse_with_options(rotate, &no_includes, &options(IsaProfile::Mips1)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:2: Instruction rol requires MIPS32r2, but the selected profile is MIPS-I"
+        );
+    }
+
+    #[test]
+    fn section_base() {
+        let prog =
+            parse_result(".data 0x10020000\nvalue: .word 1\n.text 0x00500000\nmain:\n\tnop\n")
+                .unwrap();
+        assert_eq!(prog.data_section.base, Some(Address::new(0x1002_0000)));
+        assert_eq!(prog.text_section.base, Some(Address::new(0x0050_0000)));
+        assert_eq!(
+            prog.show(),
+            ".data 0x10020000\nvalue: .word 1\n\n.text 0x00500000\nmain:\n    nop\n"
+        );
+        assert_eq!(
+            parse_result(".text\nmain:\n\tnop\n")
+                .unwrap()
+                .text_section
+                .base,
+            None
+        );
+
+        let err = parse_result(".text 0x00400002\nmain:\n\tnop\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:7: Section .text must be placed at a word aligned address from 0x00400000 to 0x0FFFFFFF: 0x00400002"
+        );
+        let err = parse_result(".text 0x00400000\nmain:\n\tnop\n.text 0x00500000\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "4:7: Section .text is already placed at 0x00400000"
+        );
+    }
+
+    #[test]
+    fn case_insensitive_labels() {
+        let no_includes = |_: &str| -> std::io::Result<String> { unreachable!() };
+        let source = ".globl MAIN\n.text\nmain:\n\tj Main\n";
+        let insensitive = ParseOptions {
+            case_sensitive: false,
+            ..ParseOptions::default()
+        };
+        let prog = parse_with_options(source, &no_includes, &insensitive).unwrap();
+        assert_eq!(
+            prog.text_section.blocks[1].instructions[0].args,
+            vec![InstructionArg::Label("main".to_string())]
+        );
+        assert_eq!(prog.text_section.global_labels, vec!["main".to_string()]);
+        assert!(Program::link(vec![prog]).is_ok());
+
+        let prog = parse_with_options(source, &no_includes, &ParseOptions::default()).unwrap();
+        assert_eq!(
+            Program::link(vec![prog]).unwrap_err(),
+            LinkError::UndefinedSymbol("Main".to_string())
+        );
+
+        let collision = ".text\nmain:\n\tnop\nMain:\n\tnop\n";
+        let err = parse_with_options(collision, &no_includes, &insensitive).unwrap_err();
+        assert_eq!(err.to_string(), "Labels Main and main differ only in case");
+        assert!(parse_with_options(collision, &no_includes, &ParseOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn label_offset() {
+        let prog = parse_result(".text\nmain:\n\tlw $t0, arr($t1)\n\tsw $t0, 4($sp)\n").unwrap();
+        let instructions = &prog.text_section.blocks[1].instructions;
+        let shown = instructions.iter().map(|i| i.show()).collect::<Vec<_>>();
+        assert_eq!(
+            shown,
+            [
+                "la $at, arr",
+                "addu $at, $at, $t1",
+                "lw $t0, 0($at)",
+                "sw $t0, 4($sp)"
+            ]
+        );
+        assert_eq!(
+            instructions[3].args[1],
+            InstructionArg::RegisterOffset(4, Register::Sp)
+        );
+    }
+
+    #[test]
+    fn assertions() {
+        let resolver = |path: &str| match path {
+            "check.asm" => Ok("\n#assert $t1 == 2\n".to_string()),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "file not found",
+            )),
+        };
+        let input = ".data
+msg: .asciiz \"see #assertions\" # not #assert $t0 == 9
+.text
+main:
+\tli $t0, 1 #assert $t0 == 1
+.include \"check.asm\"
+#assert $t2 == 3
+";
+        let prog = parse_with_resolver(input, &resolver).unwrap();
+        let locations = prog
+            .assertions
+            .iter()
+            .map(|assertion| format!("{} {}", assertion.location(), assertion.show()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            locations,
+            vec![
+                "line 5 $t0 == 0x1",
+                "check.asm line 2 $t1 == 0x2",
+                "line 7 $t2 == 0x3",
+            ]
+        );
+    }
+
+    #[test]
+    fn hex_escapes() {
+        let prog = parse_result(".data\nmsg: .asciiz \"\\x41\\x42\"\n").unwrap();
+        assert_eq!(
+            prog.data_section.initialized[0].data,
+            vec![0x41, 0x42, 0x00]
+        );
+        let prog = parse_result(".data\nmsg: .ascii \"\\xff\\t\"\n").unwrap();
+        assert_eq!(prog.data_section.initialized[0].data, vec![0xFF, b'\t']);
+        let err = parse_result(".data\nmsg: .asciiz \"\\x4g\"\n").unwrap_err();
+        assert_eq!(err.to_string(), "2:14: Invalid escape sequence: \\x4g");
+    }
+
+    #[test]
+    fn negative_offset() {
+        let prog = parse_result(".text\nmain:\n\tlw $t0, -4($sp)\n").unwrap();
+        let instruction = &prog.text_section.blocks[1].instructions[0];
+        assert_eq!(
+            instruction.args[1],
+            InstructionArg::RegisterOffset(0xFFFC, Register::Sp)
+        );
+        assert_eq!(instruction.show(), "lw $t0, -4($sp)");
+        let err = parse_result(".text\nmain:\n\tlw $t0, -32769($sp)\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:10: Immediate out of range for 16 bits: -32769"
+        );
+    }
+
+    #[test]
+    fn repeated_data() {
+        let prog =
+            parse_result(".data\nfives: .word 5:3\nmask: .byte 0xFF:2\n.text\nmain:\n\tnop\n")
+                .unwrap();
+        let data = &prog.data_section.initialized;
+        assert_eq!(data[0].data, [5, 0, 0, 0, 5, 0, 0, 0, 5, 0, 0, 0]);
+        assert_eq!(data[1].data, [0xFF, 0xFF]);
+
+        let err = parse_result(".data\nnone: .word 5:0\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "2:15: Invalid repeat count, expected 1 to 1048576: 0"
+        );
+        let err = parse_result(".data\ntext: .asciiz 5:2\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "2:15: Repeated data is only supported by .word and .byte: .asciiz 5:2"
+        );
+        let err = parse_result(".data\ntext: .space 4, 5:2\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "2:17: Repeated data is only supported by .word and .byte: .space 4, 5:2"
+        );
+    }
+
+    #[test]
+    fn data_operands() {
+        let prog =
+            parse_result(".data\nz: .asciiz \"a\", \"bc\"\ns: .ascii \"d\", \"e\"\n").unwrap();
+        let data = &prog.data_section.initialized;
+        assert_eq!(data[0].data, b"a\0bc\0");
+        assert_eq!(data[1].data, b"de");
+
+        let err = parse_result(".data\nz: .asciiz \"a\", 5\n").unwrap_err();
+        assert_eq!(err.to_string(), "2:17: Expected a string: 5");
+        let err = parse_result(".data\nbuf: .space 4, 8\n").unwrap_err();
+        assert_eq!(err.to_string(), "2:16: Expected a single size: .space 4, 8");
+    }
+
+    #[test]
+    fn immediate_out_of_range() {
+        let err = parse_result(".text\nmain:\n\taddi $t0, $t0, 70000\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:17: Immediate out of range for signed 16 bits: 70000"
+        );
+        let err = parse_result(".text\nmain:\n\tli $t0, 99999999999\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:10: Immediate out of range for 32 bits: 99999999999"
+        );
+        let prog =
+            parse_result(".text\nmain:\n\tli $t0, 0xFFFFFFFF\n\tori $t0, $t0, 0xFFFF\n").unwrap();
+        let instructions = &prog.text_section.blocks[1].instructions;
+        assert_eq!(
+            instructions[0].args[1],
+            InstructionArg::Immediate(0xFFFF_FFFF)
+        );
+        let err =
+            parse_result(".equ BIG, 0x10000\n.text\nmain:\n\tlw $t0, BIG($sp)\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "4:10: Immediate out of range for 16 bits: BIG"
+        );
+    }
+
+    #[test]
+    fn negative_immediate() {
+        let prog = parse_result(
+            ".text\nmain:\n\tli $a0, -1\n\taddi $sp, $sp, -4\n\tslti $t0, $a0, -32768\n\tandi $t1, $a0, 0xFFFF\n",
+        )
+        .unwrap();
+        let instructions = &prog.text_section.blocks[1].instructions;
+        assert_eq!(
+            instructions[0].args[1],
+            InstructionArg::Immediate(0xFFFF_FFFF)
+        );
+        assert_eq!(instructions[1].args[2], InstructionArg::Immediate(0xFFFC));
+        assert_eq!(instructions[1].show(), "addi $sp, $sp, -0x4");
+        assert_eq!(instructions[2].args[2], InstructionArg::Immediate(0x8000));
+        assert_eq!(instructions[3].args[2], InstructionArg::Immediate(0xFFFF));
+
+        // Sign-extended immediates are bounded by -32768 to 32767, zero-extended ones by 0 to 65535
+        let err = parse_result(".text\nmain:\n\taddi $a0, $zero, 0xFFFF\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:19: Immediate out of range for signed 16 bits: 0xFFFF"
+        );
+        let err = parse_result(".text\nmain:\n\tori $a0, $zero, -1\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:18: Immediate out of range for 16 bits: -1"
+        );
+        let err = parse_result(".text\nmain:\n\tlui $a0, -1\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3:11: Immediate out of range for 16 bits: -1"
+        );
+    }
 }
@@ -1,11 +1,23 @@
-use crate::registers::Register;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt::Display,
+};
+
+use crate::{
+    address::Address,
+    disassembler::decode_instruction,
+    memory::{LabelMap, ANY_DATA_END},
+    parser::ParseError,
+    registers::Register,
+};
 use colorful::{Color, Colorful};
 
 /// Represents a 32 bits long word in a MIPS program.
 pub type Word = u32;
 
-/// Represents a 16 bits literal immediate value in a MIPS program.
-pub type Immediate = u16;
+/// Represents a literal immediate value in a MIPS program.
+/// Encoded instructions hold 16 bits, only `li` accepts a full 32-bit value.
+pub type Immediate = u32;
 
 pub const DIRECTIVE_COLOR: Color = Color::LightRed;
 pub const LABEL_COLOR: Color = Color::LightGreen;
@@ -15,11 +27,43 @@ pub const INSTRUCTION_COLOR: Color = Color::LightCyan;
 pub const DATA_SOURCE_COLOR: Color = Color::Yellow;
 pub const DATA_BYTES_COLOR: Color = Color::DarkGray;
 
+/// Revisions of the MIPS instruction set, each including the instructions of the previous ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum IsaProfile {
+    /// The original instruction set, as taught in most courses.
+    Mips1,
+    /// Adds instructions such as `clz` and `eret`.
+    Mips32,
+    /// Adds rotates and sign extension, such as `rotr` and `seb`.
+    #[default]
+    Mips32r2,
+}
+
+impl IsaProfile {
+    pub fn show(&self) -> &str {
+        match self {
+            IsaProfile::Mips1 => "MIPS-I",
+            IsaProfile::Mips32 => "MIPS32",
+            IsaProfile::Mips32r2 => "MIPS32r2",
+        }
+    }
+}
+
+impl Display for IsaProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.show())
+    }
+}
+
 /// Represents the different sections of a MIPS program.
 #[derive(Debug, PartialEq)]
 pub enum Section {
     /// The data section, which contains global and static data.
     Data,
+    /// The read-only data section, which contains constant data.
+    Rdata,
+    /// The bss section, which reserves zero-initialized global data.
+    Bss,
     /// The text section, which contains the executable instructions.
     Text,
 }
@@ -28,6 +72,8 @@ impl Section {
     pub fn show(&self) -> &str {
         match self {
             Section::Data => ".data",
+            Section::Rdata => ".rdata",
+            Section::Bss => ".bss",
             Section::Text => ".text",
         }
     }
@@ -35,9 +81,26 @@ impl Section {
     pub fn show_color(&self) -> String {
         match self {
             Section::Data => ".data".color(DIRECTIVE_COLOR).to_string(),
+            Section::Rdata => ".rdata".color(DIRECTIVE_COLOR).to_string(),
+            Section::Bss => ".bss".color(DIRECTIVE_COLOR).to_string(),
             Section::Text => ".text".color(DIRECTIVE_COLOR).to_string(),
         }
     }
+
+    /// The section directive line, with the address of the section if `base` is set, e.g. `.text 0x00400000`.
+    pub fn show_placed(&self, base: Option<Address>) -> String {
+        match base {
+            Some(base) => format!("{} {}\n", self.show(), base),
+            None => format!("{}\n", self.show()),
+        }
+    }
+
+    pub fn show_placed_color(&self, base: Option<Address>) -> String {
+        match base {
+            Some(base) => format!("{} {}\n", self.show_color(), base.show_color()),
+            None => format!("{}\n", self.show_color()),
+        }
+    }
 }
 
 /// Represents raw data in the data section.
@@ -49,9 +112,82 @@ pub struct StaticData {
     pub label: String,
     /// The actual data bytes.
     pub data: Vec<u8>,
+    /// The size in bytes of each value of the data,
+    /// 4 for `.word` values, which are little-endian and reordered on a big-endian target, or 1 for bytes.
+    pub element_size: usize,
 }
 
 impl StaticData {
+    /// A null-terminated `.asciiz` string.
+    pub fn asciiz(label: &str, text: &str) -> Self {
+        let mut source = String::from(".asciiz \"");
+        for c in text.chars() {
+            match c {
+                '\n' => source.push_str("\\n"),
+                '\t' => source.push_str("\\t"),
+                '\r' => source.push_str("\\r"),
+                '\\' => source.push_str("\\\\"),
+                // The string literal ends at the first quote, even when escaped
+                '"' => source.push_str("\\x22"),
+                c if c.is_control() && c.is_ascii() => {
+                    source.push_str(&format!("\\x{:02x}", c as u8))
+                }
+                c => source.push(c),
+            }
+        }
+        source.push('"');
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        StaticData {
+            source,
+            label: label.to_string(),
+            data,
+            element_size: 1,
+        }
+    }
+
+    /// A `.word` list of little-endian words.
+    ///
+    /// # Panics
+    ///
+    /// If `words` is empty, as `.word` takes at least one value.
+    pub fn word(label: &str, words: &[i32]) -> Self {
+        assert!(!words.is_empty(), "A .word list needs at least one word");
+        let values = words.iter().map(|word| (*word as u32).to_string());
+        StaticData {
+            source: format!(".word {}", values.collect::<Vec<_>>().join(", ")),
+            label: label.to_string(),
+            data: words.iter().flat_map(|word| word.to_le_bytes()).collect(),
+            element_size: size_of::<Word>(),
+        }
+    }
+
+    /// A `.byte` list.
+    ///
+    /// # Panics
+    ///
+    /// If `bytes` is empty, as `.byte` takes at least one value.
+    pub fn bytes(label: &str, bytes: &[u8]) -> Self {
+        assert!(!bytes.is_empty(), "A .byte list needs at least one byte");
+        let values = bytes.iter().map(|byte| byte.to_string());
+        StaticData {
+            source: format!(".byte {}", values.collect::<Vec<_>>().join(", ")),
+            label: label.to_string(),
+            data: bytes.to_vec(),
+            element_size: 1,
+        }
+    }
+
+    /// `size` zeroed bytes reserved by `.space`.
+    pub fn space(label: &str, size: usize) -> Self {
+        StaticData {
+            source: format!(".space {}", size),
+            label: label.to_string(),
+            data: vec![0; size],
+            element_size: 1,
+        }
+    }
+
     pub fn show(&self) -> String {
         format!("{}: {}\n", self.label, self.source)
     }
@@ -76,12 +212,45 @@ impl StaticData {
     }
 }
 
+/// Represents zero-initialized data reserved in the bss section.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReservedData {
+    /// The source code of the reserving directive.
+    pub source: String,
+    /// The label of the reserved data.
+    pub label: String,
+    /// The number of reserved bytes.
+    pub size: usize,
+}
+
+impl ReservedData {
+    pub fn show(&self) -> String {
+        format!("{}: {}\n", self.label, self.source)
+    }
+
+    pub fn show_color(&self) -> String {
+        format!(
+            "{}: {}\n",
+            self.label.clone().color(LABEL_COLOR),
+            self.source.clone().color(DATA_SOURCE_COLOR)
+        )
+    }
+}
+
 /// Represents the data section of a MIPS program.
 #[derive(Debug, PartialEq)]
 pub struct DataSection {
     /// Initialized data.
     /// A list of global labels to their corresponding raw data.
     pub initialized: Vec<StaticData>,
+    /// Read-only initialized data, in the `.rdata` section.
+    pub read_only: Vec<StaticData>,
+    /// Uninitialized data, reserved in the `.bss` section.
+    pub uninitialized: Vec<ReservedData>,
+    /// The global labels in the data, rdata and bss sections.
+    pub global_labels: Vec<String>,
+    /// The address of the `.data` section set by `.data address`, or `None` to use the default.
+    pub base: Option<Address>,
 }
 
 impl DataSection {
@@ -99,6 +268,9 @@ impl DataSection {
 
     pub fn show(&self) -> String {
         let mut result = String::new();
+        for label in &self.global_labels {
+            result.push_str(&format!(".global {}\n", label));
+        }
         for data in &self.initialized {
             result.push_str(&data.show());
         }
@@ -107,6 +279,13 @@ impl DataSection {
 
     pub fn show_color(&self) -> String {
         let mut result = String::new();
+        for label in &self.global_labels {
+            result.push_str(&format!(
+                "{} {}\n",
+                ".global".color(DIRECTIVE_COLOR),
+                label.clone().color(LABEL_COLOR)
+            ));
+        }
         for data in &self.initialized {
             result.push_str(&data.show_color());
         }
@@ -155,28 +334,34 @@ pub enum InstructionKind {
     Andi,
     /// Branch if two registers are equal.
     ///
-    /// Syntax: `beq $s, $t, offset`
+    /// Syntax: `beq $s, $t, label` or `beq $s, $t, offset`
     ///
     /// Description: `if ($s == $t) branch to address PC + 4 + (offset * 4)`
     Beq,
     /// Branch if less that equal to zero.
     ///
-    /// Syntax: `blez $s, offset`
+    /// Syntax: `blez $s, label` or `blez $s, offset`
     ///
     /// Description: `if ($s <= 0) branch to address PC + 4 + (offset * 4)`
     Blez,
     /// Branch if two registers are not equal.
     ///
-    /// Syntax: `bne $s, $t, offset`
+    /// Syntax: `bne $s, $t, label` or `bne $s, $t, offset`
     ///
     /// Description: `if ($s != $t) branch to address PC + 4 + (offset * 4)`
     Bne,
     /// Branch to a label if a register is greater than zero.
     ///
-    /// Syntax: `bgtz $s, offset`
+    /// Syntax: `bgtz $s, label` or `bgtz $s, offset`
     ///
     /// Description: `if ($s > 0) branch to address PC + 4 + (offset * 4)`
     Bgtz,
+    /// Branch and link to a label (store return address in $ra), e.g. to read the program counter.
+    ///
+    /// Syntax: `bal label` or `bal offset`
+    ///
+    /// Description: `$ra = PC + 4; branch to address PC + 4 + (offset * 4)`
+    Bal,
     /// Jump and link to a register (store return address in $ra).
     ///
     /// Syntax: `jalr $rs`
@@ -216,6 +401,8 @@ pub enum InstructionKind {
     /// Description: `$t = address of label`
     ///
     /// Where `label` is pre-defined for something in memory (defined under the `.data` directive).
+    ///
+    /// Unlike `lw $t, label`, which loads the word stored at the label, `la` does not access memory.
     La,
     /// Load an immediate byte value into a register.
     ///
@@ -249,34 +436,81 @@ pub enum InstructionKind {
     Lui,
     /// Load a word from memory into a register.
     ///
-    /// Syntax: `lw $t, offset($s)`
+    /// Syntax: `lw $t, offset($s)` or `lw $t, label`
+    ///
+    /// Description: `$t = Memory[$s + offset]` or `$t = Memory[address of label]`
     ///
-    /// Description: `$t = Memory[$s + offset]`
+    /// Use `la $t, label` to load the address of the label itself.
     Lw,
+    /// Load the most-significant part of an unaligned word into a register.
+    /// Combined with `lwr` to load a word from an unaligned address.
+    ///
+    /// Syntax: `lwl $t, offset($s)`
+    ///
+    /// Description: `$t = (word << n) | ($t & low n bits)`, where `word` is the aligned word containing
+    /// `Memory[$s + offset]`, shifted by `n` bits such that this byte becomes the most-significant.
+    Lwl,
+    /// Load the least-significant part of an unaligned word into a register.
+    /// Combined with `lwl` to load a word from an unaligned address.
+    ///
+    /// Syntax: `lwr $t, offset($s)`
+    ///
+    /// Description: `$t = (word >> n) | ($t & high n bits)`, where `word` is the aligned word containing
+    /// `Memory[$s + offset]`, shifted by `n` bits such that this byte becomes the least-significant.
+    Lwr,
     /// Multiply two registers and store the result in a register.
     ///
     /// Syntax: `mul $d, $s, $t`
     ///
-    /// Description: `$d = $s * $t`
+    /// Description: `HI:LO = $s * $t; $d = LO`
     Mult,
     /// Multiply two registers (unsigned) and store the result in a register.
     ///
     /// Syntax: `mulu $d, $s, $t`
     ///
-    /// Description: `$d = $s * $t`
+    /// Description: `HI:LO = $s * $t; $d = LO`
     Multu,
-    /// Divide one register by another and store the result in a register.
+    /// Divide one register by another, keeping the quotient in LO and the remainder in HI.
+    /// The three operand form also stores the quotient in a register.
     ///
-    /// Syntax: `div $d, $s, $t`
+    /// Syntax: `div $s, $t` or `div $d, $s, $t`
     ///
-    /// Description: `$d = $s / $t`
+    /// Description: `LO = $s / $t; HI = $s % $t; $d = LO`
     Div,
-    /// Divide one register by another (unsigned) and store the result in a register.
+    /// Divide one register by another (unsigned), keeping the quotient in LO and the remainder in HI.
+    /// The three operand form also stores the quotient in a register.
     ///
-    /// Syntax: `divu $d, $s, $t`
+    /// Syntax: `divu $s, $t` or `divu $d, $s, $t`
     ///
-    /// Description: `$d = $s / $t`
+    /// Description: `LO = $s / $t; HI = $s % $t; $d = LO`
     Divu,
+    /// Move the HI register into a register.
+    ///
+    /// Syntax: `mfhi $d`
+    ///
+    /// Description: `$d = HI`
+    Mfhi,
+    /// Move the LO register into a register.
+    ///
+    /// Syntax: `mflo $d`
+    ///
+    /// Description: `$d = LO`
+    Mflo,
+    /// Move a coprocessor 0 register into a register,
+    /// `$8` (`BadVAddr`), `$12` (`Status`), `$13` (`Cause`) or `$14` (`EPC`).
+    /// The parser rejects other registers, which are not modeled and read as 0.
+    ///
+    /// Syntax: `mfc0 $t, $d`
+    ///
+    /// Description: `$t = CP0[$d]`
+    Mfc0,
+    /// Move a register into a coprocessor 0 register, `$12` (`Status`), `$13` (`Cause`) or `$14` (`EPC`).
+    /// The parser rejects other registers, writes to which are ignored.
+    ///
+    /// Syntax: `mtc0 $t, $d`
+    ///
+    /// Description: `CP0[$d] = $t`
+    Mtc0,
     /// Perform a bitwise NOR on two registers and store the result in a register.
     ///
     /// Syntax: `nor $d, $s, $t`
@@ -355,6 +589,48 @@ pub enum InstructionKind {
     ///
     /// Description: `$d = $t >> $s`
     Srlv,
+    /// Rotate a register right by a specified number of bits and store the result in a register (MIPS32r2).
+    ///
+    /// Syntax: `rotr $d, $t, shamt`
+    ///
+    /// Description: `$d = $t >>> shamt`
+    Rotr,
+    /// Rotate a register right by a value stored in a register (variable) and store the result in a register (MIPS32r2).
+    ///
+    /// Syntax: `rotrv $d, $t, $s`
+    ///
+    /// Description: `$d = $t >>> $s`
+    Rotrv,
+    /// Rotate a register left by a specified number of bits and store the result in a register.
+    ///
+    /// Syntax: `rol $d, $t, shamt`
+    ///
+    /// Description: `$d = $t <<< shamt`
+    Rol,
+    /// Rotate a register right by a specified number of bits and store the result in a register.
+    ///
+    /// Syntax: `ror $d, $t, shamt`
+    ///
+    /// Description: `$d = $t >>> shamt`
+    Ror,
+    /// Count the leading zero bits of a register (MIPS32).
+    ///
+    /// Syntax: `clz $d, $s`
+    ///
+    /// Description: `$d = leading_zeros($s)`
+    Clz,
+    /// Sign-extend the least-significant byte of a register (MIPS32r2).
+    ///
+    /// Syntax: `seb $d, $t`
+    ///
+    /// Description: `$d = sign_extend($t[7:0])`
+    Seb,
+    /// Sign-extend the least-significant halfword of a register (MIPS32r2).
+    ///
+    /// Syntax: `seh $d, $t`
+    ///
+    /// Description: `$d = sign_extend($t[15:0])`
+    Seh,
     /// Store a byte from a register into memory.
     ///
     /// Syntax: `sb $t, offset($s)`
@@ -373,6 +649,22 @@ pub enum InstructionKind {
     ///
     /// Description: `Memory[$s + offset] = $t`
     Sw,
+    /// Store the most-significant part of a register to an unaligned word.
+    /// Combined with `swr` to store a word to an unaligned address.
+    ///
+    /// Syntax: `swl $t, offset($s)`
+    ///
+    /// Description: `word = ($t >> n) | (word & high n bits)`, where `word` is the aligned word containing
+    /// `Memory[$s + offset]`, and the most-significant byte of `$t` is shifted by `n` bits into this byte.
+    Swl,
+    /// Store the least-significant part of a register to an unaligned word.
+    /// Combined with `swl` to store a word to an unaligned address.
+    ///
+    /// Syntax: `swr $t, offset($s)`
+    ///
+    /// Description: `word = ($t << n) | (word & low n bits)`, where `word` is the aligned word containing
+    /// `Memory[$s + offset]`, and the least-significant byte of `$t` is shifted by `n` bits into this byte.
+    Swr,
     /// Subtract one register from another and store the result in a register.
     ///
     /// Syntax: `sub $d, $s, $t`
@@ -415,9 +707,65 @@ pub enum InstructionKind {
     ///
     /// Description: `perform a system call`
     Syscall,
+    /// Raise a breakpoint exception.
+    ///
+    /// Syntax: `break`
+    ///
+    /// Description: `raise a breakpoint exception`
+    Break,
+    /// Return from an exception handler to the instruction that raised the exception.
+    ///
+    /// Syntax: `eret`
+    ///
+    /// Description: `PC = EPC; clear the exception level bit of Status`
+    Eret,
 }
 
 impl InstructionKind {
+    /// Pseudo instructions have no encoding of their own,
+    /// and do not decode back to the same instruction.
+    pub fn is_pseudo(&self) -> bool {
+        matches!(
+            self,
+            InstructionKind::La
+                | InstructionKind::Li
+                | InstructionKind::Move
+                | InstructionKind::Rol
+                | InstructionKind::Ror
+        )
+    }
+
+    /// The earliest revision of the instruction set that includes this instruction.
+    pub fn isa(&self) -> IsaProfile {
+        match self {
+            InstructionKind::Clz | InstructionKind::Eret => IsaProfile::Mips32,
+            // The `rol` and `ror` pseudo instructions are encoded as `rotr`
+            InstructionKind::Rotr
+            | InstructionKind::Rotrv
+            | InstructionKind::Rol
+            | InstructionKind::Ror
+            | InstructionKind::Seb
+            | InstructionKind::Seh => IsaProfile::Mips32r2,
+            _ => IsaProfile::Mips1,
+        }
+    }
+
+    /// Whether the 16-bit immediate of the instruction is sign-extended,
+    /// as for arithmetic, comparisons and branch offsets, rather than zero-extended.
+    pub fn has_signed_immediate(&self) -> bool {
+        use InstructionKind::*;
+        matches!(
+            self,
+            Addi | Addiu | Slti | Sltiu | Beq | Bne | Blez | Bgtz | Bal
+        )
+    }
+
+    /// Whether the instruction moves to or from the coprocessor 0 register in its second operand,
+    /// shown by its number as in `mfc0 $t0, $13`.
+    pub fn is_cp0_move(&self) -> bool {
+        matches!(self, InstructionKind::Mfc0 | InstructionKind::Mtc0)
+    }
+
     pub fn show(&self) -> &str {
         match self {
             InstructionKind::Add => "add",
@@ -441,14 +789,24 @@ impl InstructionKind {
             InstructionKind::Bne => "bne",
             InstructionKind::Lw => "lw",
             InstructionKind::Sw => "sw",
+            InstructionKind::Clz => "clz",
+            InstructionKind::Seb => "seb",
+            InstructionKind::Seh => "seh",
+            InstructionKind::Lwl => "lwl",
+            InstructionKind::Lwr => "lwr",
+            InstructionKind::Swl => "swl",
+            InstructionKind::Swr => "swr",
             InstructionKind::Li => "li",
             InstructionKind::Lui => "lui",
             InstructionKind::Move => "move",
             InstructionKind::La => "la",
             InstructionKind::J => "j",
             InstructionKind::Jal => "jal",
+            InstructionKind::Bal => "bal",
             InstructionKind::Nop => "nop",
             InstructionKind::Syscall => "syscall",
+            InstructionKind::Break => "break",
+            InstructionKind::Eret => "eret",
             InstructionKind::Jalr => "jalr",
             InstructionKind::Lb => "lb",
             InstructionKind::Lbu => "lbu",
@@ -458,6 +816,10 @@ impl InstructionKind {
             InstructionKind::Sltiu => "sltiu",
             InstructionKind::Sllv => "sllv",
             InstructionKind::Srlv => "srlv",
+            InstructionKind::Rotr => "rotr",
+            InstructionKind::Rotrv => "rotrv",
+            InstructionKind::Rol => "rol",
+            InstructionKind::Ror => "ror",
             InstructionKind::Sb => "sb",
             InstructionKind::Subu => "subu",
             InstructionKind::Xori => "xori",
@@ -469,13 +831,19 @@ impl InstructionKind {
             InstructionKind::Lh => "lh",
             InstructionKind::Srav => "srav",
             InstructionKind::Divu => "divu",
+            InstructionKind::Mfhi => "mfhi",
+            InstructionKind::Mflo => "mflo",
+            InstructionKind::Mfc0 => "mfc0",
+            InstructionKind::Mtc0 => "mtc0",
         }
     }
 }
 
-impl From<&str> for InstructionKind {
-    fn from(s: &str) -> InstructionKind {
-        match s {
+impl TryFrom<&str> for InstructionKind {
+    type Error = ParseError;
+
+    fn try_from(s: &str) -> Result<InstructionKind, ParseError> {
+        Ok(match s {
             "add" => InstructionKind::Add,
             "addi" => InstructionKind::Addi,
             "addiu" => InstructionKind::Addiu,
@@ -498,14 +866,24 @@ impl From<&str> for InstructionKind {
             "bne" => InstructionKind::Bne,
             "lw" => InstructionKind::Lw,
             "sw" => InstructionKind::Sw,
+            "clz" => InstructionKind::Clz,
+            "seb" => InstructionKind::Seb,
+            "seh" => InstructionKind::Seh,
+            "lwl" => InstructionKind::Lwl,
+            "lwr" => InstructionKind::Lwr,
+            "swl" => InstructionKind::Swl,
+            "swr" => InstructionKind::Swr,
             "li" => InstructionKind::Li,
             "lui" => InstructionKind::Lui,
             "move" => InstructionKind::Move,
             "la" => InstructionKind::La,
             "j" => InstructionKind::J,
             "jal" => InstructionKind::Jal,
+            "bal" => InstructionKind::Bal,
             "nop" => InstructionKind::Nop,
             "syscall" => InstructionKind::Syscall,
+            "break" => InstructionKind::Break,
+            "eret" => InstructionKind::Eret,
             "jalr" => InstructionKind::Jalr,
             "lb" => InstructionKind::Lb,
             "lbu" => InstructionKind::Lbu,
@@ -515,6 +893,10 @@ impl From<&str> for InstructionKind {
             "sltiu" => InstructionKind::Sltiu,
             "sllv" => InstructionKind::Sllv,
             "srlv" => InstructionKind::Srlv,
+            "rotr" => InstructionKind::Rotr,
+            "rotrv" => InstructionKind::Rotrv,
+            "rol" => InstructionKind::Rol,
+            "ror" => InstructionKind::Ror,
             "sb" => InstructionKind::Sb,
             "subu" => InstructionKind::Subu,
             "xori" => InstructionKind::Xori,
@@ -527,8 +909,12 @@ impl From<&str> for InstructionKind {
             "lh" => InstructionKind::Lh,
             "srav" => InstructionKind::Srav,
             "divu" => InstructionKind::Divu,
-            _ => panic!("Invalid instruction: {}", s),
-        }
+            "mfhi" => InstructionKind::Mfhi,
+            "mflo" => InstructionKind::Mflo,
+            "mfc0" => InstructionKind::Mfc0,
+            "mtc0" => InstructionKind::Mtc0,
+            _ => return Err(ParseError::new(format!("Invalid instruction: {}", s))),
+        })
     }
 }
 
@@ -539,10 +925,13 @@ pub enum InstructionArg {
     Register(Register),
     /// An immediate value argument.
     Immediate(Immediate),
-    /// Register offset argument.
+    /// Register offset argument: `offset($r)`.
+    /// The offset is a 16-bit two's complement value, sign-extended when adding it to the register.
     RegisterOffset(Immediate, Register),
     /// A label argument.
     Label(String),
+    /// Register offset from the address of a label: `label($r)`.
+    LabelOffset(String, Register),
 }
 
 impl InstructionArg {
@@ -578,8 +967,9 @@ impl InstructionArg {
         match self {
             InstructionArg::Register(r) => r.show().to_string(),
             InstructionArg::Immediate(i) => format!("0x{:x}", i),
-            InstructionArg::RegisterOffset(o, r) => format!("{}({})", o, r.show()),
+            InstructionArg::RegisterOffset(o, r) => format!("{}({})", *o as i16, r.show()),
             InstructionArg::Label(l) => l.to_string(),
+            InstructionArg::LabelOffset(l, r) => format!("{}({})", l, r.show()),
         }
     }
 
@@ -589,16 +979,35 @@ impl InstructionArg {
             InstructionArg::Immediate(i) => format!("0x{:x}", i).color(IMMEDIATE_COLOR).to_string(),
             InstructionArg::RegisterOffset(o, r) => format!(
                 "{}({})",
-                o.to_string().color(IMMEDIATE_COLOR),
+                (*o as i16).to_string().color(IMMEDIATE_COLOR),
                 r.show_color()
             )
             .color(REGISTER_COLOR)
             .to_string(),
             InstructionArg::Label(l) => l.to_string().color(LABEL_COLOR).to_string(),
+            InstructionArg::LabelOffset(l, r) => {
+                format!("{}({})", l.to_string().color(LABEL_COLOR), r.show_color())
+            }
         }
     }
 }
 
+impl Display for InstructionArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.show())
+    }
+}
+
+/// Show a sign-extended 16-bit immediate in hex, with a `-` if negative.
+fn show_signed(immediate: Immediate) -> String {
+    let value = immediate as i16;
+    if value < 0 {
+        format!("-0x{:x}", value.unsigned_abs())
+    } else {
+        format!("0x{:x}", value)
+    }
+}
+
 /// Represents a MIPS instruction.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Instruction {
@@ -609,6 +1018,96 @@ pub struct Instruction {
 }
 
 impl Instruction {
+    /// A register instruction `kind $rd, $rs, $rt`, such as `add` or `slt`.
+    /// The variable shifts take their operands in source order `kind $rd, $rt, $rs`,
+    /// shifting `$rt` by `$rs`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `kind` does not take three registers.
+    pub fn r_type(kind: InstructionKind, rd: Register, rs: Register, rt: Register) -> Self {
+        use InstructionKind::*;
+        let (rs, rt) = match kind {
+            Add | Addu | Sub | Subu | And | Or | Xor | Nor | Slt | Sltu | Mult | Multu | Div
+            | Divu => (rs, rt),
+            Sllv | Srlv | Srav | Rotrv => (rt, rs),
+            _ => panic!("Not a register instruction: {}", kind.show()),
+        };
+        Instruction {
+            kind,
+            args: vec![
+                InstructionArg::Register(rd),
+                InstructionArg::Register(rs),
+                InstructionArg::Register(rt),
+            ],
+        }
+    }
+
+    /// An immediate instruction `kind $rt, $rs, imm`, such as `addi` or `ori`.
+    /// The `imm` is the 16-bit immediate field, a negative immediate is given as its two's complement,
+    /// e.g. `-1i16 as u16` for `addi $rt, $rs, -1`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `kind` does not take two registers and an immediate.
+    pub fn i_type(kind: InstructionKind, rt: Register, rs: Register, imm: u16) -> Self {
+        use InstructionKind::*;
+        assert!(
+            matches!(kind, Addi | Addiu | Andi | Ori | Xori | Slti | Sltiu),
+            "Not an immediate instruction: {}",
+            kind.show()
+        );
+        Instruction {
+            kind,
+            args: vec![
+                InstructionArg::Register(rt),
+                InstructionArg::Register(rs),
+                InstructionArg::Immediate(imm as Immediate),
+            ],
+        }
+    }
+
+    /// A load `kind $rt, offset($base)`, such as `lw` or `lbu`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `kind` is not a load.
+    pub fn load(kind: InstructionKind, rt: Register, offset: i16, base: Register) -> Self {
+        use InstructionKind::*;
+        assert!(
+            matches!(kind, Lb | Lbu | Lh | Lhu | Lw | Lwl | Lwr),
+            "Not a load instruction: {}",
+            kind.show()
+        );
+        Self::memory_access(kind, rt, offset, base)
+    }
+
+    /// A store `kind $rt, offset($base)`, such as `sw` or `sb`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `kind` is not a store.
+    pub fn store(kind: InstructionKind, rt: Register, offset: i16, base: Register) -> Self {
+        use InstructionKind::*;
+        assert!(
+            matches!(kind, Sb | Sh | Sw | Swl | Swr),
+            "Not a store instruction: {}",
+            kind.show()
+        );
+        Self::memory_access(kind, rt, offset, base)
+    }
+
+    fn memory_access(kind: InstructionKind, rt: Register, offset: i16, base: Register) -> Self {
+        Instruction {
+            kind,
+            args: vec![
+                InstructionArg::Register(rt),
+                // Offsets are stored as 16-bit two's complement
+                InstructionArg::RegisterOffset(offset as u16 as Immediate, base),
+            ],
+        }
+    }
+
     pub fn show(&self) -> String {
         let mut result = self.kind.show().to_string();
         for (i, arg) in self.args.iter().enumerate() {
@@ -617,7 +1116,15 @@ impl Instruction {
             } else {
                 result.push_str(", ");
             }
-            result.push_str(&arg.show());
+            match arg {
+                InstructionArg::Immediate(i) if self.kind.has_signed_immediate() => {
+                    result.push_str(&show_signed(*i))
+                }
+                InstructionArg::Register(register) if i == 1 && self.kind.is_cp0_move() => {
+                    result.push_str(&format!("${}", *register as u8))
+                }
+                _ => result.push_str(&arg.show()),
+            }
         }
         result
     }
@@ -630,7 +1137,18 @@ impl Instruction {
             } else {
                 result.push_str(", ");
             }
-            result.push_str(&arg.show_color());
+            match arg {
+                InstructionArg::Immediate(i) if self.kind.has_signed_immediate() => {
+                    result.push_str(&show_signed(*i).color(IMMEDIATE_COLOR).to_string())
+                }
+                InstructionArg::Register(register) if i == 1 && self.kind.is_cp0_move() => result
+                    .push_str(
+                        &format!("${}", *register as u8)
+                            .color(REGISTER_COLOR)
+                            .to_string(),
+                    ),
+                _ => result.push_str(&arg.show_color()),
+            }
         }
         result
     }
@@ -638,6 +1156,51 @@ impl Instruction {
     pub const fn size() -> usize {
         4
     }
+
+    /// The register written by the instruction, if any.
+    /// Results stored only in `HI`/`LO` and memory are not included.
+    pub fn destination(&self) -> Option<Register> {
+        match (&self.kind, self.args.as_slice()) {
+            (InstructionKind::Jal | InstructionKind::Bal, _) | (InstructionKind::Jalr, [_]) => {
+                Some(Register::Ra)
+            }
+            // The two operand forms write only `hi` and `lo`
+            (
+                InstructionKind::Div
+                | InstructionKind::Divu
+                | InstructionKind::Mult
+                | InstructionKind::Multu,
+                [_, _],
+            ) => None,
+            (
+                InstructionKind::Beq
+                | InstructionKind::Bne
+                | InstructionKind::Blez
+                | InstructionKind::Bgtz
+                | InstructionKind::J
+                | InstructionKind::Jr
+                | InstructionKind::Sb
+                | InstructionKind::Sh
+                | InstructionKind::Sw
+                | InstructionKind::Swl
+                | InstructionKind::Swr
+                | InstructionKind::Nop
+                | InstructionKind::Syscall
+                | InstructionKind::Break
+                | InstructionKind::Eret
+                | InstructionKind::Mtc0,
+                _,
+            ) => None,
+            (_, [InstructionArg::Register(register), ..]) => Some(*register),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.show())
+    }
 }
 
 /// Represents a block of instructions in the text section.
@@ -682,6 +1245,8 @@ pub struct TextSection {
     pub blocks: Vec<Block>,
     /// The global labels in the text section.
     pub global_labels: Vec<String>,
+    /// The address of the `.text` section set by `.text address`, or `None` to use the default.
+    pub base: Option<Address>,
 }
 
 impl TextSection {
@@ -737,19 +1302,322 @@ pub struct Program {
     pub data_section: DataSection,
     /// The text section of the program.
     pub text_section: TextSection,
+    /// The `#assert` comments of the program, checked by the VM when the program exits.
+    pub assertions: Vec<Assertion>,
+}
+
+/// A `#assert $register == value` comment, expecting a register value when the program exits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assertion {
+    pub register: Register,
+    pub expected: Word,
+    /// The included file of the comment, or `None` for the parsed source itself.
+    pub file: Option<String>,
+    /// The 1-based line of the comment within its file.
+    pub line: usize,
+}
+
+impl Assertion {
+    pub fn show(&self) -> String {
+        format!("{} == 0x{:X}", self.register.show(), self.expected)
+    }
+
+    /// Where the comment is, e.g. `line 3` or `helpers.asm line 3`.
+    pub fn location(&self) -> String {
+        match &self.file {
+            Some(file) => format!("{} line {}", file, self.line),
+            None => format!("line {}", self.line),
+        }
+    }
+}
+
+/// An error produced while linking programs together.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    /// A global label is defined in more than one program.
+    DuplicateGlobal(String),
+    /// A label is neither defined in the program referencing it nor global in another.
+    UndefinedSymbol(String),
+    /// Two programs place the named section at different addresses.
+    ConflictingBase {
+        section: &'static str,
+        first: Address,
+        second: Address,
+    },
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkError::DuplicateGlobal(label) => {
+                write!(f, "Global label {} is defined more than once", label)
+            }
+            LinkError::UndefinedSymbol(label) => write!(f, "Undefined symbol: {}", label),
+            LinkError::ConflictingBase {
+                section,
+                first,
+                second,
+            } => write!(
+                f,
+                "The {} section is placed at both {} and {}",
+                section, first, second
+            ),
+        }
+    }
 }
 
 impl Program {
+    /// A stable hash of the program contents, equal for programs differing only in whitespace,
+    /// comments or the spelling of literals, e.g. to detect identical submissions or key caches.
+    ///
+    /// Hashes the labels with the data bytes or reserved sizes of the data,
+    /// the labels and canonical [`Instruction::show`] form of the text, the global labels and the section bases,
+    /// with 64-bit FNV-1a, which unlike [`std::hash::DefaultHasher`] is the same across Rust versions.
+    pub fn content_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut hash_bytes = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+            }
+        };
+        // Prefix every field and list with its length so that adjacent ones cannot run together
+        let mut write = |bytes: &[u8]| {
+            hash_bytes(&(bytes.len() as u64).to_le_bytes());
+            hash_bytes(bytes);
+        };
+        let count = |length: usize| (length as u64).to_le_bytes();
+        write(&count(self.data_section.initialized.len()));
+        for data in &self.data_section.initialized {
+            write(data.label.as_bytes());
+            write(&data.data);
+            write(&count(data.element_size));
+        }
+        write(&count(self.data_section.read_only.len()));
+        for data in &self.data_section.read_only {
+            write(data.label.as_bytes());
+            write(&data.data);
+            write(&count(data.element_size));
+        }
+        write(&count(self.data_section.uninitialized.len()));
+        for data in &self.data_section.uninitialized {
+            write(data.label.as_bytes());
+            write(&data.size.to_le_bytes());
+        }
+        write(&count(self.text_section.blocks.len()));
+        for block in &self.text_section.blocks {
+            write(block.label.as_bytes());
+            write(&count(block.instructions.len()));
+            for instruction in &block.instructions {
+                write(instruction.show().as_bytes());
+            }
+        }
+        for labels in [
+            &self.data_section.global_labels,
+            &self.text_section.global_labels,
+        ] {
+            write(&count(labels.len()));
+            for label in labels {
+                write(label.as_bytes());
+            }
+        }
+        // An empty field for a section without a base
+        for base in [self.data_section.base, self.text_section.base] {
+            write(&base.map_or(Vec::new(), |base| base.unwrap().to_le_bytes().to_vec()));
+        }
+        hash
+    }
+
+    /// Link programs into one, concatenating their data and text in order.
+    ///
+    /// Global labels (`.globl`) are visible to all programs and must be defined only once.
+    /// Other labels are local to their program, and are renamed if another program defines the same label.
+    pub fn link(programs: Vec<Program>) -> Result<Program, LinkError> {
+        let defined = programs
+            .iter()
+            .map(Program::defined_labels)
+            .collect::<Vec<_>>();
+        let mut globals = HashSet::new();
+        for (program, labels) in programs.iter().zip(&defined) {
+            for label in program.global_labels() {
+                if labels.contains(label) && !globals.insert(label.clone()) {
+                    return Err(LinkError::DuplicateGlobal(label.clone()));
+                }
+            }
+        }
+        let mut taken = defined.iter().flatten().cloned().collect::<HashSet<_>>();
+
+        let mut linked = Program {
+            data_section: DataSection {
+                initialized: Vec::new(),
+                read_only: Vec::new(),
+                uninitialized: Vec::new(),
+                global_labels: Vec::new(),
+                base: None,
+            },
+            text_section: TextSection {
+                blocks: Vec::new(),
+                global_labels: Vec::new(),
+                base: None,
+            },
+            assertions: Vec::new(),
+        };
+        for (unit, (mut program, labels)) in programs.into_iter().zip(&defined).enumerate() {
+            // Rename local labels that are also defined by another program
+            let mut renames = HashMap::new();
+            for label in labels {
+                let collides = defined
+                    .iter()
+                    .enumerate()
+                    .any(|(other, labels)| other != unit && labels.contains(label));
+                if !globals.contains(label) && collides {
+                    let mut name = format!("{}_{}", label, unit);
+                    while taken.contains(&name) {
+                        name.push('_');
+                    }
+                    taken.insert(name.clone());
+                    renames.insert(label.clone(), name);
+                }
+            }
+            let rename = |label: &mut String| {
+                if let Some(name) = renames.get(label) {
+                    *label = name.clone();
+                }
+            };
+            for data in &mut program.data_section.initialized {
+                rename(&mut data.label);
+            }
+            for data in &mut program.data_section.read_only {
+                rename(&mut data.label);
+            }
+            for data in &mut program.data_section.uninitialized {
+                rename(&mut data.label);
+            }
+            for block in &mut program.text_section.blocks {
+                rename(&mut block.label);
+                for instruction in &mut block.instructions {
+                    for arg in &mut instruction.args {
+                        if let InstructionArg::Label(label)
+                        | InstructionArg::LabelOffset(label, _) = arg
+                        {
+                            if !labels.contains(label) && !globals.contains(label) {
+                                return Err(LinkError::UndefinedSymbol(label.clone()));
+                            }
+                            rename(label);
+                        }
+                    }
+                }
+            }
+            linked
+                .data_section
+                .initialized
+                .append(&mut program.data_section.initialized);
+            linked
+                .data_section
+                .read_only
+                .append(&mut program.data_section.read_only);
+            linked
+                .data_section
+                .uninitialized
+                .append(&mut program.data_section.uninitialized);
+            linked
+                .data_section
+                .global_labels
+                .append(&mut program.data_section.global_labels);
+            linked
+                .text_section
+                .blocks
+                .append(&mut program.text_section.blocks);
+            linked
+                .text_section
+                .global_labels
+                .append(&mut program.text_section.global_labels);
+            linked.assertions.append(&mut program.assertions);
+            // Programs placing a section must agree on its address
+            let merge = |section, linked: &mut Option<Address>, base: Option<Address>| match (
+                *linked, base,
+            ) {
+                (Some(first), Some(second)) if first != second => Err(LinkError::ConflictingBase {
+                    section,
+                    first,
+                    second,
+                }),
+                _ => {
+                    *linked = linked.or(base);
+                    Ok(())
+                }
+            };
+            merge(
+                ".data",
+                &mut linked.data_section.base,
+                program.data_section.base,
+            )?;
+            merge(
+                ".text",
+                &mut linked.text_section.base,
+                program.text_section.base,
+            )?;
+        }
+        Ok(linked)
+    }
+
+    /// All labels defined in the data, rdata, bss and text sections.
+    pub(crate) fn defined_labels(&self) -> HashSet<String> {
+        self.data_section
+            .initialized
+            .iter()
+            .chain(&self.data_section.read_only)
+            .map(|data| data.label.clone())
+            .chain(
+                self.data_section
+                    .uninitialized
+                    .iter()
+                    .map(|data| data.label.clone()),
+            )
+            .chain(
+                self.text_section
+                    .blocks
+                    .iter()
+                    .filter(|block| !block.label.is_empty())
+                    .map(|block| block.label.clone()),
+            )
+            .collect()
+    }
+
+    /// All global labels of the program, data symbols first.
+    pub fn global_labels(&self) -> Vec<&String> {
+        self.data_section
+            .global_labels
+            .iter()
+            .chain(self.text_section.global_labels.iter())
+            .collect()
+    }
+
     pub fn show(&self) -> String {
         let mut result = String::new();
         // Data
         if !self.data_section.empty() {
-            result.push_str(&format!("{}\n", Section::Data.show()));
+            result.push_str(&Section::Data.show_placed(self.data_section.base));
             result.push_str(&self.data_section.show());
         }
+        // Rdata
+        if !self.data_section.read_only.is_empty() {
+            result.push_str(&format!("\n{}\n", Section::Rdata.show()));
+            for data in &self.data_section.read_only {
+                result.push_str(&data.show());
+            }
+        }
+        // Bss
+        if !self.data_section.uninitialized.is_empty() {
+            result.push_str(&format!("\n{}\n", Section::Bss.show()));
+            for data in &self.data_section.uninitialized {
+                result.push_str(&data.show());
+            }
+        }
         // Text
         if !self.text_section.empty() {
-            result.push_str(&format!("\n{}\n", Section::Text.show()));
+            result.push('\n');
+            result.push_str(&Section::Text.show_placed(self.text_section.base));
             result.push_str(&self.text_section.show());
         }
         result
@@ -759,17 +1627,581 @@ impl Program {
         let mut result = String::new();
         // Data
         if !self.data_section.empty() {
-            result.push_str(&format!("{}\n", Section::Data.show_color()));
+            result.push_str(&Section::Data.show_placed_color(self.data_section.base));
             result.push_str(&self.data_section.show_color());
         }
+        // Rdata
+        if !self.data_section.read_only.is_empty() {
+            result.push_str(&format!("\n{}\n", Section::Rdata.show_color()));
+            for data in &self.data_section.read_only {
+                result.push_str(&data.show_color());
+            }
+        }
+        // Bss
+        if !self.data_section.uninitialized.is_empty() {
+            result.push_str(&format!("\n{}\n", Section::Bss.show_color()));
+            for data in &self.data_section.uninitialized {
+                result.push_str(&data.show_color());
+            }
+        }
         if !self.data_section.empty() && !self.text_section.empty() {
             result.push('\n'); // Add a newline between sections
         }
         // Text
         if !self.text_section.empty() {
-            result.push_str(&format!("{}\n", Section::Text.show_color()));
+            result.push_str(&Section::Text.show_placed_color(self.text_section.base));
             result.push_str(&self.text_section.show_color());
         }
         result
     }
 }
+
+impl Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.show())
+    }
+}
+
+/// Rewrite obvious identities in the text section into the simpler instruction they compute,
+/// returning the number of rewritten instructions:
+/// - `add`, `addu`, `or` and `xor` with a `$zero` operand, `sub`/`subu` of `$zero`
+///   and shifts by 0 become `move $d, $s`.
+/// - `ori $d, $zero, imm` becomes `li $d, imm`.
+/// - `nor $d, $zero, $s` becomes the `not` idiom `nor $d, $s, $zero`,
+///   and `nor $d, $zero, $zero` becomes `li $d, 0xFFFFFFFF`.
+///
+/// Every instruction is replaced by exactly one other, so label addresses and branch offsets are unchanged.
+pub fn optimize(program: &mut Program) -> usize {
+    use InstructionArg::{Immediate as Imm, Register as Reg};
+    use InstructionKind::*;
+
+    let zero = Reg(Register::Zero);
+    let mut rewritten = 0;
+    for block in &mut program.text_section.blocks {
+        for instruction in &mut block.instructions {
+            let args = &instruction.args;
+            let (kind, args) = match (&instruction.kind, args.as_slice()) {
+                (Add | Addu | Or | Xor, [d, s, t]) if *t == zero => {
+                    (Move, vec![d.clone(), s.clone()])
+                }
+                (Add | Addu | Or | Xor, [d, s, t]) if *s == zero => {
+                    (Move, vec![d.clone(), t.clone()])
+                }
+                (Sub | Subu, [d, s, t]) if *t == zero => (Move, vec![d.clone(), s.clone()]),
+                (Sll | Srl | Sra, [d, s, Imm(0)]) => (Move, vec![d.clone(), s.clone()]),
+                (Ori, [d, s, Imm(imm)]) if *s == zero => (Li, vec![d.clone(), Imm(*imm)]),
+                (Nor, [d, s, t]) if *s == zero && *t == zero => {
+                    (Li, vec![d.clone(), Imm(0xFFFF_FFFF)])
+                }
+                (Nor, [d, s, t]) if *s == zero => (Nor, vec![d.clone(), t.clone(), zero.clone()]),
+                _ => continue,
+            };
+            log::debug!(
+                "Optimized {} into {}",
+                instruction.show(),
+                Instruction {
+                    kind: kind.clone(),
+                    args: args.clone()
+                }
+                .show()
+            );
+            *instruction = Instruction { kind, args };
+            rewritten += 1;
+        }
+    }
+    rewritten
+}
+
+/// Render the control flow graph of the text section in the Graphviz DOT language.
+///
+/// Every basic block is a node listing its instructions, named after its label,
+/// or the closest label before it and the byte offset from there.
+/// Edges are labeled `branch`, `jump` or `fallthrough`, where calls also fall through to the return address.
+pub fn to_dot(program: &Program) -> String {
+    let instructions = program.text_section.instructions();
+    let mut labels = Vec::new();
+    let mut start = 0;
+    for block in &program.text_section.blocks {
+        if !block.label.is_empty() {
+            labels.push((start, block.label.as_str()));
+        }
+        start += block.instructions.len();
+    }
+    let target = |index: usize, arg: &InstructionArg| match arg {
+        InstructionArg::Label(label) => labels.iter().find(|(_, l)| l == label).map(|(i, _)| *i),
+        InstructionArg::Immediate(offset) => index.checked_add_signed(1 + *offset as i16 as isize),
+        _ => None,
+    };
+    // The target, edge label and whether execution may continue after a control flow instruction
+    let flow = |index: usize, instruction: &Instruction| {
+        let args = &instruction.args;
+        match instruction.kind {
+            InstructionKind::Beq
+            | InstructionKind::Bne
+            | InstructionKind::Blez
+            | InstructionKind::Bgtz => Some((target(index, args.last()?), "branch", true)),
+            InstructionKind::J => Some((target(index, args.first()?), "jump", false)),
+            InstructionKind::Jal => Some((target(index, args.first()?), "jump", true)),
+            InstructionKind::Bal => Some((target(index, args.first()?), "branch", true)),
+            InstructionKind::Jr | InstructionKind::Eret => Some((None, "jump", false)),
+            InstructionKind::Jalr => Some((None, "jump", true)),
+            _ => None,
+        }
+    };
+
+    // Basic blocks begin at labels, at branch targets and after control flow instructions
+    let mut leaders = BTreeSet::from([0]);
+    leaders.extend(labels.iter().map(|(index, _)| *index));
+    for (index, instruction) in instructions.iter().enumerate() {
+        if let Some((target, _, _)) = flow(index, instruction) {
+            leaders.insert(index + 1);
+            leaders.extend(target);
+        }
+    }
+    let leaders = leaders
+        .into_iter()
+        .filter(|leader| *leader < instructions.len())
+        .collect::<Vec<_>>();
+    let name = |leader: usize| {
+        let closest = labels.iter().rev().find(|(index, _)| *index <= leader);
+        let name = match closest {
+            Some((index, label)) if *index == leader => label.to_string(),
+            Some((index, label)) => format!("{}+{}", label, (leader - index) * Instruction::size()),
+            None => format!(".text+{}", leader * Instruction::size()),
+        };
+        name.replace('\\', "\\\\").replace('"', "\\\"")
+    };
+
+    let mut dot =
+        String::from("digraph program {\n    node [shape=box, fontname=\"monospace\"];\n");
+    let mut edges = String::new();
+    for (i, leader) in leaders.iter().enumerate() {
+        let end = leaders.get(i + 1).copied().unwrap_or(instructions.len());
+        let mut label = format!("{}:\\l", name(*leader));
+        for instruction in &instructions[*leader..end] {
+            label.push_str(&instruction.show().replace('"', "\\\""));
+            label.push_str("\\l");
+        }
+        dot.push_str(&format!(
+            "    \"{}\" [label=\"{}\"];\n",
+            name(*leader),
+            label
+        ));
+
+        let mut edge = |to: usize, kind: &str| {
+            edges.push_str(&format!(
+                "    \"{}\" -> \"{}\" [label=\"{}\"];\n",
+                name(*leader),
+                name(to),
+                kind
+            ));
+        };
+        let falls_through = match flow(end - 1, instructions[end - 1]) {
+            Some((target, kind, falls_through)) => {
+                if let Some(target) = target.filter(|target| *target < instructions.len()) {
+                    edge(target, kind);
+                }
+                falls_through
+            }
+            None => true,
+        };
+        if falls_through && end < instructions.len() {
+            edge(end, "fallthrough");
+        }
+    }
+    dot.push_str(&edges);
+    dot.push_str("}\n");
+    dot
+}
+
+/// A loadable segment of a [`LoadedImage`], placed at its virtual address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageSegment {
+    pub address: Address,
+    /// The contents of the segment, zero-filled up to its size in memory.
+    pub data: Vec<u8>,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+/// A pre-assembled program, ready to be loaded with [`crate::memory::Memory::load_image`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadedImage {
+    /// The address execution starts at, also labeled `_start`.
+    pub entry: Address,
+    pub segments: Vec<ImageSegment>,
+    /// The decoded instructions of the executable segment.
+    pub text_instructions: Vec<Instruction>,
+    /// Labels of the entry and of every jump target, named `L_` followed by the hex address.
+    pub labels: LabelMap,
+}
+
+/// The largest segment [`from_elf`] loads, in bytes.
+pub const MAX_SEGMENT_SIZE: usize = 64 * 1024 * 1024;
+
+/// An error produced while reading an ELF file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElfError {
+    /// The file is not an ELF file, or is truncated.
+    Malformed(&'static str),
+    /// The file is a valid ELF file, but not a static little-endian MIPS32 executable.
+    Unsupported(&'static str),
+    /// A word of the executable segment is not a known instruction encoding.
+    UnknownInstruction { address: Address, word: Word },
+}
+
+impl Display for ElfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ElfError::Malformed(reason) => write!(f, "Malformed ELF file: {}", reason),
+            ElfError::Unsupported(reason) => write!(f, "Unsupported ELF file: {}", reason),
+            ElfError::UnknownInstruction { address, word } => {
+                write!(f, "Unknown instruction 0x{:08X} at {}", word, address)
+            }
+        }
+    }
+}
+
+/// Read a static, non-relocated, little-endian MIPS32 ELF executable.
+///
+/// The `PT_LOAD` segments are kept at their virtual addresses, and the single executable segment
+/// is decoded with [`decode_instruction`] using the standard MIPS encoding, as emitted by GNU `as`.
+/// Segments must fit below the stack, and no segment may be larger than [`MAX_SEGMENT_SIZE`].
+///
+/// Execution does not model branch delay slots: the instruction after a jump or branch
+/// is not executed when it is taken. Compile with `-fno-delayed-branch` (or `.set noreorder`
+/// and a `nop` after every jump) so that the code does not depend on them.
+pub fn from_elf(bytes: &[u8]) -> Result<LoadedImage, ElfError> {
+    const PT_LOAD: u32 = 1;
+    const PF_X: u32 = 1;
+    const PF_W: u32 = 2;
+
+    let field = |offset: usize, size: usize| {
+        offset
+            .checked_add(size)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or(ElfError::Malformed("truncated"))
+    };
+    let u16_at = |offset: usize| field(offset, 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at =
+        |offset: usize| field(offset, 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+    if bytes.get(..4) != Some(b"\x7FELF") {
+        return Err(ElfError::Malformed("missing ELF magic"));
+    }
+    if bytes.get(4..6) != Some(&[1, 1]) {
+        return Err(ElfError::Unsupported("not a 32-bit little-endian file"));
+    }
+    if u16_at(16)? != 2 {
+        return Err(ElfError::Unsupported("not an executable"));
+    }
+    if u16_at(18)? != 8 {
+        return Err(ElfError::Unsupported("not a MIPS file"));
+    }
+    let entry = Address::new(u32_at(24)?);
+    let program_headers = u32_at(28)? as usize;
+    let header_size = u16_at(42)? as usize;
+    let header_count = u16_at(44)? as usize;
+
+    let mut segments = Vec::new();
+    for index in 0..header_count {
+        let header = index
+            .checked_mul(header_size)
+            .and_then(|header| header.checked_add(program_headers))
+            .ok_or(ElfError::Malformed("truncated"))?;
+        if u32_at(header)? != PT_LOAD {
+            continue;
+        }
+        let offset = u32_at(header + 4)? as usize;
+        let address = u32_at(header + 8)?;
+        let file_size = u32_at(header + 16)? as usize;
+        let memory_size = u32_at(header + 20)? as usize;
+        let flags = u32_at(header + 24)?;
+        let size = memory_size.max(file_size);
+        if size > MAX_SEGMENT_SIZE {
+            return Err(ElfError::Unsupported("segment too large"));
+        }
+        let end = (address as usize).checked_add(size);
+        if end.is_none_or(|end| end > ANY_DATA_END.unwrap() as usize) {
+            return Err(ElfError::Unsupported("segment overlaps the stack"));
+        }
+        let mut data = field(offset, file_size)
+            .map_err(|_| ElfError::Malformed("segment out of bounds"))?
+            .to_vec();
+        data.resize(size, 0);
+        segments.push(ImageSegment {
+            address: Address::new(address),
+            data,
+            writable: flags & PF_W != 0,
+            executable: flags & PF_X != 0,
+        });
+    }
+
+    let mut text = segments.iter().filter(|segment| segment.executable);
+    let text = match (text.next(), text.next()) {
+        (Some(text), None) => text,
+        (None, _) => return Err(ElfError::Unsupported("no executable segment")),
+        (Some(_), Some(_)) => return Err(ElfError::Unsupported("multiple executable segments")),
+    };
+    let words = text
+        .data
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect::<Vec<_>>();
+
+    // Jumps are decoded to labels, so every jump target needs one
+    let mut labels = LabelMap::new();
+    labels.insert("_start".to_string(), entry);
+    for (index, word) in words.iter().enumerate() {
+        if matches!(word >> 26, 2 | 3) {
+            let delay_slot = text.address.unwrap() + index as u32 * 4 + 4;
+            let target = (delay_slot & 0xF000_0000) | ((word & 0x03FF_FFFF) << 2);
+            labels.insert(format!("L_{:08x}", target), Address::new(target));
+        }
+    }
+    let text_instructions = words
+        .iter()
+        .enumerate()
+        .map(|(index, word)| {
+            let address = text.address + index * Instruction::size();
+            decode_instruction(*word, address, &labels).ok_or(ElfError::UnknownInstruction {
+                address,
+                word: *word,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(LoadedImage {
+        entry,
+        segments,
+        text_instructions,
+        labels,
+    })
+}
+
+#[cfg(test)]
+mod test_program {
+    use super::{optimize, to_dot, Instruction, InstructionArg, InstructionKind, StaticData};
+    use crate::{
+        parser::{parse, parse_result},
+        registers::Register,
+        vm::VM,
+    };
+
+    /// Render `source` with `Display`, reparse it, and check that the program is unchanged.
+    /// Assertions are comments, which are not rendered.
+    fn round_trip(source: &str) {
+        let program = parse_result(source).unwrap();
+        let rendered = program.to_string();
+        let reparsed = parse_result(&rendered)
+            .unwrap_or_else(|err| panic!("Failed to reparse: {}\n{}", err, rendered));
+        assert_eq!(program.data_section, reparsed.data_section, "{}", rendered);
+        assert_eq!(program.text_section, reparsed.text_section, "{}", rendered);
+    }
+
+    #[test]
+    fn display_examples() {
+        round_trip(include_str!("../../examples/hello_world.asm"));
+        // mem_text.asm is left out, its immediate does not fit in 16 bits
+        round_trip(include_str!("../../examples/mmio_hello_world.asm"));
+    }
+
+    #[test]
+    fn display_all_sections() {
+        round_trip(
+            ".globl main
+.data
+msg: .asciiz \"a\\tb\\n\"
+words: .word 1, 0x2A:3, 7
+.rdata
+pi: .word 3
+.bss
+buffer: .space 16
+.text
+main:
+\tla $t0, words
+\tlw $t1, -4($sp)
+\tsw $t1, buffer($t0)
+1:
+\taddiu $t1, $t1, 1
+\tbne $t1, $zero, 1b
+\tli $v0, 10
+\tsyscall
+",
+        );
+    }
+
+    #[test]
+    fn typed_constructors() {
+        let add = Instruction::r_type(
+            InstructionKind::Add,
+            Register::T0,
+            Register::T1,
+            Register::T2,
+        );
+        assert_eq!(
+            add.args,
+            vec![
+                InstructionArg::Register(Register::T0),
+                InstructionArg::Register(Register::T1),
+                InstructionArg::Register(Register::T2),
+            ]
+        );
+        let lw = Instruction::load(InstructionKind::Lw, Register::T0, -8, Register::Sp);
+        assert_eq!(
+            lw.args,
+            vec![
+                InstructionArg::Register(Register::T0),
+                InstructionArg::RegisterOffset(0xFFF8, Register::Sp),
+            ]
+        );
+
+        // The same instructions as parsed from source
+        let program = parse(".text\nmain:\n\tadd $t0, $t1, $t2\n\tlw $t0, -8($sp)\n\tsllv $t0, $t1, $t2\n\tori $t0, $t1, 0xFF\n\tsw $t0, 4($sp)\n\taddi $t0, $t0, -1\n").unwrap();
+        let built = [
+            add,
+            lw,
+            Instruction::r_type(
+                InstructionKind::Sllv,
+                Register::T0,
+                Register::T2,
+                Register::T1,
+            ),
+            Instruction::i_type(InstructionKind::Ori, Register::T0, Register::T1, 0xFF),
+            Instruction::store(InstructionKind::Sw, Register::T0, 4, Register::Sp),
+            Instruction::i_type(
+                InstructionKind::Addi,
+                Register::T0,
+                Register::T0,
+                -1i16 as u16,
+            ),
+        ];
+        assert_eq!(
+            program.text_section.instructions(),
+            built.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn static_data_constructors() {
+        let data = [
+            StaticData::asciiz("msg", "Say \"hi\"\n\\\u{7}"),
+            StaticData::word("words", &[1, -1, 0x1234]),
+            StaticData::bytes("bytes", &[0, 255, 7]),
+            StaticData::space("buffer", 3),
+        ];
+        assert_eq!(data[0].data, b"Say \"hi\"\n\\\x07\0");
+        assert_eq!(
+            data[1].data,
+            [1, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0x34, 0x12, 0, 0]
+        );
+        assert_eq!(data[2].data, [0, 255, 7]);
+        assert_eq!(data[3].data, [0, 0, 0]);
+
+        let source = data.iter().map(StaticData::show).collect::<String>();
+        let program = parse(&format!(".data\n{}.text\nmain:\n\tnop\n", source)).unwrap();
+        assert_eq!(program.data_section.initialized, data);
+    }
+
+    #[test]
+    fn content_hash_ignores_formatting() {
+        let hash = |source: &str| parse(source).unwrap().content_hash();
+        let original = hash(".data\nvalue: .word 42\n.text\nmain:\n\tli $t0, 1\n\tsyscall\n");
+        let reformatted = hash(
+            "# Comment\n.data\nvalue:   .word   42\n\n.text\nmain:\n  li $t0,0x1 # One\n\n\tsyscall\n",
+        );
+        assert_eq!(original, reformatted);
+        let changed = hash(".data\nvalue: .word 42\n.text\nmain:\n\tli $t0, 2\n\tsyscall\n");
+        assert_ne!(original, changed);
+        let data_changed = hash(".data\nvalue: .word 43\n.text\nmain:\n\tli $t0, 1\n\tsyscall\n");
+        assert_ne!(original, data_changed);
+        let read_only = hash(".rdata\nvalue: .word 42\n.text\nmain:\n\tli $t0, 1\n\tsyscall\n");
+        assert_ne!(original, read_only);
+        // Bytes of one field do not run into the next
+        let joined = hash(".data\nab: .byte 1\n.text\nmain:\n\tsyscall\n");
+        let split = hash(".data\na: .byte 98, 1\n.text\nmain:\n\tsyscall\n");
+        assert_ne!(joined, split);
+    }
+
+    #[test]
+    fn optimize_identities() {
+        let source = ".text
+main:
+\tori $t0, $zero, 0x2A
+\tadd $t1, $t0, $zero
+\taddu $t2, $zero, $t1
+\tsll $t3, $t2, 0
+\tnor $t4, $zero, $t3
+\tnor $t5, $zero, $zero
+\tsub $t6, $t0, $t1
+\tli $v0, 10
+\tsyscall
+";
+        let mut program = parse(source).unwrap();
+        assert_eq!(optimize(&mut program), 6);
+        let show = program.text_section.blocks[1]
+            .instructions
+            .iter()
+            .map(|i| i.show())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            show[..7],
+            [
+                "li $t0, 0x2a",
+                "move $t1, $t0",
+                "move $t2, $t1",
+                "move $t3, $t2",
+                "nor $t4, $t3, $zero",
+                "li $t5, 0xffffffff",
+                "sub $t6, $t0, $t1",
+            ]
+        );
+
+        // Both versions compute the same registers
+        let run = |program| {
+            let mut vm = VM::new(program, Vec::new());
+            vm.execute(vm.entrypoint().unwrap()).unwrap();
+            vm.registers().all()
+        };
+        assert_eq!(run(program), run(parse(source).unwrap()));
+    }
+
+    #[test]
+    fn dot_loop_edges() {
+        let program = parse(
+            ".text
+main:
+\tli $t0, 0
+\tli $t1, 3
+loop:
+\taddi $t0, $t0, 1
+\tbne $t0, $t1, loop
+\tj done
+\tnop
+done:
+\tli $v0, 10
+\tsyscall
+",
+        )
+        .unwrap();
+        let dot = to_dot(&program);
+        assert!(dot.starts_with("digraph program {\n"), "{}", dot);
+        assert!(
+            dot.contains(
+                "\"loop\" [label=\"loop:\\laddi $t0, $t0, 0x1\\lbne $t0, $t1, loop\\l\"];"
+            ),
+            "{}",
+            dot
+        );
+        for edge in [
+            "\"main\" -> \"loop\" [label=\"fallthrough\"];",
+            "\"loop\" -> \"loop\" [label=\"branch\"];",
+            "\"loop\" -> \"loop+8\" [label=\"fallthrough\"];",
+            "\"loop+8\" -> \"done\" [label=\"jump\"];",
+            "\"loop+12\" -> \"done\" [label=\"fallthrough\"];",
+        ] {
+            assert!(dot.contains(edge), "missing {} in\n{}", edge, dot);
+        }
+        assert!(!dot.contains("\"loop+8\" -> \"loop+12\""), "{}", dot);
+    }
+}
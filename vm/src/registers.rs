@@ -2,11 +2,66 @@ use std::collections::HashMap;
 
 use colorful::Colorful;
 
-use crate::program::{Word, REGISTER_COLOR};
+use crate::{
+    parser::ParseError,
+    program::{Word, REGISTER_COLOR},
+};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Registers {
     values: HashMap<Register, Word>,
+    /// High word of multiplication and division results.
+    pub hi: Word,
+    /// Low word of multiplication and division results.
+    pub lo: Word,
+    /// Address of the instruction being executed or to execute next,
+    /// or of the last executed one after the VM halts.
+    pub pc: Word,
+    /// Floating point registers `$f0`-`$f31` of coprocessor 1, as raw bits.
+    pub fpr: [Word; 32],
+    /// Exception registers of coprocessor 0.
+    pub cp0: Cp0,
+}
+
+/// The coprocessor 0 registers describing the last exception,
+/// see [`crate::vm::VM::set_exception_handler`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Cp0 {
+    /// The address that caused an address error (`BadVAddr`).
+    pub bad_vaddr: Word,
+    /// Processor status, with the exception level bit [`Cp0::EXL`] set while handling an exception (`Status`).
+    pub status: Word,
+    /// The [`crate::vm::ExceptionCode`] of the last exception in bits 2 to 6 (`Cause`).
+    pub cause: Word,
+    /// The address of the instruction that raised the last exception (`EPC`).
+    pub epc: Word,
+}
+
+impl Cp0 {
+    /// Exception level bit of the `Status` register.
+    pub const EXL: Word = 1 << 1;
+
+    /// The value of coprocessor 0 register `number`, or 0 if it is not modeled.
+    pub fn get(&self, number: u8) -> Word {
+        match number {
+            8 => self.bad_vaddr,
+            12 => self.status,
+            13 => self.cause,
+            14 => self.epc,
+            _ => 0,
+        }
+    }
+
+    /// Set coprocessor 0 register `number`,
+    /// ignoring the read-only `BadVAddr` and registers that are not modeled.
+    pub fn set(&mut self, number: u8, value: Word) {
+        match number {
+            12 => self.status = value,
+            13 => self.cause = value,
+            14 => self.epc = value,
+            _ => {}
+        }
+    }
 }
 
 impl Registers {
@@ -20,6 +75,88 @@ impl Registers {
     pub fn set(&mut self, register: &Register, value: Word) {
         self.values.insert(*register, value);
     }
+
+    /// The double held by the floating point register pair `$f{even}`/`$f{even + 1}`,
+    /// with the low word in the even register.
+    pub fn get_double(&self, even: usize) -> f64 {
+        assert!(
+            even.is_multiple_of(2),
+            "Double registers must be even: $f{}",
+            even
+        );
+        let bits = (self.fpr[even + 1] as u64) << 32 | self.fpr[even] as u64;
+        f64::from_bits(bits)
+    }
+
+    /// Store a double in the floating point register pair `$f{even}`/`$f{even + 1}`.
+    pub fn set_double(&mut self, even: usize, value: f64) {
+        assert!(
+            even.is_multiple_of(2),
+            "Double registers must be even: $f{}",
+            even
+        );
+        let bits = value.to_bits();
+        self.fpr[even] = bits as Word;
+        self.fpr[even + 1] = (bits >> 32) as Word;
+    }
+
+    /// The values of all registers, ordered by their encoding.
+    pub fn all(&self) -> [Word; 32] {
+        Register::ALL.map(|register| self.get(&register))
+    }
+
+    /// Show all registers in a grid of four columns, followed by HI, LO and PC.
+    pub fn show_all(&self) -> String {
+        let mut output = String::new();
+        for row in Register::ALL.chunks(4) {
+            let line = row
+                .iter()
+                .map(|r| format!("{:>5} = 0x{:08X}", r.show(), self.get(r)))
+                .collect::<Vec<_>>()
+                .join("  ");
+            output.push_str(&line);
+            output.push('\n');
+        }
+        output.push_str(&format!(
+            "{:>5} = 0x{:08X}  {:>5} = 0x{:08X}  {:>5} = 0x{:08X}\n",
+            "hi", self.hi, "lo", self.lo, "pc", self.pc
+        ));
+        output
+    }
+
+    /// Show all registers as a JSON object mapping register names to their values.
+    pub fn show_json(&self) -> String {
+        let fields = Register::ALL
+            .iter()
+            .map(|r| (r.show(), self.get(r)))
+            .chain([("hi", self.hi), ("lo", self.lo), ("pc", self.pc)])
+            .map(|(name, value)| format!("\"{}\": {}", name, value))
+            .collect::<Vec<_>>();
+        format!("{{{}}}\n", fields.join(", "))
+    }
+}
+
+/// The role of a register in the MIPS calling convention.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AbiRole {
+    /// `$zero`, hardwired to 0.
+    Zero,
+    /// `$at`, reserved for pseudo instruction expansion.
+    AssemblerTemporary,
+    /// `$v0`-`$v1`, the return value registers by index.
+    ReturnValue(u8),
+    /// `$a0`-`$a3`, the argument registers by index.
+    Argument(u8),
+    /// `$t0`-`$t9`, not preserved across calls.
+    Temporary,
+    /// `$s0`-`$s7`, preserved across calls.
+    Saved,
+    /// `$k0`-`$k1`, reserved for the kernel.
+    Kernel,
+    GlobalPointer,
+    StackPointer,
+    FramePointer,
+    ReturnAddress,
 }
 
 /// Represents a MIPS register.
@@ -93,10 +230,99 @@ pub enum Register {
 }
 
 impl Register {
+    /// All registers, ordered by their encoding.
+    pub const ALL: [Register; 32] = [
+        Register::Zero,
+        Register::At,
+        Register::V0,
+        Register::V1,
+        Register::A0,
+        Register::A1,
+        Register::A2,
+        Register::A3,
+        Register::T0,
+        Register::T1,
+        Register::T2,
+        Register::T3,
+        Register::T4,
+        Register::T5,
+        Register::T6,
+        Register::T7,
+        Register::S0,
+        Register::S1,
+        Register::S2,
+        Register::S3,
+        Register::S4,
+        Register::S5,
+        Register::S6,
+        Register::S7,
+        Register::T8,
+        Register::T9,
+        Register::K0,
+        Register::K1,
+        Register::Gp,
+        Register::Sp,
+        Register::Fp,
+        Register::Ra,
+    ];
+
     pub fn encode(&self) -> u8 {
         *self as u8
     }
 
+    /// Get the register with the given encoding (`0..=31`).
+    pub fn decode(number: u8) -> Option<Register> {
+        Register::ALL.get(number as usize).copied()
+    }
+
+    /// The role of the register in the calling convention.
+    pub fn abi_role(&self) -> AbiRole {
+        match self {
+            Register::Zero => AbiRole::Zero,
+            Register::At => AbiRole::AssemblerTemporary,
+            Register::V0 | Register::V1 => AbiRole::ReturnValue(self.encode() - 2),
+            Register::A0 | Register::A1 | Register::A2 | Register::A3 => {
+                AbiRole::Argument(self.encode() - 4)
+            }
+            Register::S0
+            | Register::S1
+            | Register::S2
+            | Register::S3
+            | Register::S4
+            | Register::S5
+            | Register::S6
+            | Register::S7 => AbiRole::Saved,
+            Register::K0 | Register::K1 => AbiRole::Kernel,
+            Register::Gp => AbiRole::GlobalPointer,
+            Register::Sp => AbiRole::StackPointer,
+            Register::Fp => AbiRole::FramePointer,
+            Register::Ra => AbiRole::ReturnAddress,
+            _ => AbiRole::Temporary,
+        }
+    }
+
+    /// Whether a callee must restore the register before returning:
+    /// `$s0`-`$s7`, `$gp`, `$sp` and `$fp`.
+    pub fn is_callee_saved(&self) -> bool {
+        matches!(
+            self.abi_role(),
+            AbiRole::Saved | AbiRole::GlobalPointer | AbiRole::StackPointer | AbiRole::FramePointer
+        )
+    }
+
+    /// Whether a callee may overwrite the register, so the caller must save it across calls:
+    /// `$at`, `$v0`-`$v1`, `$a0`-`$a3`, `$t0`-`$t9` and `$ra`.
+    pub fn is_caller_saved(&self) -> bool {
+        matches!(
+            self.abi_role(),
+            AbiRole::AssemblerTemporary
+                | AbiRole::ReturnValue(_)
+                | AbiRole::Argument(_)
+                | AbiRole::Temporary
+                | AbiRole::ReturnAddress
+        )
+    }
+
     pub fn show(&self) -> &str {
         match self {
             Register::Zero => "$zero",
@@ -139,11 +365,17 @@ impl Register {
     }
 }
 
-impl From<&str> for Register {
-    fn from(s: &str) -> Register {
-        match s {
+impl TryFrom<&str> for Register {
+    type Error = ParseError;
+
+    fn try_from(s: &str) -> Result<Register, ParseError> {
+        // Numbered register syntax: `$0` to `$31`
+        if let Some(number) = s.strip_prefix('$').and_then(|n| n.parse::<u8>().ok()) {
+            return Register::decode(number)
+                .ok_or_else(|| ParseError::new(format!("Invalid register: {}", s)));
+        }
+        Ok(match s {
             "$zero" => Register::Zero,
-            "$0" => Register::Zero,
             "$at" => Register::At,
             "$v0" => Register::V0,
             "$v1" => Register::V1,
@@ -175,7 +407,47 @@ impl From<&str> for Register {
             "$sp" => Register::Sp,
             "$fp" => Register::Fp,
             "$ra" => Register::Ra,
-            _ => panic!("Invalid register: {}", s),
-        }
+            _ => return Err(ParseError::new(format!("Invalid register: {}", s))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_registers {
+    use super::{AbiRole, Register, Registers};
+
+    #[test]
+    fn numbered_registers() {
+        assert_eq!(Register::try_from("$0"), Ok(Register::Zero));
+        assert_eq!(Register::try_from("$8"), Ok(Register::T0));
+        assert_eq!(Register::try_from("$16"), Ok(Register::S0));
+        assert_eq!(Register::try_from("$31"), Ok(Register::Ra));
+        let err = Register::try_from("$32").unwrap_err();
+        assert_eq!(err.message, "Invalid register: $32");
+    }
+
+    #[test]
+    fn show_all() {
+        let mut registers = Registers::default();
+        registers.set(&Register::V0, 10);
+        registers.pc = 0x0040_0004;
+        let grid = registers.show_all();
+        assert_eq!(grid.lines().count(), 9);
+        assert!(grid.starts_with("$zero = 0x00000000    $at = 0x00000000    $v0 = 0x0000000A"));
+        assert!(grid.ends_with("pc = 0x00400004\n"));
+        let json = registers.show_json();
+        assert!(json.starts_with("{\"$zero\": 0, \"$at\": 0, \"$v0\": 10,"));
+        assert!(json.ends_with("\"pc\": 4194308}\n"));
+    }
+
+    #[test]
+    fn abi_roles() {
+        assert_eq!(Register::V1.abi_role(), AbiRole::ReturnValue(1));
+        assert_eq!(Register::A2.abi_role(), AbiRole::Argument(2));
+        assert_eq!(Register::T9.abi_role(), AbiRole::Temporary);
+        assert!(Register::S0.is_callee_saved() && !Register::S0.is_caller_saved());
+        assert!(Register::T0.is_caller_saved() && !Register::T0.is_callee_saved());
+        assert!(Register::Sp.is_callee_saved());
+        assert!(!Register::Zero.is_caller_saved() && !Register::Zero.is_callee_saved());
     }
 }
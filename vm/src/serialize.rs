@@ -0,0 +1,362 @@
+use std::fmt::Display;
+
+use crate::{
+    address::Address,
+    program::{
+        Assertion, Block, DataSection, Instruction, InstructionArg, InstructionKind, Program,
+        ReservedData, StaticData, TextSection,
+    },
+    registers::Register,
+};
+
+/// Version of the encoding written by [`Program::to_bytes`],
+/// incremented whenever the layout changes so that stale caches are rejected.
+pub const FORMAT_VERSION: u8 = 3;
+
+/// An error produced while decoding a program with [`Program::from_bytes`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The bytes were encoded by another version of the format.
+    Version(u8),
+    /// The bytes end in the middle of a value.
+    Truncated,
+    /// The bytes do not encode a program.
+    Malformed(&'static str),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Version(version) => write!(
+                f,
+                "Unsupported format version {}, expected {}",
+                version, FORMAT_VERSION
+            ),
+            DecodeError::Truncated => write!(f, "Truncated program"),
+            DecodeError::Malformed(reason) => write!(f, "Malformed program: {}", reason),
+        }
+    }
+}
+
+impl Program {
+    /// Encode the program in a compact binary format, e.g. to cache parsed programs.
+    ///
+    /// The first byte is the [`FORMAT_VERSION`], followed by the fields in declaration order.
+    /// Integers and lengths are LEB128 varints, strings are UTF-8 prefixed by their length,
+    /// and instructions are stored by mnemonic.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer(vec![FORMAT_VERSION]);
+        let data = &self.data_section;
+        writer.list(&data.initialized, Writer::static_data);
+        writer.list(&data.read_only, Writer::static_data);
+        writer.list(&data.uninitialized, |writer, data| {
+            writer.string(&data.label);
+            writer.string(&data.source);
+            writer.varint(data.size as u64);
+        });
+        writer.list(&data.global_labels, |writer, label| writer.string(label));
+        writer.base(data.base);
+
+        let text = &self.text_section;
+        writer.list(&text.blocks, |writer, block| {
+            writer.string(&block.label);
+            writer.list(&block.instructions, |writer, instruction| {
+                writer.string(instruction.kind.show());
+                writer.list(&instruction.args, Writer::arg);
+            });
+        });
+        writer.list(&text.global_labels, |writer, label| writer.string(label));
+        writer.base(text.base);
+
+        writer.list(&self.assertions, |writer, assertion| {
+            writer.0.push(assertion.register as u8);
+            writer.varint(assertion.expected as u64);
+            match &assertion.file {
+                Some(file) => {
+                    writer.0.push(1);
+                    writer.string(file);
+                }
+                None => writer.0.push(0),
+            }
+            writer.varint(assertion.line as u64);
+        });
+        writer.0
+    }
+
+    /// Decode a program encoded by [`Program::to_bytes`],
+    /// failing if it was encoded by another version of the format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program, DecodeError> {
+        let mut reader = Reader { bytes, position: 0 };
+        let version = reader.byte()?;
+        if version != FORMAT_VERSION {
+            return Err(DecodeError::Version(version));
+        }
+        let data_section = DataSection {
+            initialized: reader.list(Reader::static_data)?,
+            read_only: reader.list(Reader::static_data)?,
+            uninitialized: reader.list(|reader| {
+                Ok(ReservedData {
+                    label: reader.string()?,
+                    source: reader.string()?,
+                    size: reader.varint()? as usize,
+                })
+            })?,
+            global_labels: reader.list(Reader::string)?,
+            base: reader.base()?,
+        };
+        let text_section = TextSection {
+            blocks: reader.list(|reader| {
+                Ok(Block {
+                    label: reader.string()?,
+                    instructions: reader.list(|reader| {
+                        let kind = InstructionKind::try_from(reader.string()?.as_str())
+                            .map_err(|_| DecodeError::Malformed("unknown instruction"))?;
+                        Ok(Instruction {
+                            kind,
+                            args: reader.list(Reader::arg)?,
+                        })
+                    })?,
+                })
+            })?,
+            global_labels: reader.list(Reader::string)?,
+            base: reader.base()?,
+        };
+        let assertions = reader.list(|reader| {
+            Ok(Assertion {
+                register: reader.register()?,
+                expected: reader.word()?,
+                file: match reader.byte()? {
+                    0 => None,
+                    1 => Some(reader.string()?),
+                    _ => return Err(DecodeError::Malformed("invalid assertion file")),
+                },
+                line: reader.varint()? as usize,
+            })
+        })?;
+        if reader.position != bytes.len() {
+            return Err(DecodeError::Malformed("trailing bytes"));
+        }
+        Ok(Program {
+            data_section,
+            text_section,
+            assertions,
+        })
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, string: &str) {
+        self.bytes(string.as_bytes());
+    }
+
+    fn list<T>(&mut self, items: &[T], mut write: impl FnMut(&mut Self, &T)) {
+        self.varint(items.len() as u64);
+        for item in items {
+            write(self, item);
+        }
+    }
+
+    fn base(&mut self, base: Option<Address>) {
+        match base {
+            Some(base) => {
+                self.0.push(1);
+                self.varint(base.unwrap() as u64);
+            }
+            None => self.0.push(0),
+        }
+    }
+
+    fn static_data(&mut self, data: &StaticData) {
+        self.string(&data.label);
+        self.string(&data.source);
+        self.bytes(&data.data);
+        self.varint(data.element_size as u64);
+    }
+
+    fn arg(&mut self, arg: &InstructionArg) {
+        match arg {
+            InstructionArg::Register(register) => {
+                self.0.push(0);
+                self.0.push(*register as u8);
+            }
+            InstructionArg::Immediate(value) => {
+                self.0.push(1);
+                self.varint(*value as u64);
+            }
+            InstructionArg::RegisterOffset(offset, register) => {
+                self.0.push(2);
+                self.varint(*offset as u64);
+                self.0.push(*register as u8);
+            }
+            InstructionArg::Label(label) => {
+                self.0.push(3);
+                self.string(label);
+            }
+            InstructionArg::LabelOffset(label, register) => {
+                self.0.push(4);
+                self.string(label);
+                self.0.push(*register as u8);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or(DecodeError::Truncated)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::Malformed("varint too long"))
+    }
+
+    fn word(&mut self) -> Result<u32, DecodeError> {
+        u32::try_from(self.varint()?).map_err(|_| DecodeError::Malformed("word out of range"))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let len = self.varint()? as usize;
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(DecodeError::Truncated)?;
+        let bytes = self.bytes[self.position..end].to_vec();
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        String::from_utf8(self.bytes()?).map_err(|_| DecodeError::Malformed("invalid UTF-8"))
+    }
+
+    fn list<T>(
+        &mut self,
+        mut read: impl FnMut(&mut Self) -> Result<T, DecodeError>,
+    ) -> Result<Vec<T>, DecodeError> {
+        let len = self.varint()? as usize;
+        // Every item takes at least a byte, so a longer list is truncated
+        if len > self.bytes.len() - self.position {
+            return Err(DecodeError::Truncated);
+        }
+        (0..len).map(|_| read(self)).collect()
+    }
+
+    fn base(&mut self) -> Result<Option<Address>, DecodeError> {
+        match self.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some(Address::new(self.word()?))),
+            _ => Err(DecodeError::Malformed("invalid section base")),
+        }
+    }
+
+    fn register(&mut self) -> Result<Register, DecodeError> {
+        Register::decode(self.byte()?).ok_or(DecodeError::Malformed("invalid register"))
+    }
+
+    fn static_data(&mut self) -> Result<StaticData, DecodeError> {
+        Ok(StaticData {
+            label: self.string()?,
+            source: self.string()?,
+            data: self.bytes()?,
+            element_size: match self.varint()? {
+                size @ (1 | 4) => size as usize,
+                _ => return Err(DecodeError::Malformed("invalid data element size")),
+            },
+        })
+    }
+
+    fn arg(&mut self) -> Result<InstructionArg, DecodeError> {
+        Ok(match self.byte()? {
+            0 => InstructionArg::Register(self.register()?),
+            1 => InstructionArg::Immediate(self.word()?),
+            2 => InstructionArg::RegisterOffset(self.word()?, self.register()?),
+            3 => InstructionArg::Label(self.string()?),
+            4 => InstructionArg::LabelOffset(self.string()?, self.register()?),
+            _ => return Err(DecodeError::Malformed("invalid argument")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_serialize {
+    use super::{DecodeError, FORMAT_VERSION};
+    use crate::{parser::parse, program::Program};
+
+    #[test]
+    fn round_trip() {
+        let program = parse(include_str!("../../examples/hello_world.asm")).unwrap();
+        let bytes = program.to_bytes();
+        assert_eq!(bytes[0], FORMAT_VERSION);
+        assert_eq!(Program::from_bytes(&bytes), Ok(program));
+
+        let source = ".data 0x10020000
+value: .word 1, 2
+.rdata
+pi: .word 3
+.bss
+buffer: .space 8
+.text
+.globl main
+main:
+\tlw $t0, value($zero)
+\tsw $t0, -4($sp)
+\tbeq $t0, $zero, main
+\tli $v0, 10 #assert $t0 == 1
+\tsyscall
+";
+        let program = parse(source).unwrap();
+        assert_eq!(Program::from_bytes(&program.to_bytes()), Ok(program));
+    }
+
+    #[test]
+    fn rejected() {
+        let mut bytes = parse(".text\nmain:\n\tnop\n").unwrap().to_bytes();
+        assert_eq!(
+            Program::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::Truncated)
+        );
+        bytes[0] = FORMAT_VERSION + 1;
+        assert_eq!(
+            Program::from_bytes(&bytes).unwrap_err().to_string(),
+            format!(
+                "Unsupported format version {}, expected {}",
+                FORMAT_VERSION + 1,
+                FORMAT_VERSION
+            )
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use crate::{
+    address::Address,
+    memory::{Memory, MemoryError},
+    program::Word,
+    registers::{Register, Registers},
+};
+
+/// The outcome of a syscall passed to a [`SyscallHandler`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyscallResult {
+    /// The syscall was handled, continue execution.
+    Continue,
+    /// The syscall was handled, stop execution with the exit code.
+    Exit(i32),
+    /// The handler does not implement the syscall number.
+    Unhandled,
+}
+
+/// Custom syscalls of an embedding application, see [`crate::vm::VM::set_syscall_handler`].
+pub trait SyscallHandler {
+    /// Handle syscall `number`, the value of `$v0` when the `syscall` instruction executed.
+    fn handle(&mut self, number: Word, vm: &mut VmContext) -> SyscallResult;
+}
+
+impl<F: FnMut(Word, &mut VmContext) -> SyscallResult> SyscallHandler for F {
+    fn handle(&mut self, number: Word, vm: &mut VmContext) -> SyscallResult {
+        self(number, vm)
+    }
+}
+
+/// The parts of the VM a [`SyscallHandler`] may access: the registers and memory.
+pub struct VmContext<'a> {
+    registers: &'a mut Registers,
+    memory: &'a mut Memory,
+}
+
+impl<'a> VmContext<'a> {
+    pub(crate) fn new(registers: &'a mut Registers, memory: &'a mut Memory) -> Self {
+        VmContext { registers, memory }
+    }
+
+    pub fn registers(&self) -> &Registers {
+        self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        self.registers
+    }
+
+    pub fn memory(&self) -> &Memory {
+        self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        self.memory
+    }
+
+    /// The value of an argument register, e.g. `$a0`.
+    pub fn read_arg(&self, register: Register) -> Word {
+        self.registers.get(&register)
+    }
+
+    /// Return `value` from the syscall in `$v0`.
+    pub fn set_return(&mut self, value: Word) {
+        self.registers.set(&Register::V0, value);
+    }
+
+    /// Read the null-terminated string at `address`, excluding the terminator.
+    pub fn read_cstr(&mut self, address: Address) -> Result<Vec<u8>, MemoryError> {
+        self.memory.read_cstr(address)
+    }
+}
@@ -1,29 +1,783 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fmt::{Display, LowerExp},
+    io::{BufRead, Write},
+    str::FromStr,
+};
+
 use colorful::Colorful;
 
 use crate::address::Address;
-use crate::memory::MemorySegment;
+use crate::io::{default_input, default_output, Input, Output, SharedBuffer};
+use crate::memory::{Endianness, MemoryError, MemorySegment, TEXT_MAX, TEXT_START};
+use crate::syscall::{SyscallHandler, SyscallResult, VmContext};
 use crate::{
     memory::Memory,
-    program::{Instruction, InstructionArg, InstructionKind, Program, Word, LABEL_COLOR},
-    registers::{Register, Registers},
+    program::{
+        Assertion, Instruction, InstructionArg, InstructionKind, LoadedImage, Program, Word,
+        LABEL_COLOR,
+    },
+    registers::{Cp0, Register, Registers},
 };
 
+/// The largest field width printed by the padded integer syscall, wider fields are clamped to it.
+const MAX_PRINT_WIDTH: usize = 255;
+
+/// Errors that can occur while executing a program.
+#[derive(Debug, PartialEq)]
+pub enum VmError {
+    /// A jump targeted an address outside the `.text` section,
+    /// e.g. `jr $ra` before `$ra` was set by a `jal`, or `j` to a `.data` label.
+    /// The `register` holds the `value` jumped to by a `jr`/`jalr`, `None` for other jumps.
+    BadJumpTarget {
+        register: Option<Register>,
+        value: Word,
+    },
+    /// A memory access failed, e.g. a store into the read-only `.text` section.
+    MemoryFault {
+        address: Address,
+        error: MemoryError,
+    },
+    /// A word or halfword access to an address not aligned to its size,
+    /// raised only with strict alignment enabled.
+    AddressError { address: Address, alignment: u32 },
+    /// A printed string at `address` is longer than the maximum print length,
+    /// e.g. because it is missing its null terminator.
+    OutputLimitExceeded { address: Address, limit: usize },
+    /// A syscall number handled neither by the syscall handler nor the built-in syscalls.
+    UnknownSyscall(Word),
+    /// A `div`/`divu` by zero, whose result MIPS leaves unpredictable.
+    DivisionByZero,
+    /// A line of input read by a read syscall is not a valid `expected` value.
+    InputParseError {
+        expected: &'static str,
+        input: String,
+    },
+    /// Reading a line of input failed, e.g. because it is not valid UTF-8.
+    InputError(String),
+    /// The instructions of a program do not fit in the `.text` section.
+    ProgramTooLarge { size: usize, max: usize },
+    /// The program could not be loaded into memory, e.g. because it jumps to an undefined label.
+    LoadFailed(MemoryError),
+    /// A signed `add`, `addi` or `sub` overflowed.
+    ArithmeticOverflow,
+    /// A `break` instruction was executed.
+    Break,
+    /// Execution ran past the last instruction of the `.text` section at `address`,
+    /// e.g. because the program does not end with an exit syscall.
+    FellOffText(Address),
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmError::BadJumpTarget {
+                register: Some(register),
+                value,
+            } => write!(
+                f,
+                "Bad jump target: {} = 0x{:08X} is outside the .text section",
+                register.show(),
+                value
+            ),
+            VmError::BadJumpTarget {
+                register: None,
+                value,
+            } => write!(
+                f,
+                "Bad jump target: 0x{:08X} is outside the .text section",
+                value
+            ),
+            VmError::MemoryFault { address, error } => {
+                write!(f, "Memory fault at {}: {:?}", address, error)
+            }
+            VmError::AddressError { address, alignment } => write!(
+                f,
+                "Address error: {} is not aligned to {} bytes",
+                address, alignment
+            ),
+            VmError::OutputLimitExceeded { address, limit } => write!(
+                f,
+                "Output limit exceeded: string at {} is longer than {} bytes",
+                address, limit
+            ),
+            VmError::UnknownSyscall(number) => write!(f, "Unknown syscall number: {}", number),
+            VmError::DivisionByZero => write!(f, "Division by zero"),
+            VmError::InputParseError { expected, input } => {
+                write!(f, "Invalid input, expected {}: {:?}", expected, input)
+            }
+            VmError::InputError(error) => write!(f, "Failed to read input: {}", error),
+            VmError::ProgramTooLarge { size, max } => write!(
+                f,
+                "Program too large: {} bytes of instructions, but the .text section holds at most {} bytes",
+                size, max
+            ),
+            VmError::LoadFailed(MemoryError::Assemble(errors)) => {
+                let errors = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                write!(f, "Failed to load the program: {}", errors.join(", "))
+            }
+            VmError::LoadFailed(error) => write!(f, "Failed to load the program: {:?}", error),
+            VmError::ArithmeticOverflow => write!(f, "Arithmetic overflow"),
+            VmError::Break => write!(f, "Break instruction executed"),
+            VmError::FellOffText(address) => write!(
+                f,
+                "Execution fell off the end of .text at {}, add an exit syscall or a jump",
+                address
+            ),
+        }
+    }
+}
+
+/// The exception codes set in the coprocessor 0 `Cause` register when an exception is raised,
+/// see [`VM::set_exception_handler`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExceptionCode {
+    /// A misaligned load or instruction fetch (`AdEL`).
+    AddressErrorLoad = 4,
+    /// A misaligned store (`AdES`).
+    AddressErrorStore = 5,
+    /// A syscall the VM does not handle (`Sys`).
+    Syscall = 8,
+    /// A `break` instruction (`Bp`).
+    Breakpoint = 9,
+    /// A signed arithmetic overflow (`Ov`).
+    Overflow = 12,
+}
+
+/// The observable result of a [`VM::run_captured`] execution.
+#[derive(Debug, PartialEq)]
+pub struct RunResult {
+    /// Everything the program printed.
+    pub stdout: String,
+    /// The final register values, ordered by their encoding.
+    pub registers: [Word; 32],
+    /// The number of executed instructions.
+    pub instructions: u64,
+    /// The exit code, if the program exited through a syscall.
+    pub exit: Option<i32>,
+    /// The outcome of every `#assert` comment of the program.
+    pub assertions: Vec<AssertionResult>,
+}
+
+/// The outcome of an `#assert` comment, see [`VM::check_assertions`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssertionResult {
+    pub assertion: Assertion,
+    /// The value of the register when checked.
+    pub actual: Word,
+}
+
+impl AssertionResult {
+    pub fn passed(&self) -> bool {
+        self.actual == self.assertion.expected
+    }
+
+    /// Show the outcome, e.g. `FAIL line 3: $t0 == 0x2A (was 0x29)`.
+    pub fn show(&self) -> String {
+        if self.passed() {
+            format!(
+                "PASS {}: {}",
+                self.assertion.location(),
+                self.assertion.show()
+            )
+        } else {
+            format!(
+                "FAIL {}: {} (was 0x{:X})",
+                self.assertion.location(),
+                self.assertion.show(),
+                self.actual
+            )
+        }
+    }
+}
+
+/// An executed instruction, passed to the trace hook.
+pub struct TraceStep<'a> {
+    /// Address of the executed instruction.
+    pub pc: Address,
+    pub instruction: &'a Instruction,
+    /// The registers the instruction changed, with their old and new values.
+    pub changes: Vec<(Register, Word, Word)>,
+}
+
+/// Called after every executed instruction, see [`VM::set_trace_hook`].
+pub type TraceHook = Box<dyn FnMut(&TraceStep)>;
+
+/// Format a float printed by the print float and double syscalls with `precision` decimal places,
+/// or by default like MARS (Java's `Float.toString`/`Double.toString`):
+/// the shortest digits that read back as the same value with at least one decimal,
+/// in scientific notation below 10^-3 and from 10^7, e.g. `1.0`, `0.33333334` and `1.5E-5`.
+fn format_float<F: Display + LowerExp + Into<f64> + Copy>(
+    value: F,
+    precision: Option<usize>,
+) -> String {
+    let magnitude = value.into().abs();
+    if let Some(precision) = precision {
+        return format!("{:.*}", precision, value);
+    }
+    if magnitude.is_nan() {
+        return "NaN".to_string();
+    }
+    if magnitude.is_infinite() {
+        return if value.into() < 0.0 {
+            "-Infinity"
+        } else {
+            "Infinity"
+        }
+        .to_string();
+    }
+    let with_decimal = |digits: &str| match digits.contains('.') {
+        true => digits.to_string(),
+        false => format!("{}.0", digits),
+    };
+    if magnitude == 0.0 || (1e-3..1e7).contains(&magnitude) {
+        return with_decimal(&value.to_string());
+    }
+    let scientific = format!("{:e}", value);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    format!("{}E{}", with_decimal(mantissa), exponent)
+}
+
+/// The estimated number of cycles an instruction takes, see [`VM::set_cost_model`].
+pub type CostModel = Box<dyn Fn(&InstructionKind) -> u32>;
+
+/// The default cost model: one cycle for ALU operations, branches and jumps,
+/// two for memory accesses and for `li`/`la` (expanded to `lui` and `ori`),
+/// and more for multiplication and division.
+pub fn cycle_cost(kind: &InstructionKind) -> u32 {
+    match kind {
+        InstructionKind::Lb
+        | InstructionKind::Lbu
+        | InstructionKind::Lh
+        | InstructionKind::Lhu
+        | InstructionKind::Lw
+        | InstructionKind::Lwl
+        | InstructionKind::Lwr
+        | InstructionKind::Sb
+        | InstructionKind::Sh
+        | InstructionKind::Sw
+        | InstructionKind::Swl
+        | InstructionKind::Swr => 2,
+        InstructionKind::Li | InstructionKind::La => 2,
+        InstructionKind::Mult | InstructionKind::Multu => 4,
+        InstructionKind::Div | InstructionKind::Divu => 12,
+        _ => 1,
+    }
+}
+
+/// Maximum number of calls recorded for [`VM::backtrace`], the outermost calls are dropped beyond it.
+pub const MAX_CALL_DEPTH: usize = 1024;
+
+/// A call not yet returned from.
+#[derive(Clone)]
+struct Call {
+    /// Address of the `jal`, `jalr` or `bal` instruction.
+    address: Address,
+    /// Address of the instruction after the call, jumping to it returns from the call.
+    return_address: Address,
+    /// Whether the callee stored `$ra` to memory, so that it may make calls of its own.
+    saved_ra: bool,
+}
+
+/// The state an executed instruction changed, restored by [`VM::step_back`].
+struct UndoEntry {
+    /// All registers before the instruction, including the program counter.
+    registers: Registers,
+    /// The previous contents of the memory the instruction wrote.
+    writes: Vec<(Address, Vec<u8>)>,
+    instructions: u64,
+    cycles: u64,
+    exit_code: Option<i32>,
+    call_stack: Vec<Call>,
+}
+
 pub struct VM {
     registers: Registers,
     memory: Memory,
+    input: Input,
+    output: Output,
+    /// Number of instructions executed so far.
+    instructions: u64,
+    /// Exit code set by the exit syscalls.
+    exit_code: Option<i32>,
+    /// Number of times each instruction address was executed.
+    coverage: BTreeMap<Address, u64>,
+    /// Fault on misaligned word and halfword accesses.
+    strict_alignment: bool,
+    /// Return address of a [`VM::call`], stopping execution once jumped to.
+    return_sentinel: Option<Address>,
+    /// Printed output not yet written to the output sink, when buffering.
+    output_buffer: Option<Vec<u8>>,
+    /// Maximum length of a string printed by the print string syscall.
+    max_print_len: usize,
+    /// Decimal places printed by the print float and double syscalls, or `None` to print like MARS.
+    float_precision: Option<usize>,
+    /// Copy of the memory as loaded, restored by [`VM::reset`].
+    loaded: Memory,
+    /// Called after every executed instruction.
+    trace_hook: Option<TraceHook>,
+    /// Custom syscalls, tried before the built-in syscalls.
+    syscall_handler: Option<Box<dyn SyscallHandler>>,
+    /// Estimated number of cycles executed so far.
+    cycles: u64,
+    cost_model: CostModel,
+    /// Addresses [`VM::resume`] stops at.
+    breakpoints: BTreeSet<Address>,
+    /// The `#assert` comments of the program.
+    assertions: Vec<Assertion>,
+    /// Maximum number of executed instructions recorded for [`VM::step_back`], 0 when not recording.
+    rewind_depth: usize,
+    /// The most recently executed instructions, last at the back.
+    undo: VecDeque<UndoEntry>,
+    /// Addresses of the calls (`jal`, `jalr` and `bal`) not yet returned from, innermost last.
+    call_stack: Vec<Call>,
+    /// Warn about calls overwriting a return address that was not saved.
+    ra_check: bool,
+    /// Addresses of the calls that overwrote a return address that was not saved.
+    ra_warnings: Vec<Address>,
+    /// Address jumped to when an exception is raised, instead of failing with an error.
+    exception_handler: Option<Address>,
+    /// Command-line arguments placed on the stack at the start of the program.
+    args: Vec<String>,
+    /// Environment variables looked up by the get environment variable syscall.
+    env: HashMap<String, String>,
+    /// Addresses of the values of the environment variables, placed on the stack.
+    env_values: HashMap<String, Address>,
 }
 
 impl VM {
     pub fn new(program: Program, mmio: Vec<MemorySegment>) -> Self {
+        Self::try_new(program, mmio).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Create a VM like [`VM::new`], failing instead of panicking if the program cannot be loaded,
+    /// see [`Memory::try_load`].
+    pub fn try_new(mut program: Program, mmio: Vec<MemorySegment>) -> Result<Self, VmError> {
         log::debug!(
             "{}\n{}",
             "======= LOADED PROGRAM =======".blue(),
             program.show_color()
         );
-        let registers = Registers::default();
-        let memory = Memory::load(program, mmio);
+        let assertions = std::mem::take(&mut program.assertions);
+        let size = program.text_section.instructions().len() * Instruction::size();
+        let base = program.text_section.base.unwrap_or(TEXT_START);
+        let max = (TEXT_MAX - base) as usize;
+        let memory = Memory::try_load(program, mmio).map_err(|error| match error {
+            MemoryError::OutOfMemory if size > max => VmError::ProgramTooLarge { size, max },
+            error => VmError::LoadFailed(error),
+        })?;
+        Ok(Self {
+            assertions,
+            ..Self::with_memory(memory)
+        })
+    }
+
+    /// Create a VM reading `input` instead of stdin, e.g. to test programs using the read syscalls.
+    pub fn with_input(program: Program, mmio: Vec<MemorySegment>, input: &str) -> Self {
+        let mut vm = Self::new(program, mmio);
+        vm.set_input(std::io::Cursor::new(input.as_bytes().to_vec()));
+        vm
+    }
+
+    /// Create a VM running a pre-assembled image, e.g. read by [`crate::program::from_elf`].
+    pub fn from_image(image: LoadedImage, mmio: Vec<MemorySegment>) -> Self {
+        Self::with_memory(Memory::load_image(image, mmio))
+    }
+
+    fn with_memory(memory: Memory) -> Self {
         log::trace!("Memory: {:#?}", memory);
-        Self { registers, memory }
+        Self {
+            registers: Self::initial_registers(&memory),
+            loaded: memory.clone(),
+            memory,
+            trace_hook: None,
+            syscall_handler: None,
+            cycles: 0,
+            cost_model: Box::new(cycle_cost),
+            breakpoints: BTreeSet::new(),
+            assertions: Vec::new(),
+            rewind_depth: 0,
+            undo: VecDeque::new(),
+            call_stack: Vec::new(),
+            ra_check: false,
+            ra_warnings: Vec::new(),
+            exception_handler: None,
+            args: Vec::new(),
+            env: HashMap::new(),
+            env_values: HashMap::new(),
+            input: default_input(),
+            output: default_output(),
+            instructions: 0,
+            exit_code: None,
+            coverage: BTreeMap::new(),
+            strict_alignment: true,
+            return_sentinel: None,
+            output_buffer: None,
+            max_print_len: 1 << 20,
+            float_precision: None,
+        }
+    }
+
+    /// Registers as the program starts: all zero except for
+    /// `$sp` at the top of the stack and `$gp` in the middle of the first 64 KiB of `.data`.
+    fn initial_registers(memory: &Memory) -> Registers {
+        let mut registers = Registers::default();
+        let stack = memory.stack().start_address.align_down(4);
+        registers.set(&Register::Sp, stack.unwrap());
+        registers.set(&Register::Gp, 0x1001_8000);
+        registers
+    }
+
+    /// Reset the VM to run the loaded program again from the start, without parsing it anew.
+    /// Registers are reinitialized and memory is restored to its loaded state,
+    /// while the input and output sinks and settings are kept.
+    pub fn reset(&mut self) {
+        self.registers = Self::initial_registers(&self.loaded);
+        self.memory.restore(&self.loaded);
+        self.instructions = 0;
+        self.cycles = 0;
+        self.exit_code = None;
+        self.coverage.clear();
+        self.undo.clear();
+        self.call_stack.clear();
+        self.ra_warnings.clear();
+        self.return_sentinel = None;
+        if let Some(buffer) = &mut self.output_buffer {
+            buffer.clear();
+        }
+        self.place_env();
+        self.place_args();
+    }
+
+    /// Pass command-line arguments to the program, as when starting a process.
+    /// The null-terminated strings are placed at the top of the stack, followed below by
+    /// the `argv` array of pointers to them ending with a null pointer.
+    /// At the start of the program `$a0` holds `argc`, and `$a1` and `$sp` point to `argv`.
+    ///
+    /// Resets the VM like [`VM::reset`], so set the arguments before running the program.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+        self.reset();
+    }
+
+    /// Set the environment variables the program looks up by name with the get environment variable syscall.
+    /// The null-terminated values are placed at the top of the stack, above the command-line arguments.
+    ///
+    /// Resets the VM like [`VM::reset`], so set the variables before running the program.
+    pub fn set_env(&mut self, env: HashMap<String, String>) {
+        self.env = env;
+        self.reset();
+    }
+
+    fn place_env(&mut self) {
+        self.env_values.clear();
+        if self.env.is_empty() {
+            return;
+        }
+        let mut names = self.env.keys().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            let mut bytes = self.env[name].as_bytes().to_vec();
+            bytes.push(0);
+            self.memory
+                .stack_push(&bytes)
+                .expect("Environment variables do not fit on the stack");
+            self.env_values
+                .insert(name.clone(), self.memory.stack().start_address);
+        }
+        let sp = self.memory.stack().start_address.align_down(4);
+        self.registers.set(&Register::Sp, sp.unwrap());
+    }
+
+    fn place_args(&mut self) {
+        if self.args.is_empty() {
+            return;
+        }
+        let overflow = "Command-line arguments do not fit on the stack";
+        let mut pointers = Vec::new();
+        for arg in self.args.iter().rev() {
+            let mut bytes = arg.as_bytes().to_vec();
+            bytes.push(0);
+            self.memory.stack_push(&bytes).expect(overflow);
+            pointers.push(self.memory.stack().start_address);
+        }
+        let padding = self.memory.stack().start_address.unwrap() % 4;
+        self.memory
+            .stack_push(&vec![0; padding as usize])
+            .expect(overflow);
+        self.memory.stack_push_word(0).expect(overflow);
+        // The pointers were collected from the last argument, so argv[0] is pushed last
+        for pointer in pointers {
+            self.memory.stack_push_address(pointer).expect(overflow);
+        }
+        let argv = self.memory.stack().start_address.unwrap();
+        self.registers.set(&Register::A0, self.args.len() as Word);
+        self.registers.set(&Register::A1, argv);
+        self.registers.set(&Register::Sp, argv);
+    }
+
+    /// Replace the input sink read by the read syscalls
+    /// (stdin by default, or no input without the `std_io` feature).
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.input = Box::new(input);
+    }
+
+    /// Replace the output sink written by the print syscalls
+    /// (stdout by default, or discarded without the `std_io` feature).
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    /// Buffer printed output, writing and flushing it to the output sink only on newlines
+    /// and when execution ends, instead of after every print syscall (disabled by default).
+    pub fn set_output_buffering(&mut self, buffering: bool) {
+        if self.output_buffer.is_some() {
+            self.flush_output();
+        }
+        self.output_buffer = buffering.then(Vec::new);
+    }
+
+    /// Fail with [`VmError::OutputLimitExceeded`] when printing a string longer than `max_len` bytes
+    /// (1 MiB by default), instead of printing memory until a null byte is found.
+    pub fn set_max_print_len(&mut self, max_len: usize) {
+        self.max_print_len = max_len;
+    }
+
+    /// Jump to `handler` when an exception is raised instead of failing with an error,
+    /// like an operating system kernel handling the exceptions of a program.
+    ///
+    /// Arithmetic overflows, address errors, unknown syscalls and `break` instructions raise an exception,
+    /// setting `EPC` of [`Registers::cp0`] to the address of the instruction raising it,
+    /// the [`ExceptionCode`] in `Cause` and the exception level bit in `Status`.
+    pub fn set_exception_handler(&mut self, handler: Address) {
+        self.exception_handler = Some(handler);
+    }
+
+    /// Print floats and doubles with `precision` decimal places,
+    /// or `None` to print them like MARS (the default), e.g. `0.33333334` for `1.0 / 3.0`.
+    pub fn set_float_precision(&mut self, precision: Option<usize>) {
+        self.float_precision = precision;
+    }
+
+    /// Call `hook` after every executed instruction with the registers it changed.
+    /// Registers are only compared while a hook is set.
+    pub fn set_trace_hook(&mut self, hook: impl FnMut(&TraceStep) + 'static) {
+        self.trace_hook = Some(Box::new(hook));
+    }
+
+    /// Handle syscalls with `handler` before the built-in syscalls,
+    /// which handle every number the handler returns [`SyscallResult::Unhandled`] for.
+    /// Built-in syscalls can be overridden by handling their numbers.
+    pub fn set_syscall_handler(&mut self, handler: impl SyscallHandler + 'static) {
+        self.syscall_handler = Some(Box::new(handler));
+    }
+
+    /// Fault with [`VmError::AddressError`] on `lw`/`sw` to addresses not divisible by 4,
+    /// and `lh`/`lhu`/`sh` to addresses not divisible by 2 (enabled by default).
+    /// When disabled, misaligned accesses read and write across the word boundary.
+    pub fn set_strict_alignment(&mut self, strict: bool) {
+        self.strict_alignment = strict;
+    }
+
+    /// Warn about a `jal`, `jalr` or `bal` inside a function that has not stored `$ra` to memory,
+    /// overwriting the return address it needs (disabled by default).
+    /// The call sites are collected in [`VM::ra_warnings`].
+    pub fn set_ra_check(&mut self, check: bool) {
+        self.ra_check = check;
+    }
+
+    /// Addresses of the calls that overwrote a return address that was not saved,
+    /// when enabled with [`VM::set_ra_check`].
+    pub fn ra_warnings(&self) -> &[Address] {
+        &self.ra_warnings
+    }
+
+    /// Fill the uninitialized memory with `pattern` like [`Memory::set_poison`],
+    /// also when restored by [`VM::reset`], and record the reads of it
+    /// in [`Memory::uninitialized_reads`] (disabled with `None`).
+    pub fn set_poison(&mut self, pattern: Option<u8>) {
+        self.memory.set_poison(pattern);
+        self.memory.set_uninitialized_check(pattern.is_some());
+        self.loaded.set_poison(pattern);
+    }
+
+    /// Number of instructions executed so far.
+    pub fn instruction_count(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Estimated number of cycles executed so far, according to the cost model.
+    pub fn estimated_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Estimate the cycles of each executed instruction with `model` instead of [`cycle_cost`].
+    pub fn set_cost_model(&mut self, model: impl Fn(&InstructionKind) -> u32 + 'static) {
+        self.cost_model = Box::new(model);
+    }
+
+    /// Record the register and memory changes of the last `depth` executed instructions,
+    /// so that they can be undone with [`VM::step_back`] (disabled by default, as recording slows execution).
+    /// A `depth` of 0 stops recording.
+    pub fn enable_rewind(&mut self, depth: usize) {
+        self.rewind_depth = depth;
+        while self.undo.len() > depth {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Undo the last recorded instruction, restoring the registers (including the program counter)
+    /// and memory it changed. Printed output, consumed input and heap growth are not undone.
+    ///
+    /// Returns whether an instruction was undone, false if none are recorded.
+    pub fn step_back(&mut self) -> Result<bool, VmError> {
+        let Some(entry) = self.undo.pop_back() else {
+            return Ok(false);
+        };
+        self.memory
+            .revert(entry.writes)
+            .map_err(|error| VmError::MemoryFault {
+                address: Address::new(entry.registers.pc),
+                error,
+            })?;
+        self.registers = entry.registers;
+        self.instructions = entry.instructions;
+        self.cycles = entry.cycles;
+        self.exit_code = entry.exit_code;
+        self.call_stack = entry.call_stack;
+        Ok(true)
+    }
+
+    /// Stop [`VM::resume`] before executing the instruction at `address`.
+    pub fn add_breakpoint(&mut self, address: Address) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Remove a breakpoint, returning whether it was set.
+    pub fn remove_breakpoint(&mut self, address: Address) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<Address> {
+        &self.breakpoints
+    }
+
+    /// The exit code, if the program exited through a syscall.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Check every `#assert` comment of the program against the current registers,
+    /// e.g. once the program has exited.
+    pub fn check_assertions(&self) -> Vec<AssertionResult> {
+        self.assertions
+            .iter()
+            .map(|assertion| AssertionResult {
+                assertion: assertion.clone(),
+                actual: self.registers.get(&assertion.register),
+            })
+            .collect()
+    }
+
+    /// Number of times each instruction address was executed.
+    /// Instructions that never executed have no entry.
+    pub fn coverage(&self) -> &BTreeMap<Address, u64> {
+        &self.coverage
+    }
+
+    /// Show the execution count of every instruction in the `.text` section, in address order.
+    pub fn show_coverage(&self) -> String {
+        let text = self.memory.text();
+        let mut output = String::new();
+        for address in Address::step_by_words(text.start_address, text.end_address) {
+            if let Ok(label) = self.memory.label_at_address(address) {
+                output.push_str(&format!("{}:\n", label));
+            }
+            if let Ok(instruction) = self.memory.execute(address) {
+                let hits = self.coverage.get(&address).copied().unwrap_or(0);
+                output.push_str(&format!(
+                    "{:>8}  {}  {}\n",
+                    hits,
+                    address.show(),
+                    instruction.show()
+                ));
+            }
+        }
+        output
+    }
+
+    /// The address of the current instruction followed by the addresses of the calls leading to it,
+    /// innermost first, e.g. to locate a runtime error.
+    pub fn backtrace(&self) -> Vec<Address> {
+        std::iter::once(Address::new(self.registers.pc))
+            .chain(self.call_stack.iter().rev().map(|call| call.address))
+            .collect()
+    }
+
+    /// Show the [`VM::backtrace`] one frame per line,
+    /// each address resolved to the nearest preceding `.text` label, e.g. `#1 0x00400008 <main+8>`.
+    pub fn show_backtrace(&self) -> String {
+        let mut output = String::new();
+        for (index, address) in self.backtrace().into_iter().enumerate() {
+            output.push_str(&format!("#{} {}\n", index, self.show_code_address(address)));
+        }
+        output
+    }
+
+    /// Show `address` resolved to the nearest preceding `.text` label, e.g. `0x00400008 <main+8>`.
+    pub fn show_code_address(&self, address: Address) -> String {
+        let labels = self.memory.labels_in(self.memory.text());
+        match labels.iter().rev().find(|(_, start)| *start <= address) {
+            Some((label, start)) if address == *start => format!("{} <{}>", address, label),
+            Some((label, start)) => format!("{} <{}+{}>", address, label, address - *start),
+            None => address.to_string(),
+        }
+    }
+
+    /// Record a call at `address`, warning if it overwrites a return address the caller has not saved.
+    fn push_call(&mut self, address: Address, return_address: Address, links_ra: bool) {
+        if self.ra_check && links_ra && self.call_stack.last().is_some_and(|call| !call.saved_ra) {
+            log::warn!(
+                "Call at {} overwrites $ra before it was saved",
+                self.show_code_address(address)
+            );
+            self.ra_warnings.push(address);
+        }
+        if self.call_stack.len() == MAX_CALL_DEPTH {
+            self.call_stack.remove(0);
+        }
+        self.call_stack.push(Call {
+            address,
+            return_address,
+            saved_ra: false,
+        });
+    }
+
+    /// Drop the calls returned from by jumping to `target`, the innermost call returning to it and all calls it made.
+    fn pop_calls(&mut self, target: Address) {
+        if let Some(index) = self
+            .call_stack
+            .iter()
+            .rposition(|call| call.return_address == target)
+        {
+            self.call_stack.truncate(index);
+        }
+    }
+
+    /// Execute the program with the given `input` as stdin,
+    /// capturing its output, final registers, instruction count and exit code.
+    pub fn run_captured(&mut self, entrypoint: Address, input: &str) -> Result<RunResult, VmError> {
+        let stdout = SharedBuffer::new();
+        self.set_input(std::io::Cursor::new(input.as_bytes().to_vec()));
+        self.set_output(stdout.clone());
+        self.execute(entrypoint)?;
+        Ok(RunResult {
+            stdout: stdout.to_string_lossy(),
+            registers: self.registers.all(),
+            instructions: self.instructions,
+            exit: self.exit_code,
+            assertions: self.check_assertions(),
+        })
     }
 
     pub fn entrypoint(&self) -> Option<Address> {
@@ -41,457 +795,1053 @@ impl VM {
         // Or return the first address of the text section
     }
 
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    /// Call the function at `label` with up to four arguments in `$a0`-`$a3`,
+    /// run until it returns through `$ra` and return the result in `$v0`.
+    pub fn call(&mut self, label: &str, args: &[Word]) -> Result<Word, VmError> {
+        assert!(
+            args.len() <= 4,
+            "At most four arguments can be passed in registers"
+        );
+        let address = self
+            .memory
+            .address_of_label(label)
+            .unwrap_or_else(|_| panic!("Unknown function label: {}", label));
+        let registers = [Register::A0, Register::A1, Register::A2, Register::A3];
+        for (register, value) in registers.iter().zip(args) {
+            self.registers.set(register, *value);
+        }
+        // Just past the last instruction, a return address no instruction can occupy
+        let sentinel = self.memory.text().end_address;
+        self.registers.set(&Register::Ra, sentinel.unwrap());
+        self.return_sentinel = Some(sentinel);
+        let result = self.execute(address);
+        self.return_sentinel = None;
+        result?;
+        Ok(self.registers.get(&Register::V0))
+    }
+
     pub fn memory(&self) -> &Memory {
         &self.memory
     }
 
-    pub fn execute(&mut self, entrypoint: Address) {
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn execute(&mut self, entrypoint: Address) -> Result<(), VmError> {
+        let result = self.execute_loop(entrypoint);
+        self.flush_output();
+        result
+    }
+
+    fn execute_loop(&mut self, entrypoint: Address) -> Result<(), VmError> {
         log::debug!("{}", "======= EXECUTION =======".blue());
+        self.registers.pc = entrypoint.unwrap();
+        loop {
+            if self.return_sentinel == Some(Address::new(self.registers.pc)) {
+                log::debug!("Returned from call to {}", entrypoint);
+                break;
+            }
+            if self.step()? {
+                break;
+            }
+        }
+        log::debug!("{}", "====== Done ======".blue());
+        Ok(())
+    }
+
+    /// Execute the instruction at the program counter and advance it to the next instruction,
+    /// unless the program halted.
+    ///
+    /// Returns whether the program halted through an exit syscall.
+    pub fn step(&mut self) -> Result<bool, VmError> {
+        if self.rewind_depth == 0 {
+            return self.execute_step_or_trap();
+        }
+        let entry = UndoEntry {
+            registers: self.registers.clone(),
+            writes: Vec::new(),
+            instructions: self.instructions,
+            cycles: self.cycles,
+            exit_code: self.exit_code,
+            call_stack: self.call_stack.clone(),
+        };
+        self.memory.start_journal();
+        let result = self.execute_step_or_trap();
+        let writes = self.memory.take_journal();
+        if self.undo.len() == self.rewind_depth {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(UndoEntry { writes, ..entry });
+        result
+    }
+
+    /// Execute a step, jumping to the exception handler if the instruction raised an exception.
+    fn execute_step_or_trap(&mut self) -> Result<bool, VmError> {
+        let pc = Address::new(self.registers.pc);
+        let error = match self.execute_step() {
+            Err(error) => error,
+            result => return result,
+        };
+        let Some(handler) = self.exception_handler else {
+            return Err(error);
+        };
+        let is_store = self.memory.fetch(pc).is_ok_and(|instruction| {
+            matches!(
+                instruction.kind,
+                InstructionKind::Sb
+                    | InstructionKind::Sh
+                    | InstructionKind::Sw
+                    | InstructionKind::Swl
+                    | InstructionKind::Swr
+            )
+        });
+        let code = match &error {
+            VmError::ArithmeticOverflow => ExceptionCode::Overflow,
+            VmError::AddressError { address, .. } => {
+                self.registers.cp0.bad_vaddr = address.unwrap();
+                match is_store {
+                    true => ExceptionCode::AddressErrorStore,
+                    false => ExceptionCode::AddressErrorLoad,
+                }
+            }
+            VmError::UnknownSyscall(_) => ExceptionCode::Syscall,
+            VmError::Break => ExceptionCode::Breakpoint,
+            _ => return Err(error),
+        };
+        log::debug!("Exception {:?} at {}, jumping to {}", code, pc, handler);
+        let cp0 = &mut self.registers.cp0;
+        cp0.epc = pc.unwrap();
+        cp0.cause = (code as Word) << 2;
+        cp0.status |= Cp0::EXL;
+        self.registers.pc = handler.unwrap();
+        Ok(false)
+    }
 
-        // Program counter (instruction pointer): address of the next instruction to execute
-        let mut pc = entrypoint;
-
-        'execution: loop {
-            if let Ok(new_block) = self.memory.label_at_address(pc) {
-                log::debug!(
-                    "Executing block at {} {}...",
-                    pc,
-                    new_block.clone().color(LABEL_COLOR)
-                );
-            }
-            let instruction = self
-                .memory
-                .execute(pc)
-                .unwrap_or_else(|_| panic!("No instruction found at address {}", pc))
-                .clone();
-            let instruction_code = self.memory.read_word(pc).unwrap();
+    fn execute_step(&mut self) -> Result<bool, VmError> {
+        // Program counter (instruction pointer): address of the instruction to execute
+        let mut pc = Address::new(self.registers.pc);
+        if let Ok(new_block) = self.memory.label_at_address(pc) {
+            log::debug!(
+                "Executing block at {} {}...",
+                pc,
+                new_block.clone().color(LABEL_COLOR)
+            );
+        }
+        let text_end = self.memory.text().end_address;
+        let instruction = match self.memory.fetch(pc) {
+            Ok(instruction) => instruction.clone(),
+            Err(_) if pc == text_end => return Err(VmError::FellOffText(pc)),
+            Err(error) => return Err(VmError::MemoryFault { address: pc, error }),
+        };
+        if log::log_enabled!(log::Level::Debug) {
+            // Instructions are always encoded little-endian
+            let bytes = self.memory.peek(pc, Instruction::size()).unwrap();
             log::debug!(
                 "Executing instruction 0x{:08x} at {}: {}",
-                instruction_code,
+                Word::from_le_bytes(bytes.try_into().unwrap()),
                 pc,
                 instruction.show_color()
             );
+        }
 
-            // Move pointer to the next instruction in advance
-            pc += Instruction::size();
+        // Move pointer to the next instruction in advance
+        let address = pc;
+        *self.coverage.entry(pc).or_insert(0) += 1;
+        pc += Instruction::size();
+        self.instructions += 1;
+        self.cycles += (self.cost_model)(&instruction.kind) as u64;
+        let before = self.trace_hook.is_some().then(|| self.registers.all());
+        let mut halt = false;
 
-            // Process the instruction
-            match instruction.kind {
-                InstructionKind::Li => match &instruction.args[0] {
-                    InstructionArg::Register(r) => {
-                        let value = self.load_word(&instruction.args[1]);
-                        self.registers.set(r, value);
-                    }
-                    _ => panic!("Invalid argument for LI instruction"),
-                },
-                InstructionKind::La => match &instruction.args[0] {
-                    InstructionArg::Register(r) => {
-                        let addr = self.load_address(&instruction.args[1]);
-                        self.registers.set(r, addr.unwrap() as Word);
-                    }
-                    _ => panic!("Invalid argument for LA instruction"),
-                },
-                InstructionKind::Move => match &instruction.args[0] {
-                    InstructionArg::Register(r) => {
-                        let value = self.load_word(&instruction.args[1]);
-                        self.registers.set(r, value);
-                    }
-                    _ => panic!("Invalid argument for MOV instruction"),
-                },
-                InstructionKind::Add => self.arithmetic(&instruction.args, |a, b| a + b),
-                InstructionKind::Sub => self.arithmetic(&instruction.args, |a, b| a - b),
-                InstructionKind::Mult => self.arithmetic(&instruction.args, |a, b| a * b),
-                InstructionKind::Div => self.arithmetic(&instruction.args, |a, b| a / b),
-                InstructionKind::And => self.arithmetic(&instruction.args, |a, b| a & b),
-                InstructionKind::Or => self.arithmetic(&instruction.args, |a, b| a | b),
-                InstructionKind::Xor => self.arithmetic(&instruction.args, |a, b| a ^ b),
-                InstructionKind::Nor => self.arithmetic(&instruction.args, |a, b| !(a | b)),
-                InstructionKind::Slt => {
-                    self.arithmetic(&instruction.args, |a, b| if a < b { 1 } else { 0 })
-                }
-                InstructionKind::Sll => self.arithmetic(&instruction.args, |a, b| a << b),
-                InstructionKind::Srl => self.arithmetic(&instruction.args, |a, b| a >> b),
-                InstructionKind::Sra => self.arithmetic(&instruction.args, |a, b| a >> b),
-                InstructionKind::Jr => {
-                    let address = self.load_address(&instruction.args[0]);
-                    log::debug!("Jumping to address {}", address);
-                }
-                InstructionKind::Syscall => {
-                    if !self.syscall() {
-                        break 'execution;
-                    }
-                }
-                InstructionKind::Addi => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for ADDI instruction"),
-                    };
-                    let src = self.load_word(&instruction.args[1]);
-                    let imm = self.load_word(&instruction.args[2]);
-                    self.registers.set(dest, src + imm);
-                }
-                InstructionKind::Andi => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for ANDI instruction"),
-                    };
-                    let src = self.load_word(&instruction.args[1]);
-                    let imm = self.load_word(&instruction.args[2]);
-                    self.registers.set(dest, src & imm);
-                }
-                InstructionKind::Beq => {
-                    let lhs = self.load_word(&instruction.args[0]);
-                    let rhs = self.load_word(&instruction.args[1]);
-                    let offset = self.load_word(&instruction.args[2]);
-                    if lhs == rhs {
-                        pc += offset;
-                    }
+        // Process the instruction
+        match instruction.kind {
+            InstructionKind::Li => match &instruction.args[0] {
+                InstructionArg::Register(r) => {
+                    let value = self.load_word(&instruction.args[1]);
+                    self.registers.set(r, value);
                 }
-                InstructionKind::Bne => {
-                    let lhs = self.load_word(&instruction.args[0]);
-                    let rhs = self.load_word(&instruction.args[1]);
-                    let offset = self.load_word(&instruction.args[2]);
-                    if lhs != rhs {
-                        pc += offset;
-                    }
-                }
-                InstructionKind::Lw => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for LW instruction"),
-                    };
-                    let src = Address::new(self.load_word(&instruction.args[1]));
-                    let offset = self.load_word(&instruction.args[2]);
-                    let address = src + offset;
-                    let value = self.memory.read_word(address).unwrap();
-                    self.registers.set(dest, value);
-                }
-                InstructionKind::Sw => {
-                    let src = self.load_word(&instruction.args[0]);
-                    let dest = Address::new(self.load_word(&instruction.args[1]));
-                    self.memory.write(dest, &src.to_le_bytes()).unwrap();
-                }
-                InstructionKind::Lui => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for LUI instruction"),
-                    };
-                    let imm = self.load_word(&instruction.args[1]);
-                    self.registers.set(dest, imm << 16);
+                _ => panic!("Invalid argument for LI instruction"),
+            },
+            InstructionKind::La => match &instruction.args[0] {
+                InstructionArg::Register(r) => {
+                    let addr = self.load_address(&instruction.args[1]);
+                    self.registers.set(r, addr.unwrap() as Word);
                 }
-                InstructionKind::Nop => { /* Do nothing */ }
-                InstructionKind::J => {
-                    let address = self.load_address(&instruction.args[0]);
-                    pc = address;
+                _ => panic!("Invalid argument for LA instruction"),
+            },
+            InstructionKind::Move => match &instruction.args[0] {
+                InstructionArg::Register(r) => {
+                    let value = self.load_word(&instruction.args[1]);
+                    self.registers.set(r, value);
                 }
-                InstructionKind::Jal => {
-                    let address = self.load_address(&instruction.args[0]);
-                    self.registers.set(&Register::Ra, pc.unwrap() + 4);
-                    pc = address;
-                }
-                InstructionKind::Addiu => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for ADDIU instruction"),
-                    };
-                    let src = self.load_word(&instruction.args[1]);
-                    let imm = self.load_word(&instruction.args[2]);
-                    self.registers.set(dest, src.wrapping_add(imm));
+                _ => panic!("Invalid argument for MOV instruction"),
+            },
+            InstructionKind::Add => self.checked_arithmetic(&instruction.args, i32::checked_add)?,
+            InstructionKind::Sub => self.checked_arithmetic(&instruction.args, i32::checked_sub)?,
+            InstructionKind::Mult => self.multiply(&instruction.args, |a, b| {
+                (a as i32 as i64 * b as i32 as i64) as u64
+            }),
+            InstructionKind::Div => self.divide(&instruction.args, |a, b| {
+                let (a, b) = (a as i32, b as i32);
+                (a.wrapping_div(b) as Word, a.wrapping_rem(b) as Word)
+            })?,
+            InstructionKind::And => self.arithmetic(&instruction.args, |a, b| a & b),
+            InstructionKind::Or => self.arithmetic(&instruction.args, |a, b| a | b),
+            InstructionKind::Xor => self.arithmetic(&instruction.args, |a, b| a ^ b),
+            InstructionKind::Nor => self.arithmetic(&instruction.args, |a, b| !(a | b)),
+            InstructionKind::Slt => {
+                self.arithmetic(&instruction.args, |a, b| ((a as i32) < (b as i32)) as Word)
+            }
+            InstructionKind::Sll => self.shift(&instruction.args, |a, b| a << b),
+            InstructionKind::Srl => self.shift(&instruction.args, |a, b| a >> b),
+            InstructionKind::Sra => self.shift(&instruction.args, |a, b| ((a as i32) >> b) as Word),
+            InstructionKind::Jr => {
+                let address = self.jump_register(&instruction.args[0])?;
+                log::debug!("Jumping to address {}", address);
+                self.pop_calls(address);
+                pc = address;
+            }
+            InstructionKind::Syscall => halt = !self.syscall()?,
+            InstructionKind::Break => return Err(VmError::Break),
+            InstructionKind::Eret => {
+                let epc = Address::new(self.registers.cp0.epc);
+                let text = self.memory.text();
+                if epc < text.start_address || epc >= text.end_address {
+                    return Err(VmError::BadJumpTarget {
+                        register: None,
+                        value: epc.unwrap(),
+                    });
                 }
-                InstructionKind::Addu => {
-                    self.arithmetic(&instruction.args, |a, b| a.wrapping_add(b))
+                self.registers.cp0.status &= !Cp0::EXL;
+                pc = epc;
+            }
+            InstructionKind::Addi => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for ADDI instruction"),
+                };
+                let src = self.load_word(&instruction.args[1]);
+                let imm = self.load_signed_immediate(&instruction.args[2]);
+                let sum = (src as i32)
+                    .checked_add(imm as i32)
+                    .ok_or(VmError::ArithmeticOverflow)?;
+                self.registers.set(dest, sum as Word);
+            }
+            InstructionKind::Andi => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for ANDI instruction"),
+                };
+                let src = self.load_word(&instruction.args[1]);
+                let imm = self.load_word(&instruction.args[2]);
+                self.registers.set(dest, src & imm);
+            }
+            InstructionKind::Beq => {
+                let lhs = self.load_word(&instruction.args[0]);
+                let rhs = self.load_word(&instruction.args[1]);
+                if lhs == rhs {
+                    pc = self.branch_target(&instruction.args[2], pc);
                 }
-                InstructionKind::Blez => {
-                    let src = self.load_word(&instruction.args[0]);
-                    let offset = self.load_word(&instruction.args[1]);
-                    if src as i32 <= 0 {
-                        pc += offset;
-                    }
+            }
+            InstructionKind::Bne => {
+                let lhs = self.load_word(&instruction.args[0]);
+                let rhs = self.load_word(&instruction.args[1]);
+                if lhs != rhs {
+                    pc = self.branch_target(&instruction.args[2], pc);
                 }
-                InstructionKind::Bgtz => {
-                    let src = self.load_word(&instruction.args[0]);
-                    let offset = self.load_word(&instruction.args[1]);
-                    if src as i32 > 0 {
-                        pc += offset;
+            }
+            InstructionKind::Lw => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for LW instruction"),
+                };
+                let address = self.aligned(&instruction.args[1], 4)?;
+                let value = self
+                    .memory
+                    .read_word(address)
+                    .map_err(|error| VmError::MemoryFault { address, error })?;
+                self.registers.set(dest, value);
+            }
+            InstructionKind::Sw => {
+                let value = self.load_word(&instruction.args[0]);
+                let address = self.aligned(&instruction.args[1], 4)?;
+                self.memory
+                    .write_word(address, value)
+                    .map_err(|error| VmError::MemoryFault { address, error })?;
+                if instruction.args[0] == InstructionArg::Register(Register::Ra) {
+                    if let Some(call) = self.call_stack.last_mut() {
+                        call.saved_ra = true;
                     }
                 }
-                InstructionKind::Jalr => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for JALR instruction"),
-                    };
-                    let address = self.load_address(&instruction.args[1]);
-                    self.registers.set(dest, pc.unwrap());
-                    pc = address;
-                }
-                InstructionKind::Lb => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for LB instruction"),
-                    };
-                    let address = self.load_address(&instruction.args[1]);
-                    let value = self.memory.read_byte(address).unwrap() as i8 as Word;
-                    self.registers.set(dest, value);
-                }
-                InstructionKind::Lbu => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for LBU instruction"),
-                    };
-                    let address = self.load_address(&instruction.args[1]);
-                    let value = self.memory.read_byte(address).unwrap() as Word;
-                    self.registers.set(dest, value);
-                }
-                InstructionKind::Lh => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for LH instruction"),
-                    };
-                    let address = self.load_address(&instruction.args[1]);
-                    let value = self.memory.read_halfword(address).unwrap() as i16 as Word;
-                    self.registers.set(dest, value);
-                }
-                InstructionKind::Lhu => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for LHU instruction"),
-                    };
-                    let address = self.load_address(&instruction.args[1]);
-                    let value = self.memory.read_halfword(address).unwrap() as Word;
-                    self.registers.set(dest, value);
-                }
-                InstructionKind::Multu => {
-                    self.arithmetic(&instruction.args, |a, b| a.wrapping_mul(b))
-                }
-                InstructionKind::Divu => self.arithmetic(&instruction.args, |a, b| a / b),
-                InstructionKind::Ori => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for ORI instruction"),
-                    };
-                    let src = self.load_word(&instruction.args[1]);
-                    let imm = self.load_word(&instruction.args[2]);
-                    self.registers.set(dest, src | imm);
-                }
-                InstructionKind::Sltu => {
-                    self.arithmetic(&instruction.args, |a, b| if a < b { 1 } else { 0 })
-                }
-                InstructionKind::Slti => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for SLTI instruction"),
-                    };
-                    let src = self.load_word(&instruction.args[1]);
-                    let imm = self.load_word(&instruction.args[2]);
-                    self.registers
-                        .set(dest, if (src as i32) < (imm as i32) { 1 } else { 0 });
-                }
-                InstructionKind::Sltiu => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for SLTIU instruction"),
-                    };
-                    let src = self.load_word(&instruction.args[1]);
-                    let imm = self.load_word(&instruction.args[2]);
-                    self.registers.set(dest, if src < imm { 1 } else { 0 });
-                }
-                InstructionKind::Sllv => self.arithmetic(&instruction.args, |a, b| a << b),
-                InstructionKind::Srav => {
-                    self.arithmetic(&instruction.args, |a, b| (a as i32 >> b) as Word)
-                }
-                InstructionKind::Srlv => self.arithmetic(&instruction.args, |a, b| a >> b),
-                InstructionKind::Sb => {
-                    let value = self.load_word(&instruction.args[0]) as u8;
-                    let address = self.load_address(&instruction.args[1]);
-                    self.memory.write_byte(address, value).unwrap();
-                }
-                InstructionKind::Sh => {
-                    let value = self.load_word(&instruction.args[0]) as u16;
-                    let address = self.load_address(&instruction.args[1]);
-                    self.memory.write_halfword(address, value).unwrap();
+            }
+            InstructionKind::Lui => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for LUI instruction"),
+                };
+                let imm = self.load_word(&instruction.args[1]);
+                self.registers.set(dest, imm << 16);
+            }
+            InstructionKind::Nop => { /* Do nothing */ }
+            InstructionKind::J => {
+                let address = self.jump_target(&instruction.args[0])?;
+                self.pop_calls(address);
+                pc = address;
+            }
+            InstructionKind::Jal => {
+                let target = self.jump_target(&instruction.args[0])?;
+                // There are no delay slots, so return to the instruction after the jal
+                self.registers.set(&Register::Ra, pc.unwrap());
+                self.push_call(address, pc, true);
+                pc = target;
+            }
+            InstructionKind::Bal => {
+                // Link like jal, the return address is the address of the next instruction
+                self.registers.set(&Register::Ra, pc.unwrap());
+                self.push_call(address, pc, true);
+                pc = self.branch_target(&instruction.args[0], pc);
+            }
+            InstructionKind::Addiu => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for ADDIU instruction"),
+                };
+                let src = self.load_word(&instruction.args[1]);
+                let imm = self.load_signed_immediate(&instruction.args[2]);
+                self.registers.set(dest, src.wrapping_add(imm));
+            }
+            InstructionKind::Addu => self.arithmetic(&instruction.args, |a, b| a.wrapping_add(b)),
+            InstructionKind::Blez => {
+                let src = self.load_word(&instruction.args[0]);
+                if src as i32 <= 0 {
+                    pc = self.branch_target(&instruction.args[1], pc);
                 }
-                InstructionKind::Subu => {
-                    self.arithmetic(&instruction.args, |a, b| a.wrapping_sub(b))
+            }
+            InstructionKind::Bgtz => {
+                let src = self.load_word(&instruction.args[0]);
+                if src as i32 > 0 {
+                    pc = self.branch_target(&instruction.args[1], pc);
                 }
-                InstructionKind::Xori => {
-                    let dest = match &instruction.args[0] {
-                        InstructionArg::Register(r) => r,
-                        _ => panic!("Invalid argument for XORI instruction"),
-                    };
-                    let src = self.load_word(&instruction.args[1]);
-                    let imm = self.load_word(&instruction.args[2]);
-                    self.registers.set(dest, src ^ imm);
+            }
+            InstructionKind::Jalr => {
+                let (dest, target) = match instruction.args.as_slice() {
+                    [target] => (Register::Ra, target),
+                    [InstructionArg::Register(dest), target] => (*dest, target),
+                    _ => panic!("Invalid argument for JALR instruction"),
+                };
+                let target = self.jump_register(target)?;
+                self.registers.set(&dest, pc.unwrap());
+                self.push_call(address, pc, dest == Register::Ra);
+                pc = target;
+            }
+            InstructionKind::Lb => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for LB instruction"),
+                };
+                let address = self.load_address(&instruction.args[1]);
+                let value = self
+                    .memory
+                    .read_i8(address)
+                    .map_err(|error| VmError::MemoryFault { address, error })?
+                    as Word;
+                self.registers.set(dest, value);
+            }
+            InstructionKind::Lbu => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for LBU instruction"),
+                };
+                let address = self.load_address(&instruction.args[1]);
+                let value = self
+                    .memory
+                    .read_u8(address)
+                    .map_err(|error| VmError::MemoryFault { address, error })?
+                    as Word;
+                self.registers.set(dest, value);
+            }
+            InstructionKind::Lh => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for LH instruction"),
+                };
+                let address = self.aligned(&instruction.args[1], 2)?;
+                let value = self
+                    .memory
+                    .read_i16(address)
+                    .map_err(|error| VmError::MemoryFault { address, error })?
+                    as Word;
+                self.registers.set(dest, value);
+            }
+            InstructionKind::Lhu => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for LHU instruction"),
+                };
+                let address = self.aligned(&instruction.args[1], 2)?;
+                let value = self
+                    .memory
+                    .read_u16(address)
+                    .map_err(|error| VmError::MemoryFault { address, error })?
+                    as Word;
+                self.registers.set(dest, value);
+            }
+            InstructionKind::Multu => self.multiply(&instruction.args, |a, b| a as u64 * b as u64),
+            InstructionKind::Divu => self.divide(&instruction.args, |a, b| (a / b, a % b))?,
+            InstructionKind::Mfc0 | InstructionKind::Mtc0 => {
+                let (rt, rd) = match instruction.args.as_slice() {
+                    [InstructionArg::Register(rt), InstructionArg::Register(rd)] => (rt, *rd as u8),
+                    _ => panic!(
+                        "Invalid argument for {} instruction",
+                        instruction.kind.show()
+                    ),
+                };
+                match instruction.kind {
+                    InstructionKind::Mfc0 => self.registers.set(rt, self.registers.cp0.get(rd)),
+                    _ => self.registers.cp0.set(rd, self.registers.get(rt)),
                 }
             }
-        }
-        log::debug!("{}", "====== Done ======".blue());
-    }
-
-    fn load_word(&mut self, arg: &InstructionArg) -> Word {
-        match arg {
-            InstructionArg::Immediate(value) => *value as Word,
-            InstructionArg::Register(register) => self.registers.get(register),
-            InstructionArg::RegisterOffset(offset, register) => {
-                let base = Address::new(self.registers.get(register));
-                let address = base + *offset;
-                self.memory.read_word(address).unwrap_or_else(|err| {
-                    panic!("Invalid address: {}: {:?}", address, err);
-                })
+            InstructionKind::Mfhi | InstructionKind::Mflo => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!(
+                        "Invalid argument for {} instruction",
+                        instruction.kind.show()
+                    ),
+                };
+                let value = match instruction.kind {
+                    InstructionKind::Mfhi => self.registers.hi,
+                    _ => self.registers.lo,
+                };
+                self.registers.set(dest, value);
             }
-            InstructionArg::Label(label) => {
-                let address = self.memory.address_of_label(label).unwrap();
-                self.memory.read_word(address).unwrap()
+            InstructionKind::Ori => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for ORI instruction"),
+                };
+                let src = self.load_word(&instruction.args[1]);
+                let imm = self.load_word(&instruction.args[2]);
+                self.registers.set(dest, src | imm);
             }
-        }
+            InstructionKind::Sltu => {
+                self.arithmetic(&instruction.args, |a, b| if a < b { 1 } else { 0 })
+            }
+            InstructionKind::Slti => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for SLTI instruction"),
+                };
+                let src = self.load_word(&instruction.args[1]);
+                let imm = self.load_signed_immediate(&instruction.args[2]);
+                self.registers
+                    .set(dest, if (src as i32) < (imm as i32) { 1 } else { 0 });
+            }
+            InstructionKind::Sltiu => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for SLTIU instruction"),
+                };
+                let src = self.load_word(&instruction.args[1]);
+                let imm = self.load_signed_immediate(&instruction.args[2]);
+                self.registers.set(dest, if src < imm { 1 } else { 0 });
+            }
+            InstructionKind::Sllv => self.shift(&instruction.args, |a, b| a << b),
+            InstructionKind::Srav => {
+                self.shift(&instruction.args, |a, b| ((a as i32) >> b) as Word)
+            }
+            InstructionKind::Srlv => self.shift(&instruction.args, |a, b| a >> b),
+            InstructionKind::Rotr | InstructionKind::Rotrv | InstructionKind::Ror => {
+                self.shift(&instruction.args, |a, b| a.rotate_right(b))
+            }
+            InstructionKind::Rol => self.shift(&instruction.args, |a, b| a.rotate_left(b)),
+            InstructionKind::Lwl | InstructionKind::Lwr => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!(
+                        "Invalid argument for {} instruction",
+                        instruction.kind.show()
+                    ),
+                };
+                let (aligned, significance) =
+                    self.unaligned(self.load_address(&instruction.args[1]));
+                let word =
+                    self.memory
+                        .read_word(aligned)
+                        .map_err(|error| VmError::MemoryFault {
+                            address: aligned,
+                            error,
+                        })?;
+                let current = self.registers.get(dest);
+                let value = if instruction.kind == InstructionKind::Lwl {
+                    let n = (3 - significance) * 8;
+                    (word << n) | (current & ((1 << n) - 1))
+                } else {
+                    let n = significance * 8;
+                    (word >> n) | (current & !(Word::MAX >> n))
+                };
+                self.registers.set(dest, value);
+            }
+            InstructionKind::Swl | InstructionKind::Swr => {
+                let value = self.load_word(&instruction.args[0]);
+                let (aligned, significance) =
+                    self.unaligned(self.load_address(&instruction.args[1]));
+                let fault = |error| VmError::MemoryFault {
+                    address: aligned,
+                    error,
+                };
+                let word = self.memory.read_word(aligned).map_err(fault)?;
+                let word = if instruction.kind == InstructionKind::Swl {
+                    let n = (3 - significance) * 8;
+                    (value >> n) | (word & !(Word::MAX >> n))
+                } else {
+                    let n = significance * 8;
+                    (value << n) | (word & ((1 << n) - 1))
+                };
+                self.memory.write_word(aligned, word).map_err(fault)?;
+            }
+            InstructionKind::Clz => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for CLZ instruction"),
+                };
+                let value = self.load_word(&instruction.args[1]);
+                self.registers.set(dest, value.leading_zeros());
+            }
+            InstructionKind::Seb => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for SEB instruction"),
+                };
+                let value = self.load_word(&instruction.args[1]);
+                self.registers.set(dest, value as u8 as i8 as Word);
+            }
+            InstructionKind::Seh => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for SEH instruction"),
+                };
+                let value = self.load_word(&instruction.args[1]);
+                self.registers.set(dest, value as u16 as i16 as Word);
+            }
+            InstructionKind::Sb => {
+                let value = self.load_word(&instruction.args[0]) as u8;
+                let address = self.load_address(&instruction.args[1]);
+                self.memory
+                    .write_byte(address, value)
+                    .map_err(|error| VmError::MemoryFault { address, error })?;
+            }
+            InstructionKind::Sh => {
+                let value = self.load_word(&instruction.args[0]) as u16;
+                let address = self.aligned(&instruction.args[1], 2)?;
+                self.memory
+                    .write_halfword(address, value)
+                    .map_err(|error| VmError::MemoryFault { address, error })?;
+            }
+            InstructionKind::Subu => self.arithmetic(&instruction.args, |a, b| a.wrapping_sub(b)),
+            InstructionKind::Xori => {
+                let dest = match &instruction.args[0] {
+                    InstructionArg::Register(r) => r,
+                    _ => panic!("Invalid argument for XORI instruction"),
+                };
+                let src = self.load_word(&instruction.args[1]);
+                let imm = self.load_word(&instruction.args[2]);
+                self.registers.set(dest, src ^ imm);
+            }
+        }
+
+        if let (Some(hook), Some(before)) = (&mut self.trace_hook, before) {
+            let changes = Register::ALL
+                .iter()
+                .zip(before.iter().zip(self.registers.all()))
+                .filter(|(_, (old, new))| *old != new)
+                .map(|(register, (old, new))| (*register, *old, new))
+                .collect();
+            hook(&TraceStep {
+                pc: address,
+                instruction: &instruction,
+                changes,
+            });
+        }
+        if !halt {
+            self.registers.pc = pc.unwrap();
+        }
+        Ok(halt)
+    }
+
+    /// Step until the program halts or reaches a breakpoint, executing at least one instruction.
+    ///
+    /// Returns whether the program halted.
+    pub fn resume(&mut self) -> Result<bool, VmError> {
+        loop {
+            if self.step()? {
+                return Ok(true);
+            }
+            if self.breakpoints.contains(&Address::new(self.registers.pc)) {
+                return Ok(false);
+            }
+        }
+    }
+
+    fn load_word(&mut self, arg: &InstructionArg) -> Word {
+        match arg {
+            InstructionArg::Immediate(value) => *value as Word,
+            InstructionArg::Register(register) => self.registers.get(register),
+            InstructionArg::RegisterOffset(..) => {
+                let address = self.load_address(arg);
+                self.memory.read_word(address).unwrap_or_else(|err| {
+                    panic!("Invalid address: {}: {:?}", address, err);
+                })
+            }
+            InstructionArg::Label(label) => {
+                let address = self.memory.address_of_label(label).unwrap();
+                self.memory.read_word(address).unwrap()
+            }
+            InstructionArg::LabelOffset(..) => {
+                let address = self.load_address(arg);
+                self.memory.read_word(address).unwrap_or_else(|err| {
+                    panic!("Invalid address: {}: {:?}", address, err);
+                })
+            }
+        }
+    }
+
+    /// Load a 16-bit immediate sign-extended to a word, as `addi`, `addiu`, `slti` and `sltiu` do.
+    fn load_signed_immediate(&mut self, arg: &InstructionArg) -> Word {
+        match arg {
+            InstructionArg::Immediate(value) => *value as u16 as i16 as Word,
+            _ => self.load_word(arg),
+        }
     }
 
     fn load_address(&self, arg: &InstructionArg) -> Address {
         match arg {
-            InstructionArg::Immediate(value) => Address::new(*value as u32),
+            InstructionArg::Immediate(value) => Address::new(*value),
             InstructionArg::Register(register) => Address::new(self.registers.get(register)),
             InstructionArg::RegisterOffset(offset, register) => {
-                let base = Address::new(self.registers.get(register));
-                base + *offset
+                // Sign-extend the 16-bit offset
+                let offset = *offset as u16 as i16 as Word;
+                Address::new(self.registers.get(register).wrapping_add(offset))
             }
             InstructionArg::Label(label) => self.memory.address_of_label(label).unwrap(),
+            InstructionArg::LabelOffset(label, register) => {
+                let base = self.memory.address_of_label(label).unwrap();
+                Address::new(base.unwrap().wrapping_add(self.registers.get(register)))
+            }
+        }
+    }
+
+    /// The target of a branch to a label, or by a signed word offset from `next`,
+    /// the address following the branch.
+    fn branch_target(&self, arg: &InstructionArg, next: Address) -> Address {
+        match arg {
+            InstructionArg::Immediate(offset) => {
+                let offset = (*offset as i16 as i32) << 2;
+                Address::new(next.unwrap().wrapping_add(offset as u32))
+            }
+            _ => self.load_address(arg),
+        }
+    }
+
+    /// Resolve the address of a memory access of `alignment` bytes,
+    /// which must be aligned when strict alignment is enabled.
+    fn aligned(&self, arg: &InstructionArg, alignment: u32) -> Result<Address, VmError> {
+        let address = self.load_address(arg);
+        if self.strict_alignment && !address.unwrap().is_multiple_of(alignment) {
+            return Err(VmError::AddressError { address, alignment });
+        }
+        Ok(address)
+    }
+
+    /// The aligned word containing `address`, and the significance of the addressed byte
+    /// within that word (`0` is the least-significant byte) under the current endianness.
+    fn unaligned(&self, address: Address) -> (Address, u32) {
+        let byte = address.unwrap() & 3;
+        let significance = match self.memory.endianness() {
+            Endianness::Little => byte,
+            Endianness::Big => 3 - byte,
+        };
+        (address.align_down(4), significance)
+    }
+
+    /// The target of a `j`/`jal`, which must be an instruction in the `.text` section.
+    fn jump_target(&self, arg: &InstructionArg) -> Result<Address, VmError> {
+        let address = self.load_address(arg);
+        let text = self.memory.text();
+        if address < text.start_address || address >= text.end_address {
+            return Err(VmError::BadJumpTarget {
+                register: None,
+                value: address.unwrap(),
+            });
         }
+        Ok(address)
+    }
+
+    /// Resolve the target of a register jump (`jr`/`jalr`),
+    /// which must lie within the `.text` section.
+    fn jump_register(&self, arg: &InstructionArg) -> Result<Address, VmError> {
+        let register = arg
+            .clone()
+            .as_register()
+            .expect("Expected register argument");
+        let value = self.registers.get(&register);
+        let address = Address::new(value);
+        let text = self.memory.text();
+        if self.return_sentinel == Some(address) {
+            return Ok(address);
+        }
+        if address < text.start_address || address >= text.end_address {
+            return Err(VmError::BadJumpTarget {
+                register: Some(register),
+                value,
+            });
+        }
+        Ok(address)
     }
 
     fn arithmetic<F>(&mut self, args: &[InstructionArg], operation: F)
     where
-        F: Fn(Word, Word) -> Word,
+        F: FnOnce(Word, Word) -> Word,
+    {
+        match &args[0] {
+            InstructionArg::Register(r) => {
+                // `op rd, rs, rt` computes `rs op rt`, the two operand form `op rd, rs` computes `rd op rs`
+                let (lhs, rhs) = match args {
+                    [_, lhs, rhs] => (self.load_word(lhs), self.load_word(rhs)),
+                    [dest, src] => (self.load_word(dest), self.load_word(src)),
+                    _ => panic!("Invalid number of arguments for instruction"),
+                };
+                self.registers.set(r, operation(lhs, rhs));
+            }
+            _ => panic!("Invalid argument for instruction"),
+        }
+    }
+
+    /// Compute a signed operation like [`VM::arithmetic`],
+    /// failing with [`VmError::ArithmeticOverflow`] if it overflows.
+    fn checked_arithmetic<F>(
+        &mut self,
+        args: &[InstructionArg],
+        operation: F,
+    ) -> Result<(), VmError>
+    where
+        F: FnOnce(i32, i32) -> Option<i32>,
+    {
+        let (dest, lhs, rhs) = match args {
+            [InstructionArg::Register(dest), lhs, rhs] => {
+                (dest, self.load_word(lhs), self.load_word(rhs))
+            }
+            [InstructionArg::Register(dest), src] => {
+                (dest, self.registers.get(dest), self.load_word(src))
+            }
+            _ => panic!("Invalid argument for instruction"),
+        };
+        // The destination is left unchanged on overflow
+        let result = operation(lhs as i32, rhs as i32).ok_or(VmError::ArithmeticOverflow)?;
+        self.registers.set(dest, result as Word);
+        Ok(())
+    }
+
+    /// Multiply `$s` by `$t`, keeping the full 64-bit product in HI:LO.
+    /// The three operand form `mult $d, $s, $t` also stores its low word in `$d`.
+    fn multiply<F>(&mut self, args: &[InstructionArg], operation: F)
+    where
+        F: FnOnce(Word, Word) -> u64,
+    {
+        let (dest, lhs, rhs) = match args {
+            [InstructionArg::Register(dest), lhs, rhs] => (Some(dest), lhs, rhs),
+            [lhs, rhs] => (None, lhs, rhs),
+            _ => panic!("Invalid arguments for multiplication"),
+        };
+        let product = operation(self.load_word(lhs), self.load_word(rhs));
+        self.registers.hi = (product >> 32) as Word;
+        self.registers.lo = product as Word;
+        if let Some(dest) = dest {
+            self.registers.set(dest, product as Word);
+        }
+    }
+
+    /// Divide `$s` by `$t` into the quotient and remainder, kept in LO and HI.
+    /// The three operand form `div $d, $s, $t` also stores the quotient in `$d`.
+    fn divide<F>(&mut self, args: &[InstructionArg], operation: F) -> Result<(), VmError>
+    where
+        F: FnOnce(Word, Word) -> (Word, Word),
+    {
+        let (dest, lhs, rhs) = match args {
+            [InstructionArg::Register(dest), lhs, rhs] => (Some(dest), lhs, rhs),
+            [lhs, rhs] => (None, lhs, rhs),
+            _ => panic!("Invalid arguments for division"),
+        };
+        let (lhs, rhs) = (self.load_word(lhs), self.load_word(rhs));
+        if rhs == 0 {
+            return Err(VmError::DivisionByZero);
+        }
+        let (quotient, remainder) = operation(lhs, rhs);
+        self.registers.lo = quotient;
+        self.registers.hi = remainder;
+        if let Some(dest) = dest {
+            self.registers.set(dest, quotient);
+        }
+        Ok(())
+    }
+
+    /// Shift or rotate `args[1]` by the low 5 bits of `args[2]` into `args[0]`.
+    fn shift<F>(&mut self, args: &[InstructionArg], operation: F)
+    where
+        F: Fn(Word, u32) -> Word,
     {
         match &args[0] {
             InstructionArg::Register(r) => {
-                let dest = self.load_word(&args[0]);
-                let src = self.load_word(&args[1]);
-                self.registers.set(r, operation(dest, src));
+                let value = self.load_word(&args[1]);
+                let amount = self.load_word(&args[2]) & 0x1F;
+                self.registers.set(r, operation(value, amount));
             }
             _ => panic!("Invalid argument for instruction"),
         }
     }
 
-    fn syscall(&mut self) -> bool {
-        let v0: Syscall = self.registers.get(&Register::V0).into();
+    /// Print `text` to the output sink, or to the output buffer when buffering.
+    fn print(&mut self, text: &str) {
+        self.print_bytes(text.as_bytes());
+    }
+
+    /// Write raw `bytes` to the output sink, or the output buffer when buffering.
+    fn print_bytes(&mut self, bytes: &[u8]) {
+        match &mut self.output_buffer {
+            Some(buffer) => {
+                buffer.extend_from_slice(bytes);
+                if bytes.contains(&b'\n') {
+                    self.flush_output();
+                }
+            }
+            None => {
+                self.output.write_all(bytes).unwrap();
+                self.output.flush().unwrap();
+            }
+        }
+    }
+
+    /// Write any buffered output to the output sink and flush it.
+    pub(crate) fn flush_output(&mut self) {
+        if let Some(buffer) = &mut self.output_buffer {
+            if buffer.is_empty() {
+                return;
+            }
+            self.output.write_all(buffer).unwrap();
+            buffer.clear();
+        }
+        self.output.flush().unwrap();
+    }
+
+    fn syscall(&mut self) -> Result<bool, VmError> {
+        let number = self.registers.get(&Register::V0);
+        if let Some(handler) = &mut self.syscall_handler {
+            let mut context = VmContext::new(&mut self.registers, &mut self.memory);
+            match handler.handle(number, &mut context) {
+                SyscallResult::Continue => return Ok(true),
+                SyscallResult::Exit(code) => {
+                    log::debug!("Exiting program with code {}...", code);
+                    self.flush_output();
+                    self.exit_code = Some(code);
+                    return Ok(false);
+                }
+                SyscallResult::Unhandled => {}
+            }
+        }
+        let v0 = Syscall::try_from(number)?;
         match v0 {
             Syscall::PrintInt => {
                 let a0 = self.load_word(&InstructionArg::Register(Register::A0));
-                print!("{}", a0);
-                std::io::Write::flush(&mut std::io::stdout()).unwrap();
+                self.print(&(a0 as i32).to_string());
+            }
+            Syscall::PrintUnsignedInt => {
+                let a0 = self.load_word(&InstructionArg::Register(Register::A0));
+                self.print(&a0.to_string());
+            }
+            Syscall::PrintIntPadded => {
+                let a0 = self.load_word(&InstructionArg::Register(Register::A0)) as i32;
+                let width = (self.load_word(&InstructionArg::Register(Register::A1)) as usize)
+                    .min(MAX_PRINT_WIDTH);
+                let zero = self.load_word(&InstructionArg::Register(Register::A2)) != 0;
+                if zero {
+                    self.print(&format!("{:0width$}", a0));
+                } else {
+                    self.print(&format!("{:width$}", a0));
+                }
             }
             Syscall::PrintFloat => {
                 let a0 = self.load_word(&InstructionArg::Register(Register::A0));
-                print!("{}", f32::from_bits(a0));
-                std::io::Write::flush(&mut std::io::stdout()).unwrap();
+                self.print(&format_float(f32::from_bits(a0), self.float_precision));
             }
             Syscall::PrintDouble => {
-                let a0 = self.load_word(&InstructionArg::Register(Register::A0));
-                print!("{}", f64::from_bits(a0 as u64));
-                std::io::Write::flush(&mut std::io::stdout()).unwrap();
+                let f12 = self.registers.get_double(12);
+                self.print(&format_float(f12, self.float_precision));
             }
             Syscall::PrintChar => {
                 let a0 = self.load_word(&InstructionArg::Register(Register::A0));
-                print!("{}", a0 as u8 as char);
-                std::io::Write::flush(&mut std::io::stdout()).unwrap();
+                self.print(&(a0 as u8 as char).to_string());
             }
             Syscall::PrintString => {
-                const FLUSH_THRESHOLD: usize = 64;
-                const BUFFER_SIZE: usize = 128;
                 let a0 = Address::new(self.load_word(&InstructionArg::Register(Register::A0)));
-                let mut addr = a0;
-                let mut buffer = [0u8; BUFFER_SIZE];
-                let mut i = 0;
-                'print: loop {
-                    match self.memory.read_buf_max(addr, &mut buffer) {
-                        Ok(n) => {
-                            for &byte in &buffer[..n] {
-                                if byte == 0 {
-                                    break 'print;
-                                }
-                                print!("{}", byte as char);
-                                i += 1;
-                                if i % FLUSH_THRESHOLD == 0 {
-                                    // Flush every 64 characters
-                                    std::io::Write::flush(&mut std::io::stdout()).unwrap();
-                                }
-                            }
-                            if n < BUFFER_SIZE {
-                                // If we read less than the buffer size, we reached the end of the memory section
-                                break 'print;
-                            }
-                            addr += buffer.len();
-                        }
-                        Err(err) => {
-                            panic!(
-                                "Invalid reading {} bytes at address {}: {:?}",
-                                BUFFER_SIZE, addr, err
-                            );
-                        }
-                    }
+                let limit = self.max_print_len;
+                let bytes = self
+                    .memory
+                    .read_cstr_max(a0, limit.saturating_add(1))
+                    .map_err(|error| VmError::MemoryFault { address: a0, error })?;
+                if bytes.len() > limit {
+                    return Err(VmError::OutputLimitExceeded { address: a0, limit });
+                }
+                let text = bytes.iter().map(|&byte| byte as char).collect::<String>();
+                self.print(&text);
+            }
+            Syscall::PrintStringN => {
+                let a0 = Address::new(self.load_word(&InstructionArg::Register(Register::A0)));
+                let a1 = self.load_word(&InstructionArg::Register(Register::A1)) as usize;
+                let limit = self.max_print_len;
+                if a1 > limit {
+                    return Err(VmError::OutputLimitExceeded { address: a0, limit });
                 }
-                // Flush the remaining characters
-                std::io::Write::flush(&mut std::io::stdout()).unwrap();
+                let bytes = self
+                    .memory
+                    .read(a0, a1)
+                    .map_err(|error| VmError::MemoryFault { address: a0, error })?;
+                self.print_bytes(&bytes);
             }
             Syscall::ReadInt => {
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input).unwrap();
-                let value = input.trim().parse::<Word>().unwrap();
-                self.registers.set(&Register::V0, value);
+                let value = self.read_input::<i32>("an integer")?;
+                self.registers.set(&Register::V0, value as Word);
             }
             Syscall::ReadFloat => {
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input).unwrap();
-                let value = input.trim().parse::<f32>().unwrap();
+                let value = self.read_input::<f32>("a float")?;
                 self.registers.set(&Register::V0, value.to_bits() as Word);
             }
             Syscall::ReadDouble => {
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input).unwrap();
-                let value = input.trim().parse::<f64>().unwrap();
-                self.registers.set(&Register::V0, value.to_bits() as Word);
+                let value = self.read_input::<f64>("a double")?;
+                self.registers.set_double(0, value);
             }
             Syscall::ReadChar => {
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input).unwrap();
-                let value = input.trim().chars().next().unwrap() as Word;
-                self.registers.set(&Register::V0, value);
+                let input = self.read_input_line()?;
+                let value = input.chars().next().ok_or(VmError::InputParseError {
+                    expected: "a character",
+                    input,
+                })?;
+                self.registers.set(&Register::V0, value as Word);
             }
             Syscall::ReadString => {
                 let a0 = Address::new(self.load_word(&InstructionArg::Register(Register::A0))); // address of the buffer
-                let a1 = self.load_word(&InstructionArg::Register(Register::A1)); // maximum number of characters to read
-                                                                                  // TODO: Read at most `a1` characters from stdin
-                let mut input = String::with_capacity(a1 as usize);
-                std::io::stdin().read_line(&mut input).unwrap();
-                self.memory
-                    .write(a0, &input.as_bytes()[..a1 as usize])
-                    .unwrap();
+                let a1 = self.load_word(&InstructionArg::Register(Register::A1)); // size of the buffer
+                let input = self.read_raw_line()?;
+                // Leave room for the null terminator
+                let length = input.len().min((a1 as usize).saturating_sub(1));
+                if a1 > 0 {
+                    let mut bytes = input.as_bytes()[..length].to_vec();
+                    bytes.push(0);
+                    self.memory
+                        .write(a0, &bytes)
+                        .map_err(|error| VmError::MemoryFault { address: a0, error })?;
+                }
+                self.registers.set(&Register::V0, length as Word);
             }
             Syscall::Sbrk => {
                 let a0 = self.load_word(&InstructionArg::Register(Register::A0));
-                let address = self.memory.heap_allocate(a0 as usize).unwrap();
-                self.registers.set(&Register::V0, address.unwrap());
+                // A failed allocation returns -1, like `sbrk` in C
+                let address = self.memory.heap_allocate(a0 as usize);
+                let v0 = address.map_or(Word::MAX, |address| address.unwrap());
+                self.registers.set(&Register::V0, v0);
             }
-            Syscall::Exit | Syscall::Exit2 => {
+            Syscall::GetEnv => {
+                let a0 = Address::new(self.load_word(&InstructionArg::Register(Register::A0)));
+                let name = self
+                    .memory
+                    .read_cstr(a0)
+                    .map_err(|error| VmError::MemoryFault { address: a0, error })?;
+                let value = self.env_values.get(&*String::from_utf8_lossy(&name));
+                self.registers
+                    .set(&Register::V0, value.map_or(0, |address| address.unwrap()));
+            }
+            Syscall::Exit => {
                 log::debug!("Exiting program...");
-                // std::process::exit(0);
-                return false;
+                self.flush_output();
+                self.exit_code = Some(0);
+                return Ok(false);
+            }
+            Syscall::Exit2 => {
+                let a0 = self.load_word(&InstructionArg::Register(Register::A0));
+                log::debug!("Exiting program with code {}...", a0 as i32);
+                self.flush_output();
+                self.exit_code = Some(a0 as i32);
+                return Ok(false);
             }
         };
-        true
+        Ok(true)
+    }
+
+    /// Read a line of input, including its line break.
+    /// Buffered output is flushed first, so that a prompt is shown before waiting for input.
+    fn read_raw_line(&mut self) -> Result<String, VmError> {
+        self.flush_output();
+        let mut input = String::new();
+        self.input
+            .read_line(&mut input)
+            .map_err(|error| VmError::InputError(error.to_string()))?;
+        Ok(input)
+    }
+
+    /// Read a line of input without surrounding whitespace.
+    fn read_input_line(&mut self) -> Result<String, VmError> {
+        Ok(self.read_raw_line()?.trim().to_string())
+    }
+
+    /// Read a line of input and parse it as the `expected` value.
+    fn read_input<T: FromStr>(&mut self, expected: &'static str) -> Result<T, VmError> {
+        let input = self.read_input_line()?;
+        input
+            .parse()
+            .map_err(|_| VmError::InputParseError { expected, input })
     }
 }
 
 #[derive(Debug, PartialEq)]
 enum Syscall {
+    /// PrintInt - Print `a0` as a signed integer
     PrintInt = 1,
+    /// PrintUnsignedInt - Print `a0` as an unsigned integer
+    PrintUnsignedInt = 36,
+    /// PrintIntPadded - Print `a0` as a signed integer, right-justified
+    /// - `a1`: Minimum width of the printed integer, at most 255
+    /// - `a2`: Pad with zeros if non-zero, otherwise with spaces
+    PrintIntPadded = 37,
     PrintFloat = 2,
+    /// PrintDouble - Print the double in `$f12`/`$f13`
     PrintDouble = 3,
     PrintChar = 11,
     PrintString = 4,
+    /// PrintStringN - Print a buffer of an explicit length, including any null bytes
+    /// - `a0`: Address of the buffer
+    /// - `a1`: Number of bytes to print
+    PrintStringN = 18,
     ReadInt = 5,
     ReadFloat = 6,
+    /// ReadDouble - Read a double into `$f0`/`$f1`
     ReadDouble = 7,
     ReadChar = 12,
+    /// ReadString - Read a line of input into a buffer
+    /// - `a0`: Address of the buffer
+    /// - `a1`: Size of the buffer, including the null terminator
+    /// - `v0`: Number of bytes read
+    ///
+    /// At most `a1 - 1` bytes of the line are read, including the newline,
+    /// and the buffer is always null-terminated. At the end of input, the buffer
+    /// is left empty and `v0` is 0.
     ReadString = 8,
     /// Sbrk - Increment the program break (brk) pointer
     /// - `a0`: Number of bytes to increment the program break pointer
@@ -499,35 +1849,57 @@ enum Syscall {
     ///
     /// On success, the syscall returns the address of the new program break pointer,
     /// which is the address of the first newly allocated byte.
+    /// If the heap would run into the stack, it returns -1 and allocates nothing.
     Sbrk = 9,
+    /// GetEnv - Look up an environment variable set by [`VM::set_env`]
+    /// - `a0`: Address of the null-terminated name
+    /// - `v0`: Address of the null-terminated value, or 0 if the variable is not set
+    GetEnv = 60,
     Exit = 10,
+    /// Exit2 - Terminate the program with an exit code
+    /// - `a0`: The exit code
     Exit2 = 17,
 }
 
-impl From<Word> for Syscall {
-    fn from(value: Word) -> Self {
-        match value {
+impl TryFrom<Word> for Syscall {
+    type Error = VmError;
+
+    fn try_from(value: Word) -> Result<Self, VmError> {
+        Ok(match value {
             _ if value == Syscall::PrintInt as Word => Syscall::PrintInt,
+            _ if value == Syscall::PrintUnsignedInt as Word => Syscall::PrintUnsignedInt,
+            _ if value == Syscall::PrintIntPadded as Word => Syscall::PrintIntPadded,
             _ if value == Syscall::PrintFloat as Word => Syscall::PrintFloat,
             _ if value == Syscall::PrintDouble as Word => Syscall::PrintDouble,
             _ if value == Syscall::PrintChar as Word => Syscall::PrintChar,
             _ if value == Syscall::PrintString as Word => Syscall::PrintString,
+            _ if value == Syscall::PrintStringN as Word => Syscall::PrintStringN,
             _ if value == Syscall::ReadInt as Word => Syscall::ReadInt,
             _ if value == Syscall::ReadFloat as Word => Syscall::ReadFloat,
             _ if value == Syscall::ReadDouble as Word => Syscall::ReadDouble,
             _ if value == Syscall::ReadChar as Word => Syscall::ReadChar,
             _ if value == Syscall::ReadString as Word => Syscall::ReadString,
             _ if value == Syscall::Sbrk as Word => Syscall::Sbrk,
+            _ if value == Syscall::GetEnv as Word => Syscall::GetEnv,
             _ if value == Syscall::Exit as Word => Syscall::Exit,
             _ if value == Syscall::Exit2 as Word => Syscall::Exit2,
-            _ => panic!("Invalid syscall number: {}", value),
-        }
+            _ => return Err(VmError::UnknownSyscall(value)),
+        })
     }
 }
 
 #[cfg(test)]
 mod test_interpreter {
-    use crate::{parser::parse, vm::VM};
+    use crate::{
+        address::Address,
+        memory::{Endianness, MemoryError},
+        parser::parse,
+        program::{from_elf, ElfError, InstructionKind, LinkError, Program, Word},
+        registers::{Cp0, Register},
+        syscall::{SyscallResult, VmContext},
+        vm::{cycle_cost, ExceptionCode, VmError, MAX_CALL_DEPTH, VM},
+    };
+    use std::{cell::RefCell, collections::HashMap, io::Write, rc::Rc};
 
     #[test]
     fn hello_world() {
@@ -536,6 +1908,1715 @@ mod test_interpreter {
         assert_ne!(prog, None);
         let program = prog.unwrap();
         let mut vm = VM::new(program, Vec::new());
-        vm.execute(vm.entrypoint().expect("No entrypoint found"));
+        vm.execute(vm.entrypoint().expect("No entrypoint found"))
+            .unwrap();
+    }
+
+    fn run(input: &str) -> VM {
+        let program = parse(input).unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        vm
+    }
+
+    #[test]
+    fn rotate() {
+        let vm = run(".text
+main:
+\tlui $t0, 0x8000
+\tori $t0, $t0, 1
+\tror $t1, $t0, 1
+\trotr $t2, $t0, 33
+\trol $t3, $t0, 1
+\tli $t4, 1
+\trotrv $t5, $t0, $t4
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers.get(&Register::T1), 0xC000_0000);
+        assert_eq!(vm.registers.get(&Register::T2), 0xC000_0000);
+        assert_eq!(vm.registers.get(&Register::T3), 0x0000_0003);
+        assert_eq!(vm.registers.get(&Register::T5), 0xC000_0000);
+    }
+
+    #[test]
+    fn set_less_than() {
+        let vm = run(".text
+main:
+\tlui $t0, 0xFFFF
+\tori $t0, $t0, 0xFFFF
+\tli $t1, 1
+\tslt $t2, $t0, $t1
+\tsltu $t3, $t0, $t1
+\tslt $t4, $t1, $t0
+\tsltu $t5, $t1, $t0
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers.get(&Register::T2), 1);
+        assert_eq!(vm.registers.get(&Register::T3), 0);
+        assert_eq!(vm.registers.get(&Register::T4), 0);
+        assert_eq!(vm.registers.get(&Register::T5), 1);
+    }
+
+    #[test]
+    fn load_word_from_label() {
+        let vm = run(".data
+value: .word 4660
+.text
+main:
+\tlw $t0, value
+\tla $t1, value
+\tlw $t2, 0($t1)
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers.get(&Register::T0), 0x1234);
+        assert_eq!(vm.registers.get(&Register::T1), 0x1001_0000);
+        assert_eq!(vm.registers.get(&Register::T2), 0x1234);
+    }
+
+    #[test]
+    fn branch() {
+        let vm = run(".text
+main:
+\tli $t0, 3
+\tli $t3, 1
+loop:
+\taddi $t1, $t1, 2
+\tsub $t0, $t0, $t3
+\tbgtz $t0, loop
+\tbeq $t1, $zero, main
+\tbne $t1, $zero, 1
+\tli $t2, 1
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers.get(&Register::T1), 6);
+        assert_eq!(vm.registers.get(&Register::T2), 0);
+    }
+
+    #[test]
+    fn comparison_branch() {
+        let vm = run(".text
+main:
+\tli $t1, 5
+\tli $t2, 1
+loop:
+\tadd $t0, $t0, $t2
+\tblt $t0, $t1, loop
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers.get(&Register::T0), 5);
+    }
+
+    #[test]
+    fn coverage() {
+        let vm = run(".text
+main:
+\tli $t0, 1
+\tbeq $t0, $zero, skip
+\tli $v0, 10
+\tsyscall
+skip:
+\tli $t1, 1
+\tli $v0, 10
+\tsyscall
+");
+        let main = vm.memory().address_of_label("main").unwrap();
+        let skip = vm.memory().address_of_label("skip").unwrap();
+        assert_eq!(vm.coverage().get(&main), Some(&1));
+        assert_eq!(vm.coverage().get(&(main + 12u32)), Some(&1));
+        for offset in [0u32, 4, 8] {
+            assert_eq!(vm.coverage().get(&(skip + offset)), None);
+        }
+        let report = vm.show_coverage();
+        assert!(report.contains("skip:\n       0  0x00400010  li $t1, 0x1\n"));
+    }
+
+    #[test]
+    fn jal_link() {
+        let program = parse(
+            ".text
+main:
+\tjal double
+\tmove $t2, $t1
+\tli $v0, 10
+\tsyscall
+double:
+\tli $t0, 21
+\tadd $t1, $t0, $t0
+\tjr $ra
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let main = vm.entrypoint().unwrap();
+        vm.execute(main).unwrap();
+        assert_eq!(Address::new(vm.registers.get(&Register::Ra)), main + 4u32);
+        assert_eq!(vm.registers.get(&Register::T2), 42);
+    }
+
+    /// A minimal ELF executable with a program header for each `(address, contents, flags)` segment.
+    fn elf(entry: u32, segments: &[(u32, &[u8], u32)]) -> Vec<u8> {
+        let mut bytes = b"\x7FELF\x01\x01\x01".to_vec();
+        bytes.resize(16, 0);
+        for half in [2u16, 8] {
+            bytes.extend(half.to_le_bytes());
+        }
+        for word in [1u32, entry, 52, 0, 0] {
+            bytes.extend(word.to_le_bytes());
+        }
+        for half in [52u16, 32, segments.len() as u16, 40, 0, 0] {
+            bytes.extend(half.to_le_bytes());
+        }
+        let mut offset = 52 + 32 * segments.len() as u32;
+        for (address, contents, flags) in segments {
+            let size = contents.len() as u32;
+            for word in [1, offset, *address, *address, size, size + 4, *flags, 4] {
+                bytes.extend(word.to_le_bytes());
+            }
+            offset += size;
+        }
+        for (_, contents, _) in segments {
+            bytes.extend(*contents);
+        }
+        bytes
+    }
+
+    #[test]
+    fn elf_image() {
+        // Assembled with GNU `as -EL -mips32 -no-mips16 -O0`, without delay slots
+        let text = [
+            0x3C0A_1001u32, // lui $t2, 0x1001
+            0x8D4B_0000,    // lw $t3, 0($t2)
+            0x0C10_0005,    // jal twice
+            0x2002_000A,    // addi $v0, $zero, 10
+            0x0000_000C,    // syscall
+            0x016B_4820,    // twice: add $t1, $t3, $t3
+            0x03E0_0008,    // jr $ra
+        ]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect::<Vec<_>>();
+        let data = 22u32.to_le_bytes();
+        let bytes = elf(
+            0x0040_0000,
+            &[(0x0040_0000, &text, 0b101), (0x1001_0000, &data, 0b110)],
+        );
+
+        let image = from_elf(&bytes).unwrap();
+        assert_eq!(image.labels["L_00400014"], Address::new(0x0040_0014));
+        let mut vm = VM::from_image(image, Vec::new());
+        assert_eq!(vm.entrypoint(), Some(Address::new(0x0040_0000)));
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        assert_eq!(vm.registers.get(&Register::T1), 44);
+        assert_eq!(vm.exit_code(), Some(0));
+
+        assert_eq!(
+            from_elf(&bytes[..40]),
+            Err(ElfError::Malformed("truncated"))
+        );
+        assert_eq!(
+            from_elf(b"MZ"),
+            Err(ElfError::Malformed("missing ELF magic"))
+        );
+
+        // The memory size of the first segment, which is zero-filled
+        let mut huge = bytes.clone();
+        huge[52 + 20..52 + 24].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            from_elf(&huge),
+            Err(ElfError::Unsupported("segment too large"))
+        );
+        let stack = elf(0x0040_0000, &[(0xFFFF_FFF0, &text, 0b101)]);
+        assert_eq!(
+            from_elf(&stack),
+            Err(ElfError::Unsupported("segment overlaps the stack"))
+        );
+    }
+
+    #[test]
+    fn link_global_function() {
+        let main = parse(
+            ".text
+.globl main
+main:
+\tjal double
+\tnop
+\tli $v0, 10
+\tsyscall
+",
+        )
+        .unwrap();
+        let library = parse(
+            ".text
+.globl double
+double:
+\tli $t0, 21
+\tadd $t1, $t0, $t0
+\tjr $ra
+",
+        )
+        .unwrap();
+        let program = Program::link(vec![main, library]).unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        assert_eq!(vm.registers.get(&Register::T1), 42);
+        assert_eq!(vm.exit_code(), Some(0));
+
+        let unit = || parse(".text\n.globl main\nmain:\n\tnop\n").unwrap();
+        assert_eq!(
+            Program::link(vec![unit(), unit()]),
+            Err(LinkError::DuplicateGlobal("main".to_string()))
+        );
+
+        let based = |source: &str| parse(source).unwrap();
+        assert_eq!(
+            Program::link(vec![
+                based(".data 0x10020000\na: .word 1\n.text\nmain:\n\tnop\n"),
+                based(".data 0x10030000\nb: .word 2\n"),
+            ]),
+            Err(LinkError::ConflictingBase {
+                section: ".data",
+                first: Address::new(0x1002_0000),
+                second: Address::new(0x1003_0000),
+            })
+        );
+        let linked = Program::link(vec![
+            based(".data 0x10020000\na: .word 1\n.text\nmain:\n\tnop\n"),
+            based(".data\nb: .word 2\n"),
+        ]);
+        assert_eq!(
+            linked.unwrap().data_section.base,
+            Some(Address::new(0x1002_0000))
+        );
+    }
+
+    /// Store `0x44332211` to the unaligned address `value + 1` and load it back,
+    /// with the `left` and `right` instructions addressing the first and last byte.
+    fn unaligned_word(endianness: Endianness, left: u8, right: u8) -> VM {
+        let program = parse(&format!(
+            ".data
+value: .word 0
+next: .word 0
+.text
+main:
+\tla $t0, value
+\tlui $t1, 0x4433
+\tori $t1, $t1, 0x2211
+\tswl $t1, {left}($t0)
+\tswr $t1, {right}($t0)
+\tlwl $t2, {left}($t0)
+\tlwr $t2, {right}($t0)
+\tlbu $t3, 1($t0)
+\tlbu $t4, 4($t0)
+\tli $v0, 10
+\tsyscall
+"
+        ))
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        vm.memory_mut().set_endianness(endianness);
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        assert_eq!(vm.registers.get(&Register::T2), 0x4433_2211);
+        vm
+    }
+
+    #[test]
+    fn unaligned_load_store() {
+        // Little-endian: the most-significant byte is at the highest address
+        let vm = unaligned_word(Endianness::Little, 4, 1);
+        assert_eq!(vm.registers.get(&Register::T3), 0x11);
+        assert_eq!(vm.registers.get(&Register::T4), 0x44);
+
+        // Big-endian: the most-significant byte is at the lowest address
+        let vm = unaligned_word(Endianness::Big, 1, 4);
+        assert_eq!(vm.registers.get(&Register::T3), 0x44);
+        assert_eq!(vm.registers.get(&Register::T4), 0x11);
+    }
+
+    #[test]
+    fn strict_alignment() {
+        let program = || {
+            parse(
+                ".data
+value: .word 4660
+next: .word 0
+.text
+main:
+\tla $t0, value
+\tlw $t1, 1($t0)
+\tli $v0, 10
+\tsyscall
+",
+            )
+            .unwrap()
+        };
+        let mut vm = VM::new(program(), Vec::new());
+        let err = vm.execute(vm.entrypoint().unwrap()).unwrap_err();
+        assert_eq!(
+            err,
+            VmError::AddressError {
+                address: 0x1001_0001.into(),
+                alignment: 4
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Address error: 0x10010001 is not aligned to 4 bytes"
+        );
+
+        let mut vm = VM::new(program(), Vec::new());
+        vm.set_strict_alignment(false);
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        assert_eq!(vm.registers.get(&Register::T1), 0x12);
+    }
+
+    #[test]
+    fn sign_extend() {
+        let vm = run(".text
+main:
+\tli $t0, 0x80
+\tseb $t1, $t0
+\tli $t2, 0x17F
+\tseb $t3, $t2
+\tli $t4, 0x8000
+\tseh $t5, $t4
+\tli $t6, 0x7FFF
+\tseh $t7, $t6
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers.get(&Register::T1), 0xFFFF_FF80);
+        assert_eq!(vm.registers.get(&Register::T3), 0x7F);
+        assert_eq!(vm.registers.get(&Register::T5), 0xFFFF_8000);
+        assert_eq!(vm.registers.get(&Register::T7), 0x7FFF);
+    }
+
+    #[test]
+    fn count_leading_zeros() {
+        let vm = run(".text
+main:
+\tli $t0, 1
+\tclz $t1, $t0
+\tclz $t2, $zero
+\tlui $t3, 0x8000
+\tclz $t4, $t3
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers.get(&Register::T1), 31);
+        assert_eq!(vm.registers.get(&Register::T2), 32);
+        assert_eq!(vm.registers.get(&Register::T4), 0);
+    }
+
+    #[test]
+    fn shift_amount_masking() {
+        let vm = run(".text
+main:
+\tli $t0, 1
+\tli $t1, 35
+\tsllv $t2, $t0, $t1
+\tsll $t3, $t0, 35
+\tlui $t4, 0x8000
+\tsra $t5, $t4, 31
+\tli $t6, 31
+\tsrav $t7, $t4, $t6
+\tsrl $s0, $t4, 31
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers.get(&Register::T2), 8);
+        assert_eq!(vm.registers.get(&Register::T3), 8);
+        assert_eq!(vm.registers.get(&Register::T5), 0xFFFF_FFFF);
+        assert_eq!(vm.registers.get(&Register::T7), 0xFFFF_FFFF);
+        assert_eq!(vm.registers.get(&Register::S0), 1);
+    }
+
+    #[test]
+    fn run_captured() {
+        let program = parse(
+            ".text
+main:
+\tli $v0, 5
+\tsyscall
+\tsll $a0, $v0, 1
+\tli $v0, 1
+\tsyscall
+\tli $v0, 10
+\tsyscall
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let result = vm.run_captured(vm.entrypoint().unwrap(), "21\n").unwrap();
+        assert_eq!(result.stdout, "42");
+        assert_eq!(result.registers[Register::A0 as usize], 42);
+        assert_eq!(result.registers[Register::V0 as usize], 10);
+        assert_eq!(result.instructions, 7);
+        assert_eq!(result.exit, Some(0));
+    }
+
+    #[test]
+    fn with_input() {
+        let source =
+            ".text\nmain:\n\tli $v0, 5\n\tsyscall\n\tmove $t0, $v0\n\tli $v0, 10\n\tsyscall\n";
+        let mut vm = VM::with_input(parse(source).unwrap(), Vec::new(), "42\n");
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        assert_eq!(vm.registers().get(&Register::T0), 42);
+
+        let mut vm = VM::with_input(parse(source).unwrap(), Vec::new(), "notanumber\n");
+        let err = vm.execute(vm.entrypoint().unwrap()).unwrap_err();
+        assert_eq!(
+            err,
+            VmError::InputParseError {
+                expected: "an integer",
+                input: "notanumber".to_string()
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Invalid input, expected an integer: \"notanumber\""
+        );
+
+        let mut vm = VM::new(parse(source).unwrap(), Vec::new());
+        vm.set_input(std::io::Cursor::new(b"\xFF42\n".to_vec()));
+        let err = vm.execute(vm.entrypoint().unwrap()).unwrap_err();
+        assert!(matches!(err, VmError::InputError(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn step_back() {
+        let program = parse(
+            ".data
+value: .word 1
+.text
+main:
+\tli $t0, 5
+\tla $t1, value
+\tsw $t0, 0($t1)
+\tli $v0, 10
+\tsyscall
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        vm.enable_rewind(8);
+        let main = vm.entrypoint().unwrap();
+        let value = vm.memory().address_of_label("value").unwrap();
+        vm.registers_mut().pc = main.unwrap();
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.memory_mut().read_word(value).unwrap(), 5);
+
+        assert!(vm.step_back().unwrap());
+        assert!(vm.step_back().unwrap());
+        // Back after the first instruction
+        assert_eq!(vm.registers().pc, (main + 4u32).unwrap());
+        assert_eq!(vm.registers().get(&Register::T0), 5);
+        assert_eq!(vm.registers().get(&Register::T1), 0);
+        assert_eq!(vm.memory_mut().read_word(value).unwrap(), 1);
+        assert_eq!(vm.instruction_count(), 1);
+
+        assert!(vm.step_back().unwrap());
+        assert!(!vm.step_back().unwrap());
+        assert_eq!(vm.registers().pc, main.unwrap());
+    }
+
+    #[test]
+    fn trace_hook() {
+        let program = parse(".text\nmain:\n\tli $t0, 5\n\tnop\n\tli $v0, 10\n\tsyscall\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        let trace = steps.clone();
+        vm.set_trace_hook(move |step| {
+            trace
+                .borrow_mut()
+                .push((step.pc, step.instruction.kind.clone(), step.changes.clone()))
+        });
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        let steps = steps.borrow();
+        assert_eq!(steps.len(), 4);
+        assert_eq!(steps[0].0, Address::new(0x0040_0000));
+        assert_eq!(steps[0].2, vec![(Register::T0, 0, 5)]);
+        assert_eq!(steps[1].1, InstructionKind::Nop);
+        assert!(steps[1].2.is_empty());
+        assert_eq!(steps[3].1, InstructionKind::Syscall);
+    }
+
+    #[test]
+    fn json_stable() {
+        let source = ".text\nmain:\n\tli $t0, 1\n\tli $s0, 2\n\tli $v0, 10\n\tsyscall\n";
+        let first = run(source);
+        let second = run(source);
+        assert_eq!(
+            first.registers().show_json(),
+            second.registers().show_json()
+        );
+        assert_eq!(first.show_coverage(), second.show_coverage());
+    }
+
+    #[test]
+    fn mem_stats() {
+        let vm = run(".data
+a: .word 1
+b: .word 2
+.text
+main:
+\tlw $t0, a
+\tlw $t1, b
+\tadd $t2, $t0, $t1
+\tsw $t2, a
+\tsb $t2, b
+\tli $v0, 10
+\tsyscall
+");
+        let stats = vm.memory().stats();
+        assert_eq!(stats.reads.get(".data"), Some(&2));
+        assert_eq!(stats.writes.get(".data"), Some(&2));
+        assert_eq!(stats.reads.get(".text"), None);
+        assert_eq!(stats.reads_by_size.get(&4), Some(&2));
+        assert_eq!(stats.writes_by_size.get(&4), Some(&1));
+        assert_eq!(stats.writes_by_size.get(&1), Some(&1));
+        assert_eq!(stats.fetches, 7);
+        assert!(stats.show().contains(".data               2          2\n"));
+    }
+
+    #[test]
+    fn backtrace() {
+        let program = parse(
+            ".text
+main:
+\tjal outer
+\tli $v0, 10
+\tsyscall
+outer:
+\tnop
+\tjal inner
+\tjr $ra
+inner:
+\tlw $t0, 0($zero)
+\tjr $ra
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let entrypoint = vm.entrypoint().unwrap();
+        assert!(matches!(
+            vm.execute(entrypoint),
+            Err(VmError::MemoryFault { .. })
+        ));
+        let label = |label: &str| vm.memory().address_of_label(label).unwrap();
+        assert_eq!(
+            vm.backtrace(),
+            vec![label("inner"), label("outer") + 4u32, label("main")]
+        );
+        assert_eq!(
+            vm.show_backtrace(),
+            "#0 0x00400018 <inner>\n#1 0x00400010 <outer+4>\n#2 0x00400000 <main>\n"
+        );
+    }
+
+    #[test]
+    fn read_string_fault() {
+        let program = parse(
+            ".text
+main:
+\tli $a0, 0
+\tli $a1, 8
+\tli $v0, 8
+\tsyscall
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let entrypoint = vm.entrypoint().unwrap();
+        assert!(matches!(
+            vm.run_captured(entrypoint, "text\n"),
+            Err(VmError::MemoryFault { address, .. }) if address == Address::new(0)
+        ));
+    }
+
+    #[test]
+    fn print_string_fault() {
+        let program = parse(
+            ".text
+main:
+\tli $a0, 0
+\tli $a1, 8
+\tli $v0, 4
+\tsyscall
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let entrypoint = vm.entrypoint().unwrap();
+        assert!(matches!(
+            vm.run_captured(entrypoint, ""),
+            Err(VmError::MemoryFault { address, .. }) if address == Address::new(0)
+        ));
+    }
+
+    #[test]
+    fn backtrace_returns() {
+        let program = parse(
+            ".text
+main:
+\tjal copy
+\tjal tail
+back:
+\tlw $t0, 0($zero)
+copy:
+\tmove $t0, $ra
+\tjr $t0
+tail:
+\tj back
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let main = vm.entrypoint().unwrap();
+        vm.registers_mut().pc = main.unwrap();
+        // Returning through another register than $ra
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.backtrace(), vec![main + 4u32]);
+        // Returning with a jump to the return address
+        for _ in 0..2 {
+            vm.step().unwrap();
+        }
+        let back = vm.memory().address_of_label("back").unwrap();
+        assert_eq!(vm.backtrace(), vec![back]);
+    }
+
+    #[test]
+    fn call_depth() {
+        let program = parse(
+            ".text
+main:
+\tli $t0, 2000
+\tjal recurse
+recurse:
+\taddi $t0, $t0, -1
+\tbgtz $t0, call
+\tlw $t1, 0($zero)
+call:
+\tjal recurse
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let entrypoint = vm.entrypoint().unwrap();
+        assert!(matches!(
+            vm.execute(entrypoint),
+            Err(VmError::MemoryFault { .. })
+        ));
+        let backtrace = vm.backtrace();
+        assert_eq!(backtrace.len(), MAX_CALL_DEPTH + 1);
+        assert_eq!(backtrace[1], vm.memory().address_of_label("call").unwrap());
+    }
+
+    #[test]
+    fn args() {
+        let program = parse(
+            ".text
+main:
+\tmove $t0, $a0
+\tlw $a0, 4($a1)
+\tli $v0, 4
+\tsyscall
+\tlw $t1, 8($a1)
+\tli $v0, 10
+\tsyscall
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        vm.set_args(vec!["first".to_string(), "second".to_string()]);
+        let entrypoint = vm.entrypoint().unwrap();
+        let result = vm.run_captured(entrypoint, "").unwrap();
+        assert_eq!(result.stdout, "second");
+        assert_eq!(result.registers[Register::T0 as usize], 2);
+        assert_eq!(result.registers[Register::T1 as usize], 0);
+        assert_eq!(
+            result.registers[Register::Sp as usize],
+            result.registers[Register::A1 as usize]
+        );
+    }
+
+    #[test]
+    fn env() {
+        let program = parse(
+            ".data
+name: .asciiz \"FOO\"
+missing: .asciiz \"BAR\"
+.text
+main:
+\tla $a0, missing
+\tli $v0, 60
+\tsyscall
+\tmove $t0, $v0
+\tla $a0, name
+\tli $v0, 60
+\tsyscall
+\tmove $a0, $v0
+\tli $v0, 4
+\tsyscall
+\tli $v0, 10
+\tsyscall
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        vm.set_env(HashMap::from([("FOO".to_string(), "bar".to_string())]));
+        let entrypoint = vm.entrypoint().unwrap();
+        let result = vm.run_captured(entrypoint, "").unwrap();
+        assert_eq!(result.stdout, "bar");
+        assert_eq!(result.registers[Register::T0 as usize], 0);
+    }
+
+    #[test]
+    fn ra_check() {
+        let source = |prologue: &str, epilogue: &str| {
+            format!(
+                ".data
+saved: .word 0
+.text
+main:
+\tjal outer
+\tli $v0, 10
+\tsyscall
+outer:
+{}\tjal inner
+{}\tjr $ra
+inner:
+\tjr $ra
+",
+                prologue, epilogue
+            )
+        };
+        // Without saving $ra, outer returns to itself forever, so only step through the calls
+        let mut vm = VM::new(parse(&source("", "")).unwrap(), Vec::new());
+        vm.set_ra_check(true);
+        vm.registers_mut().pc = vm.entrypoint().unwrap().unwrap();
+        for _ in 0..4 {
+            vm.step().unwrap();
+        }
+        let call = vm.memory().address_of_label("outer").unwrap();
+        assert_eq!(vm.ra_warnings(), [call]);
+        assert_eq!(vm.show_code_address(call), "0x0040000C <outer>");
+
+        let vm = {
+            let prologue = "\tla $t0, saved\n\tsw $ra, 0($t0)\n";
+            let epilogue = "\tla $t0, saved\n\tlw $ra, 0($t0)\n";
+            let mut vm = VM::new(parse(&source(prologue, epilogue)).unwrap(), Vec::new());
+            vm.set_ra_check(true);
+            vm.execute(vm.entrypoint().unwrap()).unwrap();
+            vm
+        };
+        assert!(vm.ra_warnings().is_empty());
+    }
+
+    #[test]
+    fn exception_handler() {
+        let source = ".text
+main:
+\tli $t0, 0x7FFFFFFF
+\taddi $t1, $t0, 1
+\tli $v0, 10
+\tsyscall
+handler:
+\tli $t2, 1
+\tli $v0, 10
+\tsyscall
+";
+        let mut vm = VM::new(parse(source).unwrap(), Vec::new());
+        let main = vm.entrypoint().unwrap();
+        assert_eq!(vm.execute(main), Err(VmError::ArithmeticOverflow));
+
+        let mut vm = VM::new(parse(source).unwrap(), Vec::new());
+        let handler = vm.memory().address_of_label("handler").unwrap();
+        vm.set_exception_handler(handler);
+        vm.execute(main).unwrap();
+        let cp0 = &vm.registers().cp0;
+        assert_eq!(cp0.epc, (main + 4u32).unwrap());
+        assert_eq!(cp0.cause >> 2, ExceptionCode::Overflow as Word);
+        assert_eq!(cp0.status & Cp0::EXL, Cp0::EXL);
+        assert_eq!(vm.registers().get(&Register::T1), 0);
+        assert_eq!(vm.registers().get(&Register::T2), 1);
+    }
+
+    #[test]
+    fn eret() {
+        let program = parse(
+            ".text
+main:
+\tli $t0, 1
+\tbreak
+\tli $t2, 3
+\tli $v0, 10
+\tsyscall
+handler:
+\tli $t1, 2
+\teret
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let main = vm.entrypoint().unwrap();
+        let handler = vm.memory().address_of_label("handler").unwrap();
+        vm.set_exception_handler(handler);
+        vm.registers_mut().pc = main.unwrap();
+        vm.add_breakpoint(handler + 4u32);
+        assert_eq!(vm.resume(), Ok(false));
+        let cp0 = &vm.registers().cp0;
+        assert_eq!(cp0.epc, (main + 4u32).unwrap());
+        assert_eq!(cp0.cause >> 2, ExceptionCode::Breakpoint as Word);
+        // Skip the break, as a handler would by writing EPC
+        vm.registers_mut().cp0.epc += 4;
+        assert_eq!(vm.resume(), Ok(true));
+        assert_eq!(vm.registers().cp0.status & Cp0::EXL, 0);
+        assert_eq!(vm.registers().get(&Register::T1), 2);
+        assert_eq!(vm.registers().get(&Register::T2), 3);
+    }
+
+    #[test]
+    fn move_cp0() {
+        let program = parse(
+            ".text
+main:
+\tli $t0, 0x7FFFFFFF
+\tadd $t1, $t0, $t0
+\tli $v0, 10
+\tsyscall
+handler:
+\tmfc0 $t2, $13
+\tmfc0 $t3, $14
+\taddiu $k0, $t3, 4
+\tmtc0 $k0, $14
+\teret
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let main = vm.entrypoint().unwrap();
+        let handler = vm.memory().address_of_label("handler").unwrap();
+        vm.set_exception_handler(handler);
+        vm.execute(main).unwrap();
+        assert_eq!(
+            vm.registers().get(&Register::T2) >> 2,
+            ExceptionCode::Overflow as Word
+        );
+        assert_eq!(vm.registers().get(&Register::T3), (main + 4u32).unwrap());
+        assert_eq!(vm.registers().cp0.epc, (main + 8u32).unwrap());
+        assert_eq!(vm.registers().get(&Register::T1), 0);
+    }
+
+    #[test]
+    fn bal() {
+        let vm = run(".text
+main:
+\tbal here
+here:
+\tmove $t0, $ra
+\tbal skip
+\tli $t1, 1
+skip:
+\tli $v0, 10
+\tsyscall
+");
+        let here = vm.memory().address_of_label("here").unwrap();
+        assert_eq!(vm.registers().get(&Register::T0), here.unwrap());
+        assert_eq!(vm.registers().get(&Register::Ra), (here + 8u32).unwrap());
+        assert_eq!(vm.registers().get(&Register::T1), 0);
+    }
+
+    #[test]
+    fn negative_offset() {
+        // Push a value with the initial $sp, then pop the stack and read it back below $sp
+        let vm = run(".text
+main:
+\tli $t0, 42
+\tli $t3, 4
+\tsubu $sp, $sp, $t3
+\tsw $t0, 0($sp)
+\taddu $sp, $sp, $t3
+\tlw $t1, -4($sp)
+\tsw $t0, -8($sp)
+\tlw $t2, -8($sp)
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers().get(&Register::T1), 42);
+        assert_eq!(vm.registers().get(&Register::T2), 42);
+    }
+
+    #[test]
+    fn label_offset() {
+        let vm = run(".data
+arr: .word 10
+arr_1: .word 20
+arr_2: .word 30
+.text
+main:
+\tli $t1, 8
+\tlw $t0, arr($t1)
+\tli $t1, 4
+\tsw $t0, arr($t1)
+\tlw $t2, arr($t1)
+\tli $t1, -8
+\tlw $t3, arr_2($t1)
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers.get(&Register::T0), 30);
+        assert_eq!(vm.registers.get(&Register::T2), 30);
+        assert_eq!(vm.registers.get(&Register::T3), 10);
+    }
+
+    #[test]
+    fn syscall_handler() {
+        let program = parse(
+            ".text\nmain:\n\tli $v0, 100\n\tsyscall\n\tmove $t0, $v0\n\tli $v0, 101\n\tsyscall\n",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        vm.set_syscall_handler(|number, vm: &mut VmContext| match number {
+            100 => {
+                vm.registers_mut().set(&Register::V0, 0xCAFE);
+                SyscallResult::Continue
+            }
+            _ => SyscallResult::Unhandled,
+        });
+        let result = vm.execute(vm.entrypoint().unwrap());
+        assert_eq!(result, Err(VmError::UnknownSyscall(101)));
+        assert_eq!(vm.registers.get(&Register::T0), 0xCAFE);
+
+        // Built-in syscalls still run when not handled
+        let program = parse(".text\nmain:\n\tli $a0, 7\n\tli $v0, 17\n\tsyscall\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        vm.set_syscall_handler(|_, _: &mut VmContext| SyscallResult::Unhandled);
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        assert_eq!(vm.exit_code(), Some(7));
+    }
+
+    #[test]
+    fn syscall_context() {
+        let program = parse(
+            ".data\nname: .asciiz \"mips\"\n.text\nmain:\n\tla $a0, name\n\tli $v0, 100\n\tsyscall\n\tmove $t0, $v0\n\tli $v0, 10\n\tsyscall\n",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        vm.set_syscall_handler(|number, vm: &mut VmContext| match number {
+            100 => {
+                let address = Address::new(vm.read_arg(Register::A0));
+                let name = vm.read_cstr(address).unwrap();
+                vm.set_return(name.iter().map(|&byte| byte as Word).sum());
+                SyscallResult::Continue
+            }
+            _ => SyscallResult::Unhandled,
+        });
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        let expected = "mips".bytes().map(|byte| byte as Word).sum::<Word>();
+        assert_eq!(vm.registers.get(&Register::T0), expected);
+        assert_eq!(vm.exit_code(), Some(0));
+    }
+
+    #[test]
+    fn estimated_cycles() {
+        let source = ".data
+value: .word 3
+.text
+main:
+\tli $t1, 3
+\tli $t2, 1
+loop:
+\tlw $t3, value
+\tmult $t4, $t3, $t3
+\tadd $t0, $t0, $t2
+\tbne $t0, $t1, loop
+\tli $v0, 10
+\tsyscall
+";
+        let mut vm = VM::new(parse(source).unwrap(), Vec::new());
+        let kinds = Rc::new(RefCell::new(Vec::new()));
+        let trace = kinds.clone();
+        vm.set_trace_hook(move |step| trace.borrow_mut().push(step.instruction.kind.clone()));
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        let expected = kinds.borrow().iter().map(cycle_cost).sum::<u32>();
+        assert_eq!(vm.estimated_cycles(), expected as u64);
+        assert_eq!(expected, 2 + 2 + 3 * (2 + 4 + 1 + 1) + 2 + 1);
+
+        let mut vm = VM::new(parse(source).unwrap(), Vec::new());
+        vm.set_cost_model(|_| 1);
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        assert_eq!(vm.estimated_cycles(), vm.instruction_count());
+    }
+
+    #[test]
+    fn reset() {
+        let program = parse(
+            ".data
+count: .word 0
+.text
+main:
+\tli $v0, 5
+\tsyscall
+\tlw $t0, count
+\tadd $t0, $t0, $v0
+\tsw $t0, count
+\tmove $a0, $t0
+\tli $v0, 1
+\tsyscall
+\tli $v0, 10
+\tsyscall
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let entrypoint = vm.entrypoint().unwrap();
+        let first = vm.run_captured(entrypoint, "3\n").unwrap();
+        vm.reset();
+        let second = vm.run_captured(entrypoint, "4\n").unwrap();
+        assert_eq!(first.stdout, "3");
+        assert_eq!(second.stdout, "4");
+        assert_eq!(first.instructions, second.instructions);
+        assert_eq!(
+            second.registers[Register::Sp as usize],
+            first.registers[Register::Sp as usize]
+        );
+    }
+
+    #[test]
+    fn negative_immediate() {
+        let vm = run(".text
+main:
+\tli $t0, -1
+\taddi $t1, $zero, -1
+\taddiu $t2, $t0, -2
+\tslti $t3, $t0, 0
+\tsltiu $t4, $t0, -1
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers.get(&Register::T0), 0xFFFF_FFFF);
+        assert_eq!(vm.registers.get(&Register::T1), 0xFFFF_FFFF);
+        assert_eq!(vm.registers.get(&Register::T2) as i32, -3);
+        assert_eq!(vm.registers.get(&Register::T3), 1);
+        // sltiu sign-extends the immediate, then compares unsigned
+        assert_eq!(vm.registers.get(&Register::T4), 0);
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let vm = run(".text
+main:
+\tmove $s0, $sp
+\tli $t0, 77
+\tli $t1, 88
+\tli $t2, 8
+\tsubu $sp, $sp, $t2
+\tsw $t0, 4($sp)
+\tsw $t1, 0($sp)
+\tlw $t3, 0($sp)
+\tlw $t4, 4($sp)
+\taddu $sp, $sp, $t2
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers.get(&Register::T3), 88);
+        assert_eq!(vm.registers.get(&Register::T4), 77);
+        assert_eq!(
+            vm.registers.get(&Register::Sp),
+            vm.registers.get(&Register::S0)
+        );
+    }
+
+    #[test]
+    fn read_string() {
+        let program = parse(
+            ".data
+line: .ascii \"................\"
+truncated: .ascii \"................\"
+eof: .ascii \"................\"
+.text
+main:
+\tla $a0, line
+\tli $a1, 16
+\tli $v0, 8
+\tsyscall
+\tmove $t0, $v0
+\tla $a0, truncated
+\tli $a1, 8
+\tli $v0, 8
+\tsyscall
+\tmove $t1, $v0
+\tla $a0, eof
+\tli $a1, 16
+\tli $v0, 8
+\tsyscall
+\tmove $t2, $v0
+\tli $v0, 10
+\tsyscall
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let result = vm
+            .run_captured(vm.entrypoint().unwrap(), "hi\nhello world\n")
+            .unwrap();
+        let mut read = |label: &str| {
+            let address = vm.memory().address_of_label(label).unwrap();
+            vm.memory_mut().read_cstr(address).unwrap()
+        };
+        assert_eq!(read("line"), b"hi\n");
+        assert_eq!(read("truncated"), b"hello w");
+        assert_eq!(read("eof"), b"");
+        assert_eq!(result.registers[Register::T0 as usize], 3);
+        assert_eq!(result.registers[Register::T1 as usize], 7);
+        assert_eq!(result.registers[Register::T2 as usize], 0);
+    }
+
+    #[test]
+    fn call() {
+        let program = parse(
+            ".text
+main:
+\tli $v0, 10
+\tsyscall
+square:
+\tmult $v0, $a0, $a0
+\tjr $ra
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        assert_eq!(vm.call("square", &[7]).unwrap(), 49);
+        vm.registers_mut().set(&Register::A0, 3);
+        assert_eq!(vm.call("square", &[]).unwrap(), 9);
+    }
+
+    #[test]
+    fn multiply() {
+        let vm = run(".text
+main:
+\tlui $t0, 1
+\tmultu $t1, $t0, $t0
+\tnor $t2, $zero, $zero
+\tmult $t3, $t2, $t0
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers.get(&Register::T1), 0);
+        assert_eq!(vm.registers.get(&Register::T3), 0xFFFF_0000);
+        // -1 * 0x10000 sign-extends into HI
+        assert_eq!(vm.registers.hi, 0xFFFF_FFFF);
+        assert_eq!(vm.registers.lo, 0xFFFF_0000);
+
+        let vm = run(".text
+main:
+\tlui $t0, 1
+\tmultu $t1, $t0, $t0
+\tli $v0, 10
+\tsyscall
+");
+        let (hi, lo) = (vm.registers.hi as u64, vm.registers.lo as u64);
+        assert_eq!(hi << 32 | lo, 0x1_0000_0000);
+    }
+
+    #[test]
+    fn multiply_two_operands() {
+        let vm = run(".text
+main:
+\tli $t0, 6
+\tli $t1, 7
+\tmult $t0, $t1
+\tmflo $t2
+\tmultu $t0, $t1
+\tli $v0, 10
+\tsyscall
+");
+        // Only HI:LO is written, the operands are unchanged
+        assert_eq!(vm.registers.get(&Register::T0), 6);
+        assert_eq!(vm.registers.get(&Register::T1), 7);
+        assert_eq!(vm.registers.get(&Register::T2), 42);
+        assert_eq!((vm.registers.hi, vm.registers.lo), (0, 42));
+    }
+
+    #[test]
+    fn remainder() {
+        let vm = run(".text
+main:
+\tli $t0, 17
+\tli $t1, 5
+\trem $t2, $t0, $t1
+\tdiv $t3, $t0, $t1
+\tnor $t4, $zero, $zero
+\trem $t5, $t4, $t1
+\tremu $t6, $t4, $t1
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(vm.registers.get(&Register::T2), 2);
+        assert_eq!(vm.registers.get(&Register::T3), 3);
+        // The remainder takes the sign of the dividend
+        assert_eq!(vm.registers.get(&Register::T5) as i32, -1);
+        assert_eq!(vm.registers.get(&Register::T6), 0xFFFF_FFFF % 5);
+
+        let program = parse(".text\nmain:\n\tli $t0, 17\n\trem $t1, $t0, $zero\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        assert_eq!(
+            vm.execute(vm.entrypoint().unwrap()),
+            Err(VmError::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn output_buffering() {
+        /// Counts the writes and flushes reaching the output sink.
+        #[derive(Clone, Default)]
+        struct Counting(Rc<RefCell<(Vec<u8>, usize, usize)>>);
+
+        impl Write for Counting {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                let mut counts = self.0.borrow_mut();
+                counts.0.extend_from_slice(buf);
+                counts.1 += 1;
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.0.borrow_mut().2 += 1;
+                Ok(())
+            }
+        }
+
+        let program = parse(
+            ".text
+main:
+\tli $t0, 1000
+\tli $t1, 1
+loop:
+\tli $a0, 97
+\tli $v0, 11
+\tsyscall
+\tsub $t0, $t0, $t1
+\tbgtz $t0, loop
+\tli $v0, 10
+\tsyscall
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let output = Counting::default();
+        vm.set_output(output.clone());
+        vm.set_output_buffering(true);
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        let (bytes, writes, flushes) = output.0.borrow().clone();
+        assert_eq!(bytes, vec![b'a'; 1000]);
+        assert_eq!(writes, 1);
+        assert_eq!(flushes, 1);
+
+        /// Input recording the output written before it is read.
+        struct Prompted(Counting, Rc<RefCell<Vec<u8>>>);
+
+        impl std::io::Read for Prompted {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                *self.1.borrow_mut() = self.0 .0.borrow().0.clone();
+                let input = b"7\n";
+                buf[..input.len()].copy_from_slice(input);
+                Ok(input.len())
+            }
+        }
+
+        let program = parse(
+            ".data
+prompt: .asciiz \"Number: \"
+.text
+main:
+\tla $a0, prompt
+\tli $v0, 4
+\tsyscall
+\tli $v0, 5
+\tsyscall
+\tli $v0, 10
+\tsyscall
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let output = Counting::default();
+        let prompted = Rc::new(RefCell::new(Vec::new()));
+        vm.set_output(output.clone());
+        vm.set_output_buffering(true);
+        vm.set_input(std::io::BufReader::new(Prompted(output, prompted.clone())));
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        assert_eq!(*prompted.borrow(), b"Number: ");
+    }
+
+    #[test]
+    fn max_print_len() {
+        let program = parse(
+            ".data\nshort: .asciiz \"hi\"\nlong: .ascii \"unterminated\"\n.text\nmain:\n\tla $a0, short\n\tli $v0, 4\n\tsyscall\n\tla $a0, long\n\tsyscall\n",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        vm.set_max_print_len(8);
+        let long = vm.memory().address_of_label("long").unwrap();
+        assert_eq!(
+            vm.run_captured(vm.entrypoint().unwrap(), ""),
+            Err(VmError::OutputLimitExceeded {
+                address: long,
+                limit: 8
+            })
+        );
+    }
+
+    #[test]
+    fn injected_io() {
+        let program = parse(
+            ".data
+msg: .asciiz \"ok \"
+buf: .ascii \"........\"
+.text
+main:
+\tli $v0, 5
+\tsyscall
+\tmove $a0, $v0
+\tli $v0, 1
+\tsyscall
+\tli $v0, 6
+\tsyscall
+\tmove $a0, $v0
+\tli $v0, 2
+\tsyscall
+\tli $v0, 7
+\tsyscall
+\tli $v0, 3
+\tsyscall
+\tli $v0, 12
+\tsyscall
+\tmove $a0, $v0
+\tli $v0, 11
+\tsyscall
+\tla $a0, buf
+\tli $a1, 8
+\tli $v0, 8
+\tsyscall
+\tla $a0, msg
+\tli $v0, 4
+\tsyscall
+\tla $a0, buf
+\tli $a1, 2
+\tli $v0, 18
+\tsyscall
+\tli $v0, 10
+\tsyscall
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        vm.registers_mut().set_double(12, -0.5);
+        let result = vm
+            .run_captured(vm.entrypoint().unwrap(), "12\n1.5\n2.25\nx\nhi\n")
+            .unwrap();
+        assert_eq!(result.stdout, "121.5-0.5xok hi");
+        assert_eq!(vm.registers().get_double(0), 2.25);
+    }
+
+    #[test]
+    fn print_int_sign() {
+        let program = parse(".text\nmain:\n\tli $a0, 0xFFFFFFFF\n\tli $v0, 1\n\tsyscall\n\tli $v0, 11\n\tli $a0, 32\n\tsyscall\n\tli $a0, 0xFFFFFFFF\n\tli $v0, 36\n\tsyscall\n\tli $v0, 10\n\tsyscall\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let result = vm.run_captured(vm.entrypoint().unwrap(), "").unwrap();
+        assert_eq!(result.stdout, "-1 4294967295");
+    }
+
+    #[test]
+    fn print_int_padded() {
+        let program = parse(".text\nmain:\n\tli $a0, 42\n\tli $a1, 5\n\tli $a2, 1\n\tli $v0, 37\n\tsyscall\n\tli $a2, 0\n\tsyscall\n\tli $v0, 10\n\tsyscall\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let result = vm.run_captured(vm.entrypoint().unwrap(), "").unwrap();
+        assert_eq!(result.stdout, "00042   42");
+    }
+
+    #[test]
+    fn print_int_padded_width_clamped() {
+        let program = parse(".text\nmain:\n\tli $a0, 7\n\tli $a1, 0x7FFFFFFF\n\tli $v0, 37\n\tsyscall\n\tli $v0, 10\n\tsyscall\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let result = vm.run_captured(vm.entrypoint().unwrap(), "").unwrap();
+        assert_eq!(result.stdout, format!("{:>255}", 7));
+    }
+
+    #[test]
+    fn print_string_n() {
+        let program = parse(
+            ".data\nbuffer: .ascii \"ab\\0cd\"\n.text\nmain:\n\tla $a0, buffer\n\tli $a1, 5\n\tli $v0, 18\n\tsyscall\n\tli $a1, 2\n\tsyscall\n\tli $v0, 10\n\tsyscall\n",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let result = vm.run_captured(vm.entrypoint().unwrap(), "").unwrap();
+        assert_eq!(result.stdout.as_bytes(), b"ab\0cdab");
+    }
+
+    #[test]
+    fn float_precision() {
+        let program = parse(".text\nmain:\n\tli $v0, 2\n\tsyscall\n\tli $v0, 11\n\tli $a0, 32\n\tsyscall\n\tli $v0, 3\n\tsyscall\n\tli $v0, 10\n\tsyscall\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let third = 1.0f32 / 3.0;
+        vm.registers_mut().set(&Register::A0, third.to_bits());
+        vm.registers_mut().set_double(12, 1.0 / 3.0);
+        let result = vm.run_captured(vm.entrypoint().unwrap(), "").unwrap();
+        assert_eq!(result.stdout, "0.33333334 0.3333333333333333");
+
+        vm.reset();
+        vm.set_float_precision(Some(4));
+        vm.registers_mut().set(&Register::A0, third.to_bits());
+        vm.registers_mut().set_double(12, 1.0 / 3.0);
+        let result = vm.run_captured(vm.entrypoint().unwrap(), "").unwrap();
+        assert_eq!(result.stdout, "0.3333 0.3333");
+    }
+
+    #[test]
+    fn format_float() {
+        let format = |value: f64| super::format_float(value, None);
+        assert_eq!(format(1.0), "1.0");
+        assert_eq!(format(-0.0), "-0.0");
+        assert_eq!(format(1e7), "1.0E7");
+        assert_eq!(format(1.5e-5), "1.5E-5");
+        assert_eq!(format(f64::NEG_INFINITY), "-Infinity");
+        assert_eq!(super::format_float(2.5f32, Some(0)), "2");
+    }
+
+    #[test]
+    fn double() {
+        let program = parse(".text\nmain:\n\tli $v0, 3\n\tsyscall\n\tli $v0, 7\n\tsyscall\n\tli $v0, 10\n\tsyscall\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        vm.registers_mut().set_double(12, 1234567.890625);
+        let result = vm.run_captured(vm.entrypoint().unwrap(), "-0.1\n").unwrap();
+        assert_eq!(result.stdout, "1234567.890625");
+        assert_eq!(vm.registers().get_double(0), -0.1);
+        assert_eq!(vm.registers().fpr[1], ((-0.1f64).to_bits() >> 32) as u32);
+    }
+
+    #[test]
+    fn invalid_jump_target() {
+        let program = parse(".data\nvalue: .word 1\n.text\nmain:\n\tj value\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let value = vm.memory().address_of_label("value").unwrap();
+        let err = vm.execute(vm.entrypoint().unwrap()).unwrap_err();
+        assert_eq!(
+            err,
+            VmError::BadJumpTarget {
+                register: None,
+                value: value.unwrap()
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Bad jump target: 0x10010000 is outside the .text section"
+        );
+    }
+
+    #[test]
+    fn exit_code() {
+        let program = parse(".text\nmain:\n\tli $a0, 42\n\tli $v0, 17\n\tsyscall\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let result = vm.run_captured(vm.entrypoint().unwrap(), "").unwrap();
+        assert_eq!(result.exit, Some(42));
+    }
+
+    #[test]
+    fn store_to_text() {
+        let program = parse(".text\nmain:\n\tla $t0, main\n\tsw $t1, 0($t0)\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let main = vm.entrypoint().unwrap();
+        let err = vm.execute(main).unwrap_err();
+        assert_eq!(
+            err,
+            VmError::MemoryFault {
+                address: main,
+                error: MemoryError::ProtectionFault
+            }
+        );
+    }
+
+    #[test]
+    fn try_new_errors() {
+        let program =
+            parse(".text 0x0FFFFFF0\nmain:\n\tnop\n\tnop\n\tnop\n\tnop\n\tnop\n").unwrap();
+        assert_eq!(
+            VM::try_new(program, Vec::new()).err(),
+            Some(VmError::ProgramTooLarge { size: 20, max: 15 })
+        );
+        let program = parse(".text\nmain:\n\tj nowhere\n").unwrap();
+        let err = VM::try_new(program, Vec::new()).err().unwrap();
+        assert!(matches!(err, VmError::LoadFailed(MemoryError::Assemble(_))));
+        assert_eq!(
+            err.to_string(),
+            "Failed to load the program: Failed to assemble `j nowhere`: unknown label nowhere"
+        );
+        let program = parse(".data 0x7F7FFFF0\nbuf: .space 32\n.text\nmain:\n\tnop\n").unwrap();
+        assert_eq!(
+            VM::try_new(program, Vec::new()).err(),
+            Some(VmError::LoadFailed(MemoryError::OutOfMemory))
+        );
+        let mut program = parse(".text\nmain:\n\tnop\n").unwrap();
+        program.text_section.blocks.clear();
+        assert_eq!(
+            VM::try_new(program, Vec::new()).err(),
+            Some(VmError::LoadFailed(MemoryError::NoText))
+        );
+    }
+
+    #[test]
+    fn sbrk_exhausted() {
+        let vm = run(".text
+main:
+\tli $a0, 16
+\tli $v0, 9
+\tsyscall
+\tmove $t0, $v0
+\tli $a0, -1
+\tli $v0, 9
+\tsyscall
+\tmove $t1, $v0
+\tli $v0, 10
+\tsyscall
+");
+        assert_eq!(
+            Address::new(vm.registers.get(&Register::T0)),
+            vm.memory().heap().start_address
+        );
+        assert_eq!(vm.registers.get(&Register::T1), Word::MAX);
+    }
+
+    #[test]
+    fn fell_off_text() {
+        let program = parse(".text\nmain:\n\tli $t0, 1\n\tnop\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let main = vm.entrypoint().unwrap();
+        let err = vm.execute(main).unwrap_err();
+        assert_eq!(err, VmError::FellOffText(main + 8));
+        assert_eq!(
+            err.to_string(),
+            "Execution fell off the end of .text at 0x00400008, add an exit syscall or a jump"
+        );
+        assert_eq!(vm.registers().get(&Register::T0), 1);
+    }
+
+    #[test]
+    fn load_byte_fault() {
+        let program = parse(".text\nmain:\n\tlb $t0, 0($zero)\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let err = vm.execute(vm.entrypoint().unwrap()).unwrap_err();
+        assert_eq!(
+            err,
+            VmError::MemoryFault {
+                address: Address::new(0),
+                error: MemoryError::InvalidSection
+            }
+        );
+    }
+
+    #[test]
+    fn store_to_rdata() {
+        let program = parse(
+            ".rdata\nanswer: .word 42\n.text\nmain:\n\tla $t0, answer\n\tlw $t1, 0($t0)\n\tsw $t1, 0($t0)\n",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let err = vm.execute(vm.entrypoint().unwrap()).unwrap_err();
+        assert_eq!(vm.registers().get(&Register::T1), 42);
+        assert_eq!(
+            err,
+            VmError::MemoryFault {
+                address: vm.memory().address_of_label("answer").unwrap(),
+                error: MemoryError::ProtectionFault
+            }
+        );
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        let program = parse(
+            ".text
+main:
+	la $t0, patch
+	li $t1, 0
+	sw $t1, 0($t0)
+patch:
+	li $t2, 7
+	li $v0, 10
+	syscall
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        vm.memory_mut().set_text_writable(true);
+        vm.execute(vm.entrypoint().unwrap()).unwrap();
+        assert_eq!(vm.registers.get(&Register::T2), 0);
+        assert_eq!(vm.exit_code(), Some(0));
+
+        // Reset restores the original instructions and keeps the text writable
+        vm.reset();
+        let patch = vm.memory().labels()["patch"];
+        assert_eq!(
+            vm.memory().execute(patch).unwrap().kind,
+            InstructionKind::Li
+        );
+        assert_eq!(vm.execute(vm.entrypoint().unwrap()), Ok(()));
+        assert_eq!(vm.registers.get(&Register::T2), 0);
+    }
+
+    #[test]
+    fn jr_unset_ra() {
+        let program = parse(".text\nmain:\n\tjr $ra\n").unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let err = vm.execute(vm.entrypoint().unwrap()).unwrap_err();
+        assert_eq!(
+            err,
+            VmError::BadJumpTarget {
+                register: Some(Register::Ra),
+                value: 0
+            }
+        );
+        assert!(err.to_string().contains("$ra = 0x00000000"));
+    }
+
+    #[test]
+    fn assertions() {
+        let program = parse(
+            ".text
+main:
+\tli $t0, 42 #assert $t0 == 0x2A
+\tli $t1, 0xFFFFFFFF
+\tli $v0, 10
+\tsyscall
+#assert $t1 == -1
+#assert $t0 == 41
+",
+        )
+        .unwrap();
+        let mut vm = VM::new(program, Vec::new());
+        let result = vm.run_captured(vm.entrypoint().unwrap(), "").unwrap();
+        let outcomes = result
+            .assertions
+            .iter()
+            .map(|assertion| assertion.show())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            vec![
+                "PASS line 3: $t0 == 0x2A",
+                "PASS line 7: $t1 == 0xFFFFFFFF",
+                "FAIL line 8: $t0 == 0x29 (was 0x2A)",
+            ]
+        );
+        assert!(!result.assertions[2].passed());
     }
 }